@@ -1,8 +1,76 @@
 use anyhow::{Context, Result};
+use chrono::{Local, SecondsFormat};
 use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::fmt;
 use std::fs;
+use std::io::{BufRead, Write};
 use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Contents of an approval file: which book.toml was approved, when, and by
+/// whom.
+///
+/// Approval files written before this metadata existed hold only the path;
+/// they are read with the other fields empty.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalRecord {
+    /// Canonical path of the approved book.toml
+    pub path: String,
+    /// When the approval was given (RFC 3339, local time)
+    #[serde(default)]
+    pub approved_at: Option<String>,
+    /// User who gave the approval
+    #[serde(default)]
+    pub approved_by: Option<String>,
+    /// Whether the approval is by content only (see [`approve`])
+    #[serde(default)]
+    pub portable: bool,
+}
+
+impl ApprovalRecord {
+    /// Parses an approval file, accepting the old plain-path format.
+    fn parse(content: &str) -> Self {
+        serde_json::from_str(content).unwrap_or_else(|_| Self {
+            path: content.trim().to_string(),
+            approved_at: None,
+            approved_by: None,
+            portable: false,
+        })
+    }
+}
+
+impl fmt::Display for ApprovalRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path)?;
+        if self.portable {
+            write!(f, " (portable)")?;
+        }
+        if let Some(ref approved_at) = self.approved_at {
+            write!(f, ", approved {}", approved_at)?;
+        }
+        if let Some(ref approved_by) = self.approved_by {
+            write!(f, " by {}", approved_by)?;
+        }
+        Ok(())
+    }
+}
+
+/// Name of the current user, from `$USER`, `$USERNAME`, or `whoami`
+fn current_user() -> Option<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+        .or_else(|| {
+            let output = Command::new("whoami").output().ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        })
+        .filter(|user| !user.is_empty())
+}
 
 /// Compute SHA256 hash of path + "\n" + content (direnv style)
 pub fn compute_hash(path: &Path, content: &str) -> String {
@@ -13,74 +81,228 @@ pub fn compute_hash(path: &Path, content: &str) -> String {
     format!("{:x}", hasher.finalize())
 }
 
-/// Get the approval directory path
-fn get_approval_dir() -> Result<PathBuf> {
+/// Compute SHA256 hash of content alone, for portable approvals that stay
+/// valid wherever the book.toml is moved or cloned
+pub fn compute_content_hash(content: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(content.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Get the directory holding this tool's persistent data
+pub fn get_data_dir() -> Result<PathBuf> {
     // Check for XDG_DATA_HOME environment variable first (respects XDG standard on all platforms)
     if let Ok(xdg_data_home) = std::env::var("XDG_DATA_HOME") {
-        return Ok(PathBuf::from(xdg_data_home)
-            .join("mdbook-check-code")
-            .join("allow"));
+        return Ok(PathBuf::from(xdg_data_home).join("mdbook-check-code"));
     }
 
     // Fall back to platform-specific defaults via directories crate
     let proj_dirs = ProjectDirs::from("", "", "mdbook-check-code")
         .context("Failed to determine project directories")?;
-    Ok(proj_dirs.data_dir().join("allow"))
+    Ok(proj_dirs.data_dir().to_path_buf())
+}
+
+/// Get the approval directory path
+fn get_approval_dir() -> Result<PathBuf> {
+    Ok(get_data_dir()?.join("allow"))
 }
 
-/// Check if a book.toml is approved
+/// Check if a book.toml is approved, either at its current path or portably
+/// by content
 pub fn is_approved(book_toml_path: &Path) -> Result<bool> {
+    Ok(approval_record(book_toml_path)?.is_some())
+}
+
+/// Get the approval covering a book.toml, preferring a path-bound approval
+/// over a portable one
+#[allow(dead_code)] // Used by CLI binary
+pub fn approval_record(book_toml_path: &Path) -> Result<Option<ApprovalRecord>> {
+    approval_record_in(&get_approval_dir()?, book_toml_path)
+}
+
+fn approval_record_in(
+    approval_dir: &Path,
+    book_toml_path: &Path,
+) -> Result<Option<ApprovalRecord>> {
     let content = fs::read_to_string(book_toml_path)
         .with_context(|| format!("Failed to read {}", book_toml_path.display()))?;
-    let hash = compute_hash(book_toml_path, &content);
-    let approval_dir = get_approval_dir()?;
-    let approval_file = approval_dir.join(&hash);
-    Ok(approval_file.exists())
+    let hashes = [
+        compute_hash(book_toml_path, &content),
+        compute_content_hash(&content),
+    ];
+    for hash in hashes {
+        let approval_file = approval_dir.join(hash);
+        if approval_file.exists() {
+            let record = fs::read_to_string(&approval_file).with_context(|| {
+                format!("Failed to read approval file: {}", approval_file.display())
+            })?;
+            return Ok(Some(ApprovalRecord::parse(&record)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+fn is_approved_in(approval_dir: &Path, book_toml_path: &Path) -> Result<bool> {
+    Ok(approval_record_in(approval_dir, book_toml_path)?.is_some())
 }
 
 /// Approve a book.toml
+///
+/// A portable approval is keyed by content only, so it also covers the same
+/// book.toml after the book is moved or cloned elsewhere.
 #[allow(dead_code)] // Used by CLI binary
-pub fn approve(book_toml_path: &Path) -> Result<()> {
+pub fn approve(book_toml_path: &Path, portable: bool) -> Result<()> {
+    approve_in(&get_approval_dir()?, book_toml_path, portable)
+}
+
+fn approve_in(approval_dir: &Path, book_toml_path: &Path, portable: bool) -> Result<()> {
     let content = fs::read_to_string(book_toml_path)
         .with_context(|| format!("Failed to read {}", book_toml_path.display()))?;
-    let hash = compute_hash(book_toml_path, &content);
-    let approval_dir = get_approval_dir()?;
+    let hash = if portable {
+        compute_content_hash(&content)
+    } else {
+        compute_hash(book_toml_path, &content)
+    };
 
     // Create approval directory if it doesn't exist
-    fs::create_dir_all(&approval_dir).with_context(|| {
+    fs::create_dir_all(approval_dir).with_context(|| {
         format!(
             "Failed to create approval directory: {}",
             approval_dir.display()
         )
     })?;
 
-    // Write approval file with the path
+    // Write approval file with the path, time, and user
     let approval_file = approval_dir.join(&hash);
     let canonical_path = book_toml_path
         .canonicalize()
         .unwrap_or_else(|_| book_toml_path.to_path_buf());
-    fs::write(&approval_file, canonical_path.display().to_string())
+    let record = ApprovalRecord {
+        path: canonical_path.display().to_string(),
+        approved_at: Some(Local::now().to_rfc3339_opts(SecondsFormat::Secs, false)),
+        approved_by: current_user(),
+        portable,
+    };
+    fs::write(&approval_file, serde_json::to_string_pretty(&record)?)
         .with_context(|| format!("Failed to write approval file: {}", approval_file.display()))?;
 
     Ok(())
 }
 
-/// Deny (remove approval) for a book.toml
+/// Directories never searched for book.toml files: build output and
+/// vendored dependencies
+const SKIPPED_DIRS: &[&str] = &["target", "node_modules"];
+
+/// Find every book.toml under a directory, for recursive approval
+///
+/// Hidden directories and build or dependency directories ([`SKIPPED_DIRS`])
+/// are skipped, symbolic links are not followed, and the result is sorted.
+#[allow(dead_code)] // Used by CLI binary
+pub fn find_book_tomls(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory: {}", dir.display()))?
+        .collect::<std::io::Result<_>>()
+        .with_context(|| format!("Failed to read directory: {}", dir.display()))?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let mut book_tomls = Vec::new();
+    for entry in entries {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let file_type = entry.file_type()?;
+        if file_type.is_file() && name == "book.toml" {
+            book_tomls.push(entry.path());
+        } else if file_type.is_dir()
+            && !name.starts_with('.')
+            && !SKIPPED_DIRS.contains(&name.as_ref())
+        {
+            book_tomls.extend(find_book_tomls(&entry.path())?);
+        }
+    }
+    Ok(book_tomls)
+}
+
+/// Approve a book.toml after showing what it configures
+///
+/// `summary` (the resolved languages the book would run) is written to
+/// `output`, followed by a `y/N` prompt answered from `input`, unless `yes` is
+/// set. Returns whether the book was approved.
+#[allow(dead_code)] // Used by CLI binary
+pub fn approve_confirmed(
+    book_toml_path: &Path,
+    portable: bool,
+    summary: &str,
+    yes: bool,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<bool> {
+    approve_confirmed_in(
+        &get_approval_dir()?,
+        book_toml_path,
+        portable,
+        summary,
+        yes,
+        input,
+        output,
+    )
+}
+
+fn approve_confirmed_in(
+    approval_dir: &Path,
+    book_toml_path: &Path,
+    portable: bool,
+    summary: &str,
+    yes: bool,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<bool> {
+    writeln!(
+        output,
+        "{} runs the following compilers:",
+        book_toml_path.display()
+    )?;
+    write!(output, "{}", summary)?;
+    if !yes {
+        write!(output, "Approve? [y/N] ")?;
+        output.flush()?;
+        let mut answer = String::new();
+        input
+            .read_line(&mut answer)
+            .context("Failed to read confirmation")?;
+        if !matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes") {
+            return Ok(false);
+        }
+    }
+    approve_in(approval_dir, book_toml_path, portable)?;
+    Ok(true)
+}
+
+/// Deny (remove approval) for a book.toml, both at its current path and
+/// portably by content
 #[allow(dead_code)] // Used by CLI binary
 pub fn deny(book_toml_path: &Path) -> Result<()> {
+    deny_in(&get_approval_dir()?, book_toml_path)
+}
+
+fn deny_in(approval_dir: &Path, book_toml_path: &Path) -> Result<()> {
     let content = fs::read_to_string(book_toml_path)
         .with_context(|| format!("Failed to read {}", book_toml_path.display()))?;
-    let hash = compute_hash(book_toml_path, &content);
-    let approval_dir = get_approval_dir()?;
-    let approval_file = approval_dir.join(&hash);
+    let hashes = [
+        compute_hash(book_toml_path, &content),
+        compute_content_hash(&content),
+    ];
 
-    if approval_file.exists() {
-        fs::remove_file(&approval_file).with_context(|| {
-            format!(
-                "Failed to remove approval file: {}",
-                approval_file.display()
-            )
-        })?;
+    for hash in hashes {
+        let approval_file = approval_dir.join(&hash);
+        if approval_file.exists() {
+            fs::remove_file(&approval_file).with_context(|| {
+                format!(
+                    "Failed to remove approval file: {}",
+                    approval_file.display()
+                )
+            })?;
+        }
     }
 
     Ok(())
@@ -88,15 +310,17 @@ pub fn deny(book_toml_path: &Path) -> Result<()> {
 
 /// List all approved books
 #[allow(dead_code)] // Used by CLI binary
-pub fn list_approved() -> Result<Vec<String>> {
-    let approval_dir = get_approval_dir()?;
+pub fn list_approved() -> Result<Vec<ApprovalRecord>> {
+    list_approved_in(&get_approval_dir()?)
+}
 
+fn list_approved_in(approval_dir: &Path) -> Result<Vec<ApprovalRecord>> {
     if !approval_dir.exists() {
         return Ok(vec![]);
     }
 
     let mut approved = Vec::new();
-    for entry in fs::read_dir(&approval_dir).with_context(|| {
+    for entry in fs::read_dir(approval_dir).with_context(|| {
         format!(
             "Failed to read approval directory: {}",
             approval_dir.display()
@@ -104,11 +328,303 @@ pub fn list_approved() -> Result<Vec<String>> {
     })? {
         let entry = entry?;
         if entry.path().is_file() {
-            if let Ok(path_content) = fs::read_to_string(entry.path()) {
-                approved.push(path_content);
+            if let Ok(record) = fs::read_to_string(entry.path()) {
+                approved.push(ApprovalRecord::parse(&record));
             }
         }
     }
 
     Ok(approved)
 }
+
+/// Remove approvals whose book.toml no longer exists or no longer matches
+/// the approved content, returning the removed approvals
+#[allow(dead_code)] // Used by CLI binary
+pub fn prune() -> Result<Vec<ApprovalRecord>> {
+    prune_in(&get_approval_dir()?)
+}
+
+fn prune_in(approval_dir: &Path) -> Result<Vec<ApprovalRecord>> {
+    if !approval_dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut removed = Vec::new();
+    for entry in fs::read_dir(approval_dir).with_context(|| {
+        format!(
+            "Failed to read approval directory: {}",
+            approval_dir.display()
+        )
+    })? {
+        let entry = entry?;
+        let approval_file = entry.path();
+        if !approval_file.is_file() {
+            continue;
+        }
+        let Ok(record) = fs::read_to_string(&approval_file) else {
+            continue;
+        };
+        let record = ApprovalRecord::parse(&record);
+
+        let book_toml = Path::new(&record.path);
+        let current_hash = fs::read_to_string(book_toml).ok().map(|content| {
+            if record.portable {
+                compute_content_hash(&content)
+            } else {
+                compute_hash(book_toml, &content)
+            }
+        });
+        if current_hash.as_deref() != Some(entry.file_name().to_string_lossy().as_ref()) {
+            fs::remove_file(&approval_file).with_context(|| {
+                format!(
+                    "Failed to remove approval file: {}",
+                    approval_file.display()
+                )
+            })?;
+            removed.push(record);
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    const BOOK_TOML: &str = "[preprocessor.check-code.languages.c]\ncompiler = \"gcc\"\n";
+
+    fn write_book(dir: &Path) -> PathBuf {
+        fs::create_dir_all(dir).unwrap();
+        let book_toml = dir.join("book.toml");
+        fs::write(&book_toml, BOOK_TOML).unwrap();
+        book_toml
+    }
+
+    #[test]
+    fn test_path_bound_approval_lost_on_rename() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let approval_dir = temp_dir.path().join("allow");
+        let book_toml = write_book(&temp_dir.path().join("book"));
+
+        approve_in(&approval_dir, &book_toml, false).unwrap();
+        assert!(is_approved_in(&approval_dir, &book_toml).unwrap());
+
+        let moved = temp_dir.path().join("moved");
+        fs::rename(temp_dir.path().join("book"), &moved).unwrap();
+        assert!(!is_approved_in(&approval_dir, &moved.join("book.toml")).unwrap());
+    }
+
+    #[test]
+    fn test_portable_approval_survives_rename() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let approval_dir = temp_dir.path().join("allow");
+        let book_toml = write_book(&temp_dir.path().join("book"));
+
+        approve_in(&approval_dir, &book_toml, true).unwrap();
+        assert!(is_approved_in(&approval_dir, &book_toml).unwrap());
+
+        let moved = temp_dir.path().join("moved");
+        fs::rename(temp_dir.path().join("book"), &moved).unwrap();
+        let moved_toml = moved.join("book.toml");
+        assert!(is_approved_in(&approval_dir, &moved_toml).unwrap());
+
+        // Changing the content still requires a new approval
+        fs::write(&moved_toml, format!("{}flags = [\"-O2\"]\n", BOOK_TOML)).unwrap();
+        assert!(!is_approved_in(&approval_dir, &moved_toml).unwrap());
+    }
+
+    #[test]
+    fn test_approval_records_time_and_user() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let approval_dir = temp_dir.path().join("allow");
+        let book_toml = write_book(&temp_dir.path().join("book"));
+
+        approve_in(&approval_dir, &book_toml, false).unwrap();
+        let record = approval_record_in(&approval_dir, &book_toml)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            record.path,
+            book_toml.canonicalize().unwrap().display().to_string()
+        );
+        assert!(!record.portable);
+
+        // What `status` prints after "Approved: "
+        let timestamp = Regex::new(r"approved \d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}").unwrap();
+        assert!(timestamp.is_match(&record.to_string()), "{}", record);
+
+        assert_eq!(list_approved_in(&approval_dir).unwrap(), vec![record]);
+    }
+
+    #[test]
+    fn test_plain_path_approval_file_still_read() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let approval_dir = temp_dir.path().join("allow");
+        let book_toml = write_book(&temp_dir.path().join("book"));
+        let canonical = book_toml.canonicalize().unwrap().display().to_string();
+
+        fs::create_dir_all(&approval_dir).unwrap();
+        let hash = compute_hash(&book_toml, BOOK_TOML);
+        fs::write(approval_dir.join(hash), &canonical).unwrap();
+
+        let record = approval_record_in(&approval_dir, &book_toml)
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.path, canonical);
+        assert_eq!(record.approved_at, None);
+        assert_eq!(record.to_string(), canonical);
+    }
+
+    #[test]
+    fn test_prune_removes_deleted_book() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let approval_dir = temp_dir.path().join("allow");
+        let kept = write_book(&temp_dir.path().join("kept"));
+        let deleted = write_book(&temp_dir.path().join("deleted"));
+
+        approve_in(&approval_dir, &kept, false).unwrap();
+        approve_in(&approval_dir, &deleted, false).unwrap();
+        let deleted_path = deleted.canonicalize().unwrap().display().to_string();
+        fs::remove_dir_all(temp_dir.path().join("deleted")).unwrap();
+
+        let removed = prune_in(&approval_dir).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].path, deleted_path);
+        assert!(is_approved_in(&approval_dir, &kept).unwrap());
+        assert_eq!(list_approved_in(&approval_dir).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_prune_removes_edited_book() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let approval_dir = temp_dir.path().join("allow");
+        let path_bound = write_book(&temp_dir.path().join("path_bound"));
+        let portable = write_book(&temp_dir.path().join("portable"));
+
+        approve_in(&approval_dir, &path_bound, false).unwrap();
+        approve_in(&approval_dir, &portable, true).unwrap();
+        assert!(prune_in(&approval_dir).unwrap().is_empty());
+
+        for book_toml in [&path_bound, &portable] {
+            fs::write(book_toml, format!("{}flags = [\"-O2\"]\n", BOOK_TOML)).unwrap();
+        }
+        let removed = prune_in(&approval_dir).unwrap();
+        assert_eq!(removed.len(), 2);
+        assert!(list_approved_in(&approval_dir).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_recursive_approval_of_nested_books() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let approval_dir = temp_dir.path().join("allow");
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/nested_books");
+
+        let book_tomls = find_book_tomls(&root).unwrap();
+        assert_eq!(
+            book_tomls,
+            [
+                root.join("guide/book.toml"),
+                root.join("specs/api/book.toml")
+            ]
+        );
+
+        for book_toml in &book_tomls {
+            approve_in(&approval_dir, book_toml, false).unwrap();
+        }
+        for book_toml in &book_tomls {
+            assert!(is_approved_in(&approval_dir, book_toml).unwrap());
+        }
+        let skipped = root.join("specs/api/node_modules/dep/book.toml");
+        assert!(!is_approved_in(&approval_dir, &skipped).unwrap());
+    }
+
+    #[test]
+    fn test_find_book_tomls_skips_build_and_hidden_dirs() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        for dir in ["b", "a", "target/debug", ".git"] {
+            write_book(&temp_dir.path().join(dir));
+        }
+
+        assert_eq!(
+            find_book_tomls(temp_dir.path()).unwrap(),
+            [
+                temp_dir.path().join("a/book.toml"),
+                temp_dir.path().join("b/book.toml")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_declined_confirmation_leaves_book_unapproved() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let approval_dir = temp_dir.path().join("allow");
+        let book_toml = write_book(&temp_dir.path().join("book"));
+        let summary = "c\n  compiler: gcc\n";
+
+        let mut output = Vec::new();
+        let approved = approve_confirmed_in(
+            &approval_dir,
+            &book_toml,
+            false,
+            summary,
+            false,
+            &mut "n\n".as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        assert!(!approved);
+        assert!(!is_approved_in(&approval_dir, &book_toml).unwrap());
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("compiler: gcc"));
+        assert!(output.ends_with("Approve? [y/N] "));
+
+        let approved = approve_confirmed_in(
+            &approval_dir,
+            &book_toml,
+            false,
+            summary,
+            false,
+            &mut "y\n".as_bytes(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert!(approved);
+        assert!(is_approved_in(&approval_dir, &book_toml).unwrap());
+    }
+
+    #[test]
+    fn test_yes_skips_confirmation() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let approval_dir = temp_dir.path().join("allow");
+        let book_toml = write_book(&temp_dir.path().join("book"));
+
+        let mut output = Vec::new();
+        let approved = approve_confirmed_in(
+            &approval_dir,
+            &book_toml,
+            false,
+            "c\n",
+            true,
+            &mut "".as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        assert!(approved);
+        assert!(!String::from_utf8(output).unwrap().contains("[y/N]"));
+        assert!(is_approved_in(&approval_dir, &book_toml).unwrap());
+    }
+
+    #[test]
+    fn test_deny_removes_portable_approval() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let approval_dir = temp_dir.path().join("allow");
+        let book_toml = write_book(&temp_dir.path().join("book"));
+
+        approve_in(&approval_dir, &book_toml, false).unwrap();
+        approve_in(&approval_dir, &book_toml, true).unwrap();
+        deny_in(&approval_dir, &book_toml).unwrap();
+        assert!(!is_approved_in(&approval_dir, &book_toml).unwrap());
+    }
+}
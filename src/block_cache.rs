@@ -0,0 +1,145 @@
+use crate::approval::get_data_dir;
+use crate::cache::{content_key, hash, is_cacheable};
+use crate::compilation::{CompilationResult, CompilationTask};
+use crate::extractor::CodeBlock;
+use crate::language::ConfiguredLanguage;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Persistent record of code blocks that already compiled successfully.
+///
+/// A block is identified by its content rather than its position, so inserting
+/// or editing other blocks in the chapter does not invalidate it. The key
+/// covers the book root and chapter path in addition to the inputs hashed by
+/// [`content_key`]; changing any of them makes the block compile again. Each
+/// entry is an empty marker file named by the key's SHA256 hash.
+pub struct BlockCache {
+    dir: PathBuf,
+    book_root: PathBuf,
+}
+
+impl BlockCache {
+    /// Opens the cache for the book at `book_root`, stored in the `blocks`
+    /// directory next to book approvals.
+    pub fn open(book_root: &Path) -> Result<Self> {
+        Ok(Self::with_dir(get_data_dir()?.join("blocks"), book_root))
+    }
+
+    fn with_dir(dir: PathBuf, book_root: &Path) -> Self {
+        let book_root = book_root
+            .canonicalize()
+            .unwrap_or_else(|_| book_root.to_path_buf());
+        Self { dir, book_root }
+    }
+
+    /// Removes tasks for blocks that compiled successfully with identical
+    /// inputs before, returning the remaining tasks and the number skipped.
+    ///
+    /// Only code blocks are skipped; propagated-context and standalone checks
+    /// always run.
+    pub fn retain_changed(&self, tasks: Vec<CompilationTask>) -> (Vec<CompilationTask>, usize) {
+        let total = tasks.len();
+        let tasks: Vec<_> = tasks
+            .into_iter()
+            .filter(|task| {
+                !is_cacheable(task.kind())
+                    || !self
+                        .entry(
+                            task.language(),
+                            task.chapter_path(),
+                            &task.block_source(),
+                            task.block(),
+                        )
+                        .exists()
+            })
+            .collect();
+        let skipped = total - tasks.len();
+        (tasks, skipped)
+    }
+
+    /// Records every successful code block in `results`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory or an entry cannot be written.
+    pub fn record(&self, results: &[CompilationResult]) -> Result<()> {
+        fs::create_dir_all(&self.dir).with_context(|| {
+            format!(
+                "Failed to create block cache directory: {}",
+                self.dir.display()
+            )
+        })?;
+
+        for result in results
+            .iter()
+            .filter(|r| r.success() && is_cacheable(r.kind()))
+        {
+            let entry = self.entry(
+                result.language(),
+                result.chapter_path(),
+                result.code(),
+                result.block(),
+            );
+            fs::write(&entry, "")
+                .with_context(|| format!("Failed to write cache entry: {}", entry.display()))?;
+        }
+
+        Ok(())
+    }
+
+    fn entry(
+        &self,
+        language: &ConfiguredLanguage,
+        chapter_path: &Path,
+        code: &str,
+        block: &CodeBlock,
+    ) -> PathBuf {
+        let input = format!(
+            "{}\n{}\n{}",
+            self.book_root.display(),
+            chapter_path.display(),
+            content_key(language, code, block)
+        );
+        self.dir.join(hash(&input))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compilation::TaskKind;
+    use crate::config::LanguageConfig;
+
+    fn task(code: &str, flags: &[&str]) -> CompilationTask {
+        let mut config: LanguageConfig = toml::from_str(r#"compiler = "true""#).unwrap();
+        config.flags = flags.iter().map(|f| f.to_string()).collect();
+        CompilationTask::new(
+            ConfiguredLanguage::new("c".to_string(), None, config),
+            PathBuf::from("/tmp/block_cache_test"),
+            PathBuf::from("chapter.md"),
+            0,
+            CodeBlock::default(),
+            code.to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_skips_only_unchanged_successful_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BlockCache::with_dir(dir.path().join("blocks"), Path::new("/book"));
+
+        let result = task("int x;", &[]).compile().await;
+        assert!(result.success());
+        cache.record(&[result]).unwrap();
+
+        let (remaining, skipped) = cache.retain_changed(vec![
+            task("int x;", &[]),
+            task("int y;", &[]),
+            task("int x;", &["-Wall"]),
+            task("int x;", &[]).with_kind(TaskKind::PropagatedContext),
+        ]);
+        assert_eq!(skipped, 1);
+        assert_eq!(remaining.len(), 3);
+    }
+}
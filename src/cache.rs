@@ -0,0 +1,152 @@
+use crate::approval::get_data_dir;
+use crate::compilation::{CompilationResult, CompilationTask, TaskKind};
+use crate::extractor::CodeBlock;
+use crate::language::ConfiguredLanguage;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+
+/// Names of the cache directories under the data directory, removed by [`clean`].
+const CACHE_DIRS: [&str; 3] = ["cache", "blocks", "chapters"];
+
+/// On-disk cache of successful compilations, shared by all books.
+///
+/// Entries are keyed by [`content_key`]: the block's code with any propagated
+/// code, its expected outcome, and every language setting that affects the
+/// check (compiler, flags, preamble, ...). Changing any of these produces a
+/// different key, so stale entries are never hit. Each entry is an empty
+/// marker file named by the key.
+pub struct CompileCache {
+    dir: PathBuf,
+}
+
+impl CompileCache {
+    /// Opens the cache in the `cache` directory next to book approvals.
+    pub fn open() -> Result<Self> {
+        Ok(Self::with_dir(get_data_dir()?.join("cache")))
+    }
+
+    fn with_dir(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Returns whether `task` compiled successfully before with identical inputs.
+    ///
+    /// Only code blocks are cached; propagated-context and standalone checks
+    /// always compile.
+    pub fn contains(&self, task: &CompilationTask) -> bool {
+        is_cacheable(task.kind())
+            && self
+                .dir
+                .join(content_key(
+                    task.language(),
+                    &task.block_source(),
+                    task.block(),
+                ))
+                .exists()
+    }
+
+    /// Records `result` if it is a successful code block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory or entry cannot be written.
+    pub fn record(&self, result: &CompilationResult) -> Result<()> {
+        if !result.success() || !is_cacheable(result.kind()) {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create cache directory: {}", self.dir.display()))?;
+        let entry = self.dir.join(content_key(
+            result.language(),
+            result.code(),
+            result.block(),
+        ));
+        fs::write(&entry, "")
+            .with_context(|| format!("Failed to write cache entry: {}", entry.display()))
+    }
+}
+
+/// Removes all cached compilation results, including the per-book block cache.
+///
+/// Returns the directories that were removed.
+#[allow(dead_code)] // Used by CLI binary
+pub fn clean() -> Result<Vec<PathBuf>> {
+    let data_dir = get_data_dir()?;
+    let mut removed = Vec::new();
+    for name in CACHE_DIRS {
+        let dir = data_dir.join(name);
+        if dir.exists() {
+            fs::remove_dir_all(&dir)
+                .with_context(|| format!("Failed to remove cache directory: {}", dir.display()))?;
+            removed.push(dir);
+        }
+    }
+    Ok(removed)
+}
+
+/// Computes the SHA256 key identifying a block's compilation inputs.
+///
+/// `code` is the block's code combined with its propagated code (see
+/// [`ConfiguredLanguage::block_source`]); the preamble and layout are covered
+/// by the language fingerprint.
+pub fn content_key(language: &ConfiguredLanguage, code: &str, block: &CodeBlock) -> String {
+    let input = format!(
+        "{}\n{}\n{:?}\n{:?}\n{}\n{}",
+        language.fingerprint(),
+        block.should_fail,
+        block.expect_error,
+        block.expect_output,
+        block.no_run,
+        code
+    );
+    hash(&input)
+}
+
+/// Returns the hex-encoded SHA256 hash of `input`.
+pub fn hash(input: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(input.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Returns whether results of this kind of task are cached.
+pub fn is_cacheable(kind: TaskKind) -> bool {
+    matches!(kind, TaskKind::Block | TaskKind::DependentBlock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LanguageConfig;
+
+    fn task(code: &str, flags: &[&str]) -> CompilationTask {
+        let mut config: LanguageConfig = toml::from_str(r#"compiler = "true""#).unwrap();
+        config.flags = flags.iter().map(|f| f.to_string()).collect();
+        CompilationTask::new(
+            ConfiguredLanguage::new("c".to_string(), None, config),
+            PathBuf::from("/tmp/compile_cache_test"),
+            PathBuf::from("chapter.md"),
+            0,
+            CodeBlock::default(),
+            code.to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_cache_hits_only_identical_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = CompileCache::with_dir(dir.path().join("cache"));
+
+        let result = task("int x;", &[]).compile().await;
+        cache.record(&result).unwrap();
+
+        assert!(cache.contains(&task("int x;", &[])));
+        assert!(!cache.contains(&task("int y;", &[])));
+        assert!(!cache.contains(&task("int x;", &["-Wall"])));
+        assert!(!cache.contains(&task("int x;", &[]).with_propagated("int z;\n".to_string())));
+        assert!(!cache.contains(&task("int x;", &[]).with_kind(TaskKind::PropagatedContext)));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::approval::get_data_dir;
+use crate::cache::hash;
+use crate::config::CheckCodeConfig;
+use crate::language::compiler_identity;
+use crate::task_collector::BlockTally;
+use anyhow::{Context, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Persistent record of chapters whose blocks all compiled successfully.
+///
+/// A chapter is identified by its source, the book root, its path, and a hash
+/// of the whole `[preprocessor.check-code]` configuration and the configured
+/// compiler binaries, so editing the chapter, any setting, or upgrading a
+/// compiler makes it compile again. Files the blocks include are not tracked,
+/// which is why the cache is not used with `compile_in_place`. Unchanged
+/// chapters are not extracted or compiled at all. Each entry is named by the
+/// key's SHA256 hash and holds the chapter's [`BlockTally`] as JSON, so
+/// `expected_blocks` and `check_unused_variants` still count its blocks.
+///
+/// Chapters checked in a run are only recorded by [`ChapterCache::record`],
+/// which the preprocessor calls once the whole build succeeded.
+pub struct ChapterCache {
+    dir: PathBuf,
+    book_root: PathBuf,
+    config_hash: String,
+    /// Keys of chapters checked in this run, by chapter path
+    pending: Mutex<HashMap<PathBuf, PathBuf>>,
+    skipped: Mutex<usize>,
+    /// Blocks of the skipped chapters
+    skipped_tally: Mutex<BlockTally>,
+}
+
+impl ChapterCache {
+    /// Opens the cache for the book at `book_root` built with `config`,
+    /// stored in the `chapters` directory next to book approvals.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data directory cannot be determined.
+    pub fn open(book_root: &Path, config: &CheckCodeConfig) -> Result<Self> {
+        Ok(Self::with_dir(
+            get_data_dir()?.join("chapters"),
+            book_root,
+            config,
+        ))
+    }
+
+    fn with_dir(dir: PathBuf, book_root: &Path, config: &CheckCodeConfig) -> Self {
+        let book_root = book_root
+            .canonicalize()
+            .unwrap_or_else(|_| book_root.to_path_buf());
+        let compilers: BTreeSet<String> = config
+            .languages
+            .values()
+            .filter(|language| language.enabled)
+            .flat_map(|language| {
+                std::iter::once(&language.compiler)
+                    .chain(language.variants.values().map(|variant| &variant.compiler))
+            })
+            .map(|compiler| compiler_identity(compiler))
+            .collect();
+        // Serialized through a JSON value, whose maps are sorted, so the hash
+        // does not depend on HashMap iteration order
+        let config_hash = serde_json::to_value(config)
+            .map(|value| hash(&format!("{}\n{:?}", value, compilers)))
+            .unwrap_or_default();
+        Self {
+            dir,
+            book_root,
+            config_hash,
+            pending: Mutex::new(HashMap::new()),
+            skipped: Mutex::new(0),
+            skipped_tally: Mutex::new(BlockTally::default()),
+        }
+    }
+
+    /// Returns whether the chapter at `chapter_path` with source `content`
+    /// was recorded by an earlier successful run, adding its blocks to
+    /// [`ChapterCache::skipped_tally`]. Otherwise the chapter is remembered
+    /// for [`ChapterCache::record`].
+    ///
+    /// Entries that cannot be read back, such as markers written by older
+    /// versions, count as changed.
+    pub fn unchanged(&self, chapter_path: &Path, content: &str) -> bool {
+        let entry = self.entry(chapter_path, content);
+        let recorded = fs::read_to_string(&entry)
+            .ok()
+            .and_then(|json| serde_json::from_str::<BlockTally>(&json).ok());
+        if let Some(tally) = recorded {
+            *self.skipped.lock().unwrap() += 1;
+            self.skipped_tally.lock().unwrap().merge(&tally);
+            return true;
+        }
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(chapter_path.to_path_buf(), entry);
+        false
+    }
+
+    /// Number of chapters [`ChapterCache::unchanged`] found in the cache.
+    pub fn skipped(&self) -> usize {
+        *self.skipped.lock().unwrap()
+    }
+
+    /// Blocks of the chapters [`ChapterCache::unchanged`] found in the cache.
+    pub fn skipped_tally(&self) -> BlockTally {
+        self.skipped_tally.lock().unwrap().clone()
+    }
+
+    /// Records every chapter checked in this run with its tally from
+    /// `tallies`, keyed by chapter path (chapters without blocks may be
+    /// missing). Call only after all of their blocks compiled successfully.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory or an entry cannot be written.
+    pub fn record(&self, tallies: &HashMap<PathBuf, BlockTally>) -> Result<()> {
+        fs::create_dir_all(&self.dir).with_context(|| {
+            format!(
+                "Failed to create chapter cache directory: {}",
+                self.dir.display()
+            )
+        })?;
+
+        for (chapter_path, entry) in self.pending.lock().unwrap().drain() {
+            let tally = tallies.get(&chapter_path).cloned().unwrap_or_default();
+            fs::write(&entry, serde_json::to_string(&tally)?)
+                .with_context(|| format!("Failed to write cache entry: {}", entry.display()))?;
+        }
+
+        Ok(())
+    }
+
+    fn entry(&self, chapter_path: &Path, content: &str) -> PathBuf {
+        let input = format!(
+            "{}\n{}\n{}\n{}",
+            self.book_root.display(),
+            chapter_path.display(),
+            self.config_hash,
+            content
+        );
+        self.dir.join(hash(&input))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::LanguageRegistry;
+    use crate::task_collector::collect_compilation_tasks;
+    use mdbook::book::{Book, BookItem, Chapter};
+    use tempfile::TempDir;
+
+    fn book(content: &str) -> Book {
+        let mut book = Book::new();
+        book.push_item(BookItem::Chapter(Chapter::new(
+            "Chapter",
+            content.to_string(),
+            "chapter.md",
+            Vec::new(),
+        )));
+        book
+    }
+
+    #[test]
+    fn test_second_run_skips_unchanged_chapter() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "true"
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+        let content = "```c,propagate\nint x;\n```\n";
+        let collect = |content: &str, config: &CheckCodeConfig| {
+            let cache =
+                ChapterCache::with_dir(dir.path().join("chapters"), Path::new("/book"), config);
+            let mut book = book(content);
+            let tasks = collect_compilation_tasks(
+                &mut book,
+                Path::new(""),
+                config,
+                &registry,
+                &temp_dir,
+                Some(&cache),
+            )
+            .unwrap();
+            cache.record(&BlockTally::by_chapter(&tasks)).unwrap();
+            let BookItem::Chapter(ref chapter) = book.sections[0] else {
+                unreachable!()
+            };
+            (tasks.len(), cache.skipped(), chapter.content.clone())
+        };
+
+        assert_eq!(
+            collect(content, &config),
+            (1, 0, "```c\nint x;\n```\n".to_string())
+        );
+        // Skipped chapters still have their fences rewritten
+        assert_eq!(
+            collect(content, &config),
+            (0, 1, "```c\nint x;\n```\n".to_string())
+        );
+
+        assert_eq!(collect("```c\nint y;\n```\n", &config).0, 1);
+        let changed_config = CheckCodeConfig {
+            parallel_jobs: Some(2),
+            ..config.clone()
+        };
+        assert_eq!(collect(content, &changed_config).0, 1);
+    }
+
+    #[test]
+    fn test_compiler_change_invalidates_chapters() {
+        let dir = tempfile::tempdir().unwrap();
+        let compiler = dir.path().join("cc");
+        fs::write(&compiler, "#!/bin/sh\n").unwrap();
+        let config: CheckCodeConfig = toml::from_str(&format!(
+            "[languages.c]\ncompiler = {:?}",
+            compiler.display().to_string()
+        ))
+        .unwrap();
+        let unchanged = || {
+            let cache = ChapterCache::with_dir(dir.path().join("chapters"), dir.path(), &config);
+            let unchanged = cache.unchanged(Path::new("chapter.md"), "text");
+            cache.record(&HashMap::new()).unwrap();
+            unchanged
+        };
+
+        assert!(!unchanged());
+        assert!(unchanged());
+        fs::write(&compiler, "#!/bin/sh\nexit 0\n").unwrap();
+        assert!(!unchanged());
+    }
+
+    #[test]
+    fn test_skipped_chapter_keeps_block_tally() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "true"
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+        let cache = || ChapterCache::with_dir(dir.path().join("chapters"), dir.path(), &config);
+        let content = "```c\nint x;\n```\n\n```c\nint y;\n```\n";
+        let collect = |cache: &ChapterCache| {
+            collect_compilation_tasks(
+                &mut book(content),
+                Path::new(""),
+                &config,
+                &registry,
+                &temp_dir,
+                Some(cache),
+            )
+            .unwrap()
+        };
+
+        let first = cache();
+        let tasks = collect(&first);
+        first.record(&BlockTally::by_chapter(&tasks)).unwrap();
+
+        let second = cache();
+        assert!(collect(&second).is_empty());
+        assert_eq!(second.skipped_tally(), BlockTally::of_tasks(&tasks));
+
+        // Entries without a tally are compiled again
+        fs::write(first.entry(Path::new("chapter.md"), content), "").unwrap();
+        assert_eq!(collect(&cache()).len(), 2);
+    }
+}
@@ -1,7 +1,69 @@
-use crate::language::ConfiguredLanguage;
+use crate::cache::CompileCache;
+use crate::diff::line_diff;
+use crate::extractor::CodeBlock;
+use crate::language::{artifact_path, CompilerNotFound, ConfiguredLanguage, SourceFile};
+use crate::reporting::Progress;
+use anyhow::Context;
 use futures::stream::{self, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+/// Directory under the book root that `keep_failed_sources` copies failing
+/// sources into.
+pub const FAILED_SOURCES_DIR: &str = "check-code-failures";
+
+/// What a compilation task validates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TaskKind {
+    /// A code block compiled without any propagated code
+    Block,
+    /// A code block compiled with the chapter's propagated code prepended
+    DependentBlock,
+    /// The chapter's accumulated propagated code compiled on its own
+    PropagatedContext,
+    /// A dependent block compiled without its propagated code, for analysis only
+    Standalone,
+}
+
+fn block_label(kind: TaskKind, block_index: usize) -> String {
+    match kind {
+        TaskKind::PropagatedContext => "propagated context".to_string(),
+        TaskKind::Block | TaskKind::DependentBlock => format!("#{}", block_index),
+        TaskKind::Standalone => format!("#{} (standalone)", block_index),
+    }
+}
+
+/// Removes the entries next to a path named like it, when dropped.
+struct Cleanup(PathBuf);
+
+impl Drop for Cleanup {
+    fn drop(&mut self) {
+        let (Some(dir), Some(stem)) = (self.0.parent(), self.0.file_name()) else {
+            return;
+        };
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.file_name() != Some(stem) && path.file_stem() != Some(stem) {
+                continue;
+            }
+            let removed = if path.is_dir() {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            };
+            if let Err(e) = removed {
+                log::warn!("Failed to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
 
 /// A compilation task representing a single code block to be compiled.
 ///
@@ -12,7 +74,12 @@ pub struct CompilationTask {
     temp_path: PathBuf,
     chapter_path: PathBuf,
     block_index: usize,
+    block: CodeBlock,
     code: String,
+    propagated: String,
+    kind: TaskKind,
+    files: Vec<SourceFile>,
+    cleanup: Option<PathBuf>,
 }
 
 impl CompilationTask {
@@ -21,6 +88,7 @@ impl CompilationTask {
         temp_path: PathBuf,
         chapter_path: PathBuf,
         block_index: usize,
+        block: CodeBlock,
         code: String,
     ) -> Self {
         Self {
@@ -28,43 +96,285 @@ impl CompilationTask {
             temp_path,
             chapter_path,
             block_index,
+            block,
             code,
+            propagated: String::new(),
+            kind: TaskKind::Block,
+            files: Vec::new(),
+            cleanup: None,
+        }
+    }
+
+    /// Removes the files written for this task once it is compiled, for
+    /// sources written outside the temporary directory (`compile_in_place`):
+    /// every entry next to `stem` named `stem` or `stem.<extension>`.
+    pub fn with_cleanup(mut self, stem: Option<PathBuf>) -> Self {
+        self.cleanup = stem;
+        self
+    }
+
+    /// Sets the code propagated into this block from earlier blocks, placed
+    /// according to the language's `source_layout`.
+    pub fn with_propagated(mut self, propagated: String) -> Self {
+        self.propagated = propagated;
+        self
+    }
+
+    /// Makes this task compile a multi-file unit: the files are written into
+    /// the task's temporary path, used as a directory, and compiled together
+    /// (see [`ConfiguredLanguage::compile_unit`]).
+    pub fn with_files(mut self, files: Vec<SourceFile>) -> Self {
+        self.files = files;
+        self
+    }
+
+    /// Sets what this task validates (defaults to [`TaskKind::Block`]).
+    pub fn with_kind(mut self, kind: TaskKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn language(&self) -> &ConfiguredLanguage {
+        &self.language
+    }
+
+    pub fn kind(&self) -> TaskKind {
+        self.kind
+    }
+
+    pub fn chapter_path(&self) -> &Path {
+        &self.chapter_path
+    }
+
+    pub fn block(&self) -> &CodeBlock {
+        &self.block
+    }
+
+    /// Source files passed to the compiler and the command line compiling
+    /// them, as [`CompilationTask::compile`] would run it.
+    pub fn compile_command(&self) -> (Vec<PathBuf>, Vec<String>) {
+        let sources: Vec<PathBuf> = if !self.files.is_empty() {
+            self.files
+                .iter()
+                .map(|file| self.temp_path.join(&file.name))
+                .collect()
+        } else {
+            vec![self.temp_path.clone()]
+        };
+        let paths: Vec<&Path> = sources.iter().map(PathBuf::as_path).collect();
+        let command = self
+            .language
+            .build_command(&paths, &artifact_path(&self.temp_path));
+        (sources, command)
+    }
+
+    /// Human-readable label for the validated block, see
+    /// [`CompilationResult::block_label`].
+    pub fn block_label(&self) -> String {
+        block_label(self.kind, self.block_index)
+    }
+
+    /// The block's code combined with its propagated code, as shown in
+    /// reports (see [`ConfiguredLanguage::block_source`]).
+    pub fn block_source(&self) -> String {
+        self.language.block_source(&self.propagated, &self.code)
+    }
+
+    /// Produces a successful result without compiling, for a task found in
+    /// the compilation cache.
+    pub fn cached(self) -> CompilationResult {
+        let code = self.block_source();
+        let block_start_line = self.language.block_start_line(&self.propagated, &self.code);
+        CompilationResult {
+            language: self.language,
+            duration: Duration::ZERO,
+            chapter_path: self.chapter_path,
+            block_index: self.block_index,
+            block: self.block,
+            code,
+            kind: self.kind,
+            error_message: None,
+            run_stdout: None,
+            run_stderr: None,
+            cached: true,
+            block_start_line,
+            source_path: self.temp_path,
+            kept_source: None,
+            missing_compiler: false,
         }
     }
 
     /// Executes compilation asynchronously and consumes the task to produce a result.
     ///
     /// This method performs the actual compilation, measures duration,
-    /// and converts any errors into the appropriate result format.
+    /// and converts any errors into the appropriate result format. For blocks
+    /// marked `should_fail` the outcome is inverted: a compile error is a
+    /// success and a clean compile is reported as a failure. With
+    /// `expect_error`, the compile error must also contain the expected text.
+    /// In run mode, the program built from a block is then executed and its
+    /// output captured.
     pub async fn compile(self) -> CompilationResult {
         log::debug!("Compiling {} block", self.language);
+        // Dropped when compiling ends, panics, or is cancelled
+        let _cleanup = self.cleanup.clone().map(Cleanup);
 
         let start = Instant::now();
-        let compile_result = self.language.compile(&self.code, &self.temp_path).await;
+        let origin = format!(
+            "{} block #{}",
+            self.chapter_path.display(),
+            self.block_index
+        );
+        let compile_result = if self.files.is_empty() {
+            self.language
+                .compile(&self.propagated, &self.code, &self.temp_path, &origin)
+                .await
+        } else {
+            self.language
+                .compile_unit(&self.files, &self.temp_path)
+                .await
+        };
+
+        // Blocks marked should_fail must be rejected by the compiler; a timeout
+        // is never an expected failure
+        let is_block = matches!(self.kind, TaskKind::Block | TaskKind::DependentBlock);
+        let expects_failure = self.block.should_fail && is_block;
+
+        // In run mode, blocks that compiled are executed (unless marked no_run)
+        // and must exit cleanly, printing the expected output if any
+        let mut run_stdout = None;
+        let mut run_stderr = None;
+        let compile_result = match compile_result {
+            Ok(Some(artifact)) if is_block && !expects_failure && !self.block.no_run => {
+                match self.language.run_artifact(&artifact, &self.temp_path).await {
+                    Ok(output) => {
+                        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+                        let outcome = if !output.status.success() {
+                            Err(anyhow::anyhow!("Program exited with {}", output.status))
+                        } else if let Some(ref expected) = self.block.expect_output {
+                            check_output(expected, &stdout)
+                        } else {
+                            Ok(())
+                        };
+                        run_stdout = Some(stdout);
+                        run_stderr = Some(String::from_utf8_lossy(&output.stderr).into_owned());
+                        outcome
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Ok(None) if is_block && !expects_failure && self.block.expect_output.is_some() => {
+                Err(anyhow::anyhow!(
+                    "expect_output requires a run_command for language '{}'",
+                    self.language
+                ))
+            }
+            other => other.map(|_| ()),
+        };
         let duration = start.elapsed();
 
+        // A missing compiler is never an expected failure either
+        let missing_compiler = compile_result
+            .as_ref()
+            .is_err_and(|e| e.downcast_ref::<CompilerNotFound>().is_some());
+        let error_message = match compile_result {
+            Err(e) if missing_compiler => Some(e.to_string()),
+            Err(e) if is_timeout(&e) => Some(format!(
+                "{} block #{} timed out after {} seconds",
+                self.chapter_path.display(),
+                self.block_index,
+                self.language.timeout_seconds().unwrap_or_default()
+            )),
+            Err(e) if expects_failure => match self.block.expect_error {
+                Some(ref expected) if !e.to_string().contains(expected.as_str()) => Some(format!(
+                    "Block failed with a different error than expected\nExpected error containing: {}\n\nActual error:\n{}",
+                    expected, e
+                )),
+                _ => None,
+            },
+            Err(e) => Some(e.to_string()),
+            Ok(()) if expects_failure => Some(match self.block.expect_error {
+                Some(ref expected) => format!(
+                    "Block expected to fail with \"{}\" but compiled successfully",
+                    expected
+                ),
+                None => "Block marked should_fail but compiled successfully".to_string(),
+            }),
+            Ok(()) => None,
+        };
+
+        let code = self.block_source();
+        let block_start_line = self.language.block_start_line(&self.propagated, &self.code);
         CompilationResult {
             language: self.language,
             duration,
             chapter_path: self.chapter_path,
             block_index: self.block_index,
-            code: self.code,
-            error_message: compile_result.err().map(|e| e.to_string()),
+            block: self.block,
+            code,
+            kind: self.kind,
+            error_message,
+            run_stdout,
+            run_stderr,
+            cached: false,
+            block_start_line,
+            source_path: self.temp_path,
+            kept_source: None,
+            missing_compiler,
         }
     }
 }
 
+/// Compares a program's stdout against an `expect_output` value.
+///
+/// Both sides are normalized before comparison: `\r\n` line endings become
+/// `\n` and all trailing `\n` characters are removed. Everything else,
+/// including leading and trailing spaces on each line, must match exactly.
+fn check_output(expected: &str, actual: &str) -> anyhow::Result<()> {
+    let normalize = |s: &str| s.replace("\r\n", "\n").trim_end_matches('\n').to_string();
+    let (expected, actual) = (normalize(expected), normalize(actual));
+    if expected != actual {
+        anyhow::bail!(
+            "Program output does not match expect_output\n\n\
+             Diff between expected and actual output:\n{}",
+            line_diff(&expected, &actual)
+        );
+    }
+    Ok(())
+}
+
+/// Returns whether `error` was caused by the compiler exceeding its timeout.
+fn is_timeout(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::TimedOut)
+    })
+}
+
 /// Result of compiling a single code block.
 ///
 /// This struct captures all compilation outcomes (success or failure)
 /// along with timing information for statistics and trace logging.
+///
+/// Reporters can inspect the originating [`CodeBlock`] (attributes and
+/// surrounding heading) via [`CompilationResult::block`] and the resolved
+/// compiler and flags via [`CompilationResult::language`].
 pub struct CompilationResult {
     language: ConfiguredLanguage,
     duration: Duration,
     chapter_path: PathBuf,
     block_index: usize,
+    block: CodeBlock,
     code: String,
+    kind: TaskKind,
     error_message: Option<String>,
+    run_stdout: Option<String>,
+    run_stderr: Option<String>,
+    cached: bool,
+    block_start_line: usize,
+    source_path: PathBuf,
+    kept_source: Option<PathBuf>,
+    missing_compiler: bool,
 }
 
 impl CompilationResult {
@@ -89,13 +399,61 @@ impl CompilationResult {
         self.block_index
     }
 
+    /// The extracted code block this result was compiled from.
+    pub fn block(&self) -> &CodeBlock {
+        &self.block
+    }
+
     pub fn code(&self) -> &str {
         &self.code
     }
 
+    pub fn kind(&self) -> TaskKind {
+        self.kind
+    }
+
+    /// Human-readable label for the validated block, e.g. `#3` or
+    /// `propagated context`.
+    pub fn block_label(&self) -> String {
+        block_label(self.kind, self.block_index)
+    }
+
     pub fn error_message(&self) -> Option<&str> {
         self.error_message.as_deref()
     }
+
+    /// Captured stdout of the block's program, in run mode.
+    pub fn run_stdout(&self) -> Option<&str> {
+        self.run_stdout.as_deref()
+    }
+
+    /// Captured stderr of the block's program, in run mode.
+    pub fn run_stderr(&self) -> Option<&str> {
+        self.run_stderr.as_deref()
+    }
+
+    /// Returns true if the result came from the compilation cache.
+    pub fn is_cached(&self) -> bool {
+        self.cached
+    }
+
+    /// One-based line at which the block's own code starts in the source
+    /// file given to the compiler.
+    pub fn block_start_line(&self) -> usize {
+        self.block_start_line
+    }
+
+    /// Returns true if the block failed because its compiler does not exist;
+    /// the error message then names the compiler and how to fix it.
+    pub fn missing_compiler(&self) -> bool {
+        self.missing_compiler
+    }
+
+    /// Copy of the source file given to the compiler, if this result failed
+    /// and [`keep_failed_sources`] kept it.
+    pub fn kept_source(&self) -> Option<&Path> {
+        self.kept_source.as_deref()
+    }
 }
 
 /// Compiles all tasks asynchronously with controlled concurrency.
@@ -103,20 +461,743 @@ impl CompilationResult {
 /// Uses `buffer_unordered` to limit the number of concurrent compilation tasks,
 /// which controls how many compiler subprocesses run simultaneously.
 ///
+/// Tasks of languages marked `serial` are grouped per (chapter, language) and
+/// each group compiles its tasks one at a time in document order, while
+/// different groups (and all other tasks) still run in parallel.
+///
+/// Tasks whose language declares a `resource` hold that resource's
+/// single-permit semaphore while compiling, so languages sharing a resource
+/// never compile concurrently with each other.
+///
+/// With a `cache`, tasks that compiled successfully before with identical
+/// inputs are not compiled again, and new successes are recorded.
+///
 /// Returns a tuple of (results, total_parallel_duration).
 pub async fn compile_tasks(
     tasks: Vec<CompilationTask>,
     max_concurrent: usize,
+    max_memory_jobs: usize,
+    fail_fast: bool,
+    progress: Option<&Progress>,
+    cache: Option<&CompileCache>,
 ) -> (Vec<CompilationResult>, Duration) {
     let parallel_start = Instant::now();
 
-    let results: Vec<CompilationResult> = stream::iter(tasks)
-        .map(|task| task.compile())
-        .buffer_unordered(max_concurrent)
-        .collect()
-        .await;
+    let memory = &Semaphore::new(max_memory_jobs);
+
+    let mut resources: HashMap<String, Arc<Semaphore>> = HashMap::new();
+    for task in &tasks {
+        if let Some(resource) = task.language.resource() {
+            resources
+                .entry(resource.to_string())
+                .or_insert_with(|| Arc::new(Semaphore::new(1)));
+        }
+    }
+    let resources = &resources;
+
+    let mut compiled = stream::iter(group_serial_tasks(tasks))
+        .map(|group| async move {
+            let mut results = Vec::with_capacity(group.len());
+            for task in group {
+                if cache.is_some_and(|cache| cache.contains(&task)) {
+                    results.push(task.cached());
+                    progress.inspect(|progress| progress.advance());
+                    continue;
+                }
+
+                let _permit = match task.language.resource() {
+                    Some(resource) => Some(
+                        resources[resource]
+                            .acquire()
+                            .await
+                            .expect("resource semaphore is never closed"),
+                    ),
+                    None => None,
+                };
+                // A weight above the budget would never be granted; cap it so
+                // such a language simply compiles one block at a time.
+                let weight = task.language.memory_weight().min(max_memory_jobs as u32);
+                let _memory_permit = match weight {
+                    0 => None,
+                    weight => Some(
+                        memory
+                            .acquire_many(weight)
+                            .await
+                            .expect("memory semaphore is never closed"),
+                    ),
+                };
+                let result = task.compile().await;
+                if let Some(cache) = cache {
+                    if let Err(error) = cache.record(&result) {
+                        log::warn!("{:#}", error);
+                    }
+                }
+                let stop = fail_fast && fails_fast(&result);
+                results.push(result);
+                progress.inspect(|progress| progress.advance());
+                if stop {
+                    break;
+                }
+            }
+            results
+        })
+        .buffer_unordered(max_concurrent);
+
+    let mut results = Vec::new();
+    while let Some(group) = compiled.next().await {
+        let stop = fail_fast && group.iter().any(fails_fast);
+        results.extend(group);
+        if stop {
+            // Dropping the stream cancels the groups still compiling
+            break;
+        }
+    }
+
+    // Results arrive in completion order; sort them so reports are reproducible
+    results.sort_by(|a, b| {
+        (&a.chapter_path, a.block_index, a.kind).cmp(&(&b.chapter_path, b.block_index, b.kind))
+    });
 
     let parallel_duration = parallel_start.elapsed();
 
     (results, parallel_duration)
 }
+
+/// Whether `result` stops a `fail_fast` run. Standalone compilations are
+/// diagnostics and never do.
+fn fails_fast(result: &CompilationResult) -> bool {
+    !result.success() && result.kind() != TaskKind::Standalone
+}
+
+/// Removes failures caused by a broken propagated context.
+///
+/// For every chapter and language whose [`TaskKind::PropagatedContext`] task
+/// failed, the failures of that language's [`TaskKind::DependentBlock`] tasks
+/// in the chapter are dropped so only the root failure is reported. Returns
+/// the remaining failures and the number of suppressed ones.
+pub fn suppress_context_cascades(
+    failed: Vec<&CompilationResult>,
+) -> (Vec<&CompilationResult>, usize) {
+    let broken_contexts: HashSet<(&Path, String)> = failed
+        .iter()
+        .filter(|r| r.kind() == TaskKind::PropagatedContext)
+        .map(|r| (r.chapter_path(), r.language().to_string()))
+        .collect();
+
+    let before = failed.len();
+    let remaining: Vec<_> = failed
+        .into_iter()
+        .filter(|r| {
+            r.kind() != TaskKind::DependentBlock
+                || !broken_contexts.contains(&(r.chapter_path(), r.language().to_string()))
+        })
+        .collect();
+    let suppressed = before - remaining.len();
+
+    (remaining, suppressed)
+}
+
+/// Finds blocks that compile only because of propagated code.
+///
+/// Returns the [`TaskKind::DependentBlock`] results that succeeded while the
+/// matching [`TaskKind::Standalone`] result failed.
+pub fn context_dependent_blocks<'a>(
+    results: &'a [CompilationResult],
+    standalone: &[CompilationResult],
+) -> Vec<&'a CompilationResult> {
+    let failed_standalone: HashSet<(&Path, usize)> = standalone
+        .iter()
+        .filter(|r| !r.success())
+        .map(|r| (r.chapter_path(), r.block_index()))
+        .collect();
+
+    results
+        .iter()
+        .filter(|r| r.kind() == TaskKind::DependentBlock && r.success())
+        .filter(|r| failed_standalone.contains(&(r.chapter_path(), r.block_index())))
+        .collect()
+}
+
+/// Copies the source file given to the compiler for each failed result into
+/// `dir`, named after the chapter and block index, and records where it was
+/// kept. The files of a multi-file unit are copied into a directory of that
+/// name instead.
+///
+/// Previous contents of `dir` are removed first, so it only ever holds the
+/// failures of the latest build. Successful results are never copied, and
+/// `dir` is only created if something failed.
+pub fn keep_failed_sources(results: &mut [CompilationResult], dir: &Path) -> anyhow::Result<()> {
+    if dir.exists() {
+        fs::remove_dir_all(dir).with_context(|| format!("Failed to remove {}", dir.display()))?;
+    }
+
+    // Languages compiling from stdin never wrote a source file to keep
+    for result in results
+        .iter_mut()
+        .filter(|r| !r.success() && r.source_path.exists())
+    {
+        let chapter = result
+            .chapter_path
+            .display()
+            .to_string()
+            .replace(['/', '\\'], "_");
+        let name = match result.kind {
+            TaskKind::PropagatedContext => format!("{}_context", chapter),
+            _ => format!("{}_block_{}", chapter, result.block_index),
+        };
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let kept = if result.source_path.is_dir() {
+            let kept = dir.join(name);
+            copy_dir(&result.source_path, &kept)?;
+            kept
+        } else {
+            let kept = result.language.source_path(dir, &name);
+            if let Some(parent) = kept.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+            copy_file(&result.source_path, &kept)?;
+            kept
+        };
+        result.kept_source = Some(kept);
+    }
+
+    Ok(())
+}
+
+fn copy_file(from: &Path, to: &Path) -> anyhow::Result<()> {
+    fs::copy(from, to)
+        .with_context(|| format!("Failed to copy {} to {}", from.display(), to.display()))?;
+    Ok(())
+}
+
+/// Copies the files directly inside `from` into a new directory `to`.
+fn copy_dir(from: &Path, to: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(to).with_context(|| format!("Failed to create {}", to.display()))?;
+    for entry in fs::read_dir(from).with_context(|| format!("Failed to read {}", from.display()))? {
+        let path = entry?.path();
+        if path.is_file() {
+            copy_file(&path, &to.join(path.file_name().unwrap_or_default()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Splits tasks into groups that must each run sequentially.
+///
+/// Tasks of a `serial` language are grouped by (chapter, language) preserving
+/// their collection order; every other task forms a group of its own.
+fn group_serial_tasks(tasks: Vec<CompilationTask>) -> Vec<Vec<CompilationTask>> {
+    let mut groups: Vec<Vec<CompilationTask>> = Vec::new();
+    let mut serial_groups: HashMap<(PathBuf, String), usize> = HashMap::new();
+
+    for task in tasks {
+        if !task.language.is_serial() {
+            groups.push(vec![task]);
+            continue;
+        }
+
+        let key = (task.chapter_path.clone(), task.language.to_string());
+        match serial_groups.get(&key) {
+            Some(&index) => groups[index].push(task),
+            None => {
+                serial_groups.insert(key, groups.len());
+                groups.push(vec![task]);
+            }
+        }
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LanguageConfig;
+
+    fn task(language: &str, serial: bool, chapter: &str, block_index: usize) -> CompilationTask {
+        let mut config: LanguageConfig = toml::from_str(r#"compiler = "true""#).unwrap();
+        config.serial = serial;
+        CompilationTask::new(
+            ConfiguredLanguage::new(language.to_string(), None, config),
+            PathBuf::from(format!("/tmp/{}_{}", chapter, block_index)),
+            PathBuf::from(chapter),
+            block_index,
+            CodeBlock::default(),
+            String::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_shared_resource_serializes_languages() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let lock = temp_dir.path().join("lock");
+        // Fails if another holder of the resource is compiling at the same time
+        let script = format!("mkdir {0} || exit 1; sleep 0.05; rmdir {0}", lock.display());
+
+        let tasks: Vec<_> = ["c", "cpp", "c", "cpp"]
+            .iter()
+            .enumerate()
+            .map(|(i, language)| {
+                let mut config: LanguageConfig = toml::from_str(r#"compiler = "sh""#).unwrap();
+                config.flags = vec!["-c".to_string(), script.clone(), "sh".to_string()];
+                config.resource = Some("shared".to_string());
+                CompilationTask::new(
+                    ConfiguredLanguage::new(language.to_string(), None, config),
+                    temp_dir.path().join(format!("block_{}", i)),
+                    PathBuf::from(format!("chapter_{}.md", i)),
+                    i,
+                    CodeBlock::default(),
+                    String::new(),
+                )
+            })
+            .collect();
+
+        let (results, _) = compile_tasks(tasks, 4, 4, false, None, None).await;
+        assert_eq!(results.len(), 4);
+        assert!(results.iter().all(|r| r.success()));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_tasks_get_distinct_outdirs() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        // Fails if another block already wrote to the same output directory
+        let script = "[ -z \"$(ls {outdir})\" ] || exit 1; touch {outdir}/out.js; sleep 0.05";
+
+        let tasks: Vec<_> = (0..2)
+            .map(|i| {
+                let mut config: LanguageConfig = toml::from_str(r#"compiler = "sh""#).unwrap();
+                config.flags = vec!["-c".to_string(), script.to_string()];
+                CompilationTask::new(
+                    ConfiguredLanguage::new("typescript".to_string(), None, config),
+                    temp_dir.path().join(format!("block_{}.ts", i)),
+                    PathBuf::from("chapter.md"),
+                    i,
+                    CodeBlock::default(),
+                    String::new(),
+                )
+            })
+            .collect();
+
+        let (results, _) = compile_tasks(tasks, 2, 1, false, None, None).await;
+        assert!(results.iter().all(|r| r.success()));
+        for i in 0..2 {
+            assert!(temp_dir
+                .path()
+                .join(format!("block_{}.outdir/out.js", i))
+                .exists());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_weight_bounds_concurrency() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let running = temp_dir.path().join("running");
+        std::fs::create_dir(&running).unwrap();
+        // Fails if more than two weighted blocks are compiling at the same time
+        let script = format!(
+            "touch {0}/$$; n=$(ls {0} | wc -l); sleep 0.05; rm {0}/$$; [ $n -le 2 ]",
+            running.display()
+        );
+
+        let tasks: Vec<_> = (0..8)
+            .map(|i| {
+                let mut config: LanguageConfig = toml::from_str(r#"compiler = "sh""#).unwrap();
+                config.flags = vec!["-c".to_string(), script.clone(), "sh".to_string()];
+                config.memory_weight = 2;
+                CompilationTask::new(
+                    ConfiguredLanguage::new("rust".to_string(), None, config),
+                    temp_dir.path().join(format!("block_{}", i)),
+                    PathBuf::from(format!("chapter_{}.md", i)),
+                    i,
+                    CodeBlock::default(),
+                    String::new(),
+                )
+            })
+            .collect();
+
+        let (results, _) = compile_tasks(tasks, 8, 4, false, None, None).await;
+        assert_eq!(results.len(), 8);
+        assert!(results.iter().all(|r| r.success()));
+    }
+
+    #[tokio::test]
+    async fn test_results_are_in_document_order() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let tasks = || -> Vec<_> {
+            (0..6)
+                .map(|i| {
+                    // Earlier blocks take longer, so they finish last
+                    let mut config: LanguageConfig = toml::from_str(r#"compiler = "sh""#).unwrap();
+                    config.flags = vec!["-c".to_string(), format!("sleep 0.0{}", 6 - i)];
+                    CompilationTask::new(
+                        ConfiguredLanguage::new("c".to_string(), None, config),
+                        temp_dir.path().join(format!("block_{}", i)),
+                        PathBuf::from(format!("chapter_{}.md", i % 2)),
+                        i / 2,
+                        CodeBlock::default(),
+                        String::new(),
+                    )
+                })
+                .collect()
+        };
+        let order = |results: &[CompilationResult]| -> Vec<(PathBuf, usize)> {
+            results
+                .iter()
+                .map(|r| (r.chapter_path().to_path_buf(), r.block_index()))
+                .collect()
+        };
+
+        let (first, _) = compile_tasks(tasks(), 6, 1, false, None, None).await;
+        let (second, _) = compile_tasks(tasks(), 6, 1, false, None, None).await;
+        assert_eq!(order(&first), order(&second));
+        assert_eq!(
+            order(&first),
+            [0, 1]
+                .iter()
+                .flat_map(|c| (0..3).map(move |i| (PathBuf::from(format!("chapter_{}.md", c)), i)))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fail_fast_stops_at_first_failure() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let tasks: Vec<_> = (0..8)
+            .map(|i| {
+                let script = if i == 0 { "exit 1" } else { "sleep 5" };
+                let mut config: LanguageConfig = toml::from_str(r#"compiler = "sh""#).unwrap();
+                config.flags = vec!["-c".to_string(), script.to_string()];
+                CompilationTask::new(
+                    ConfiguredLanguage::new("c".to_string(), None, config),
+                    temp_dir.path().join(format!("block_{}", i)),
+                    PathBuf::from("chapter.md"),
+                    i,
+                    CodeBlock::default(),
+                    String::new(),
+                )
+            })
+            .collect();
+
+        let start = Instant::now();
+        let (results, _) = compile_tasks(tasks, 4, 1, true, None, None).await;
+        assert!(start.elapsed() < Duration::from_secs(4));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].block_index(), 0);
+        assert!(!results[0].success());
+    }
+
+    #[tokio::test]
+    async fn test_progress_counts_every_task() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let tasks: Vec<_> = (0..5)
+            .map(|i| task("c", i % 2 == 0, "chapter.md", i))
+            .map(|task| {
+                let path = temp_dir.path().join(format!("block_{}", task.block_index));
+                CompilationTask {
+                    temp_path: path,
+                    ..task
+                }
+            })
+            .collect();
+
+        let progress = Progress::new(tasks.len(), false);
+        let (results, _) = compile_tasks(tasks, 4, 1, false, Some(&progress), None).await;
+        assert_eq!(results.len(), 5);
+        assert_eq!(progress.done(), 5);
+    }
+
+    fn result(chapter: &str, kind: TaskKind, success: bool) -> CompilationResult {
+        let task = task("c", false, chapter, 0).with_kind(kind);
+        CompilationResult {
+            language: task.language,
+            duration: Duration::ZERO,
+            chapter_path: task.chapter_path,
+            block_index: task.block_index,
+            block: task.block,
+            code: task.code,
+            kind: task.kind,
+            error_message: (!success).then(|| "error".to_string()),
+            run_stdout: None,
+            run_stderr: None,
+            cached: false,
+            block_start_line: 1,
+            source_path: PathBuf::new(),
+            kept_source: None,
+            missing_compiler: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_should_fail_inverts_outcome() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let compile = |compiler: &str, should_fail: bool| {
+            let config: LanguageConfig =
+                toml::from_str(&format!("compiler = {:?}", compiler)).unwrap();
+            let block = CodeBlock {
+                should_fail,
+                ..Default::default()
+            };
+            CompilationTask::new(
+                ConfiguredLanguage::new("c".to_string(), None, config),
+                temp_dir
+                    .path()
+                    .join(format!("{}_{}", compiler, should_fail)),
+                PathBuf::from("a.md"),
+                0,
+                block,
+                String::new(),
+            )
+            .compile()
+        };
+
+        assert!(compile("false", true).await.success());
+        assert!(compile("true", false).await.success());
+        assert!(!compile("false", false).await.success());
+
+        let result = compile("true", true).await;
+        assert_eq!(
+            result.error_message(),
+            Some("Block marked should_fail but compiled successfully")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expect_error_matches_compiler_output() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let compile = |expected: &str| {
+            let config: LanguageConfig = toml::from_str(
+                r#"compiler = "sh"
+flags = ["-c", "echo 'error: cannot borrow' >&2; exit 1"]"#,
+            )
+            .unwrap();
+            let block = CodeBlock {
+                should_fail: true,
+                expect_error: Some(expected.to_string()),
+                ..Default::default()
+            };
+            CompilationTask::new(
+                ConfiguredLanguage::new("c".to_string(), None, config),
+                temp_dir.path().join(expected.replace(' ', "_")),
+                PathBuf::from("a.md"),
+                0,
+                block,
+                String::new(),
+            )
+            .compile()
+        };
+
+        assert!(compile("cannot borrow").await.success());
+
+        let result = compile("type mismatch").await;
+        let message = result.error_message().unwrap();
+        assert!(message.contains("Expected error containing: type mismatch"));
+        assert!(message.contains("error: cannot borrow"));
+    }
+
+    #[tokio::test]
+    async fn test_run_mode_fails_on_nonzero_exit() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config: LanguageConfig = toml::from_str(
+            r#"compiler = "sh"
+flags = ["-c", "printf '#!/bin/sh\\necho running\\nexit 3\\n' > {artifact} && chmod +x {artifact}"]
+run_command = ["{artifact}"]"#,
+        )
+        .unwrap();
+        let result = CompilationTask::new(
+            ConfiguredLanguage::new("c".to_string(), None, config),
+            temp_dir.path().join("block.c"),
+            PathBuf::from("a.md"),
+            0,
+            CodeBlock::default(),
+            String::new(),
+        )
+        .compile()
+        .await;
+
+        assert!(result.error_message().unwrap().contains("Program exited"));
+        assert_eq!(result.run_stdout(), Some("running\n"));
+    }
+
+    #[tokio::test]
+    async fn test_no_run_block_is_only_compiled() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config: LanguageConfig = toml::from_str(
+            r#"compiler = "sh"
+flags = ["-c", "printf '#!/bin/sh\\nexit 101\\n' > {artifact} && chmod +x {artifact}"]
+run_command = ["{artifact}"]"#,
+        )
+        .unwrap();
+        let block = CodeBlock {
+            no_run: true,
+            ..Default::default()
+        };
+        let result = CompilationTask::new(
+            ConfiguredLanguage::new("c".to_string(), None, config),
+            temp_dir.path().join("block.c"),
+            PathBuf::from("a.md"),
+            0,
+            block,
+            String::new(),
+        )
+        .compile()
+        .await;
+
+        assert!(result.success());
+        assert_eq!(result.run_stdout(), None);
+    }
+
+    #[test]
+    fn test_check_output_normalizes_trailing_newlines() {
+        assert!(check_output("42", "42\n").is_ok());
+        assert!(check_output("1\n2\n\n", "1\r\n2\r\n").is_ok());
+        assert!(check_output("42", "42 \n").is_err());
+
+        let err = check_output("1\n2", "1\n3\n").unwrap_err();
+        assert!(err.to_string().contains("  1\n- 2\n+ 3\n"));
+    }
+
+    #[test]
+    fn test_context_dependent_blocks() {
+        let results = [
+            result("a.md", TaskKind::DependentBlock, true),
+            result("b.md", TaskKind::DependentBlock, true),
+            result("c.md", TaskKind::DependentBlock, false),
+        ];
+        let standalone = [
+            result("a.md", TaskKind::Standalone, false),
+            result("b.md", TaskKind::Standalone, true),
+            result("c.md", TaskKind::Standalone, false),
+        ];
+
+        let dependent = context_dependent_blocks(&results, &standalone);
+        assert_eq!(dependent.len(), 1);
+        assert_eq!(dependent[0].chapter_path(), Path::new("a.md"));
+    }
+
+    #[test]
+    fn test_suppress_context_cascades() {
+        let results = [
+            result("a.md", TaskKind::PropagatedContext, false),
+            result("a.md", TaskKind::DependentBlock, false),
+            result("a.md", TaskKind::Block, false),
+            result("b.md", TaskKind::PropagatedContext, true),
+            result("b.md", TaskKind::DependentBlock, false),
+        ];
+        let failed: Vec<_> = results.iter().filter(|r| !r.success()).collect();
+
+        let (remaining, suppressed) = suppress_context_cascades(failed);
+        assert_eq!(suppressed, 1);
+        let remaining: Vec<_> = remaining
+            .iter()
+            .map(|r| (r.chapter_path().display().to_string(), r.kind()))
+            .collect();
+        assert_eq!(
+            remaining,
+            vec![
+                ("a.md".to_string(), TaskKind::PropagatedContext),
+                ("a.md".to_string(), TaskKind::Block),
+                ("b.md".to_string(), TaskKind::DependentBlock),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_group_serial_tasks_per_chapter_and_language() {
+        let tasks = vec![
+            task("c", true, "a.md", 0),
+            task("ts", false, "a.md", 1),
+            task("c", true, "b.md", 0),
+            task("c", true, "a.md", 2),
+            task("ts", false, "a.md", 3),
+        ];
+
+        let groups: Vec<Vec<(String, usize)>> = group_serial_tasks(tasks)
+            .into_iter()
+            .map(|group| {
+                group
+                    .into_iter()
+                    .map(|t| (t.chapter_path.display().to_string(), t.block_index))
+                    .collect()
+            })
+            .collect();
+
+        assert_eq!(
+            groups,
+            vec![
+                vec![("a.md".to_string(), 0), ("a.md".to_string(), 2)],
+                vec![("a.md".to_string(), 1)],
+                vec![("b.md".to_string(), 0)],
+                vec![("a.md".to_string(), 3)],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_compiler_reported_actionably() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config: LanguageConfig =
+            toml::from_str(r#"compiler = "mdbook-check-code-no-such-compiler""#).unwrap();
+        // Even a block expected to fail must not pass because nothing ran
+        let block = CodeBlock {
+            should_fail: true,
+            ..CodeBlock::default()
+        };
+        let result = CompilationTask::new(
+            ConfiguredLanguage::new("c".to_string(), None, config),
+            temp_dir.path().join("block_0.c"),
+            PathBuf::from("intro.md"),
+            0,
+            block,
+            "int x;".to_string(),
+        )
+        .compile()
+        .await;
+
+        assert!(!result.success());
+        assert!(result.missing_compiler());
+        assert_eq!(
+            result.error_message().unwrap(),
+            "Compiler 'mdbook-check-code-no-such-compiler' for language 'c' was not found on PATH — install it or set an absolute path in book.toml"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keep_failed_sources() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let kept_dir = temp_dir.path().join("check-code-failures");
+        fs::create_dir_all(&kept_dir).unwrap();
+        fs::write(kept_dir.join("stale.c"), "").unwrap();
+
+        let mut results = Vec::new();
+        for (index, compiler) in [(0, "true"), (1, "false")] {
+            let config: LanguageConfig = toml::from_str(&format!(
+                "compiler = {:?}\npreamble = \"#include <stdio.h>\"",
+                compiler
+            ))
+            .unwrap();
+            let task = CompilationTask::new(
+                ConfiguredLanguage::new("c".to_string(), None, config),
+                temp_dir.path().join(format!("block_{}.c", index)),
+                PathBuf::from("guide/intro.md"),
+                index,
+                CodeBlock::default(),
+                "int x;".to_string(),
+            );
+            results.push(task.compile().await);
+        }
+
+        keep_failed_sources(&mut results, &kept_dir).unwrap();
+
+        assert!(results[0].kept_source().is_none());
+        let kept = results[1].kept_source().unwrap();
+        assert_eq!(kept, kept_dir.join("guide_intro.md_block_1.c"));
+        let source = fs::read_to_string(kept).unwrap();
+        assert!(source.contains("#include <stdio.h>"));
+        assert!(source.contains("int x;"));
+
+        // Only the failure is kept; earlier runs' files are gone
+        let entries: Vec<_> = fs::read_dir(&kept_dir).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+    }
+}
@@ -1,8 +1,54 @@
 use anyhow::{Context, Result};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
-use std::path::Path;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Number of failures reported in full when `max_reported_errors` is not configured.
+pub const DEFAULT_MAX_REPORTED_ERRORS: usize = 10;
+
+/// Characters rejected in compiler paths.
+pub const SHELL_METACHARACTERS: [char; 6] = [';', '|', '&', '`', '\n', '\r'];
+
+/// WASM runtime options that give the module access to the host: preopened
+/// directories, environment variables, sockets, and WASI capability settings
+/// (`-S`/`--wasi` in wasmtime, `--net` in wasmer). Rejected for the `wasm`
+/// backend.
+///
+/// This catches common mistakes and does not make the backend a sandbox:
+/// other options of these or other runtimes may still grant host access.
+pub const WASM_HOST_ACCESS_FLAGS: &[&str] = &[
+    "--dir",
+    "--mapdir",
+    "--env",
+    "--tcplisten",
+    "--listenfd",
+    "--net",
+    "--wasi",
+    "-S",
+];
+
+/// Returns the first of `flags` that is one of [`WASM_HOST_ACCESS_FLAGS`],
+/// alone, with an `=value`, or, for short options, with the value attached.
+pub fn wasm_host_access_flag(flags: &[String]) -> Option<&str> {
+    flags.iter().map(String::as_str).find(|flag| {
+        WASM_HOST_ACCESS_FLAGS.iter().any(|option| {
+            flag.strip_prefix(option).is_some_and(|rest| {
+                rest.is_empty() || rest.starts_with('=') || !option.starts_with("--")
+            })
+        })
+    })
+}
+
+/// Fence markers treated as prose-only when `prose_markers` is not configured.
+pub const DEFAULT_PROSE_MARKERS: &[&str] =
+    &["mermaid", "text", "plaintext", "console", "diff", "output"];
+
+/// Renderers the preprocessor checks code for when `renderers` is not configured.
+pub const DEFAULT_RENDERERS: &[&str] = &["html"];
 
 /// Configuration for the check-code preprocessor.
 ///
@@ -13,7 +59,7 @@ use std::path::Path;
 ///
 /// ```toml
 /// [preprocessor.check-code]
-/// parallel_jobs = 4  # Optional: limit parallel compilations (default: all CPUs)
+/// parallel_jobs = 4  # Optional: limit parallel compilations (default: 8 per CPU)
 ///
 /// [preprocessor.check-code.languages.c]
 /// enabled = true
@@ -25,16 +71,289 @@ use std::path::Path;
 #[serde(default)]
 pub struct CheckCodeConfig {
     /// Number of parallel compilation jobs.
-    /// If None or 0, uses all available CPU cores (default behavior).
+    /// If None or 0, uses eight jobs per CPU core (`num_cpus::get() * 8`),
+    /// since compilations mostly wait on subprocesses.
     /// Set to 1 for sequential compilation.
     #[serde(default)]
     pub parallel_jobs: Option<usize>,
 
+    /// Memory budget shared by languages with a `memory_weight`. Each of their
+    /// blocks holds `memory_weight` units while compiling, so at most this
+    /// many units are in use at once. If None or 0, uses one unit per CPU core.
+    #[serde(default)]
+    pub max_memory_jobs: Option<usize>,
+
+    /// Optional path for a JUnit XML report of all validated blocks.
+    /// Relative paths are resolved against the book root. Also accepted as
+    /// `junit_file`.
+    #[serde(default, alias = "junit_file")]
+    pub junit_path: Option<PathBuf>,
+
+    /// Optional path for a JSON report of all validated blocks, for tooling.
+    /// Relative paths are resolved against the book root.
+    #[serde(default)]
+    pub report_file: Option<PathBuf>,
+
+    /// Optional path for a SARIF 2.1.0 log of failed blocks, for code
+    /// scanning. Relative paths are resolved against the book root.
+    #[serde(default)]
+    pub sarif_file: Option<PathBuf>,
+
+    /// Optional path for a Markdown summary of the build, for pull request
+    /// comments. Relative paths are resolved against the book root.
+    #[serde(default)]
+    pub summary_file: Option<PathBuf>,
+
+    /// Optional SQLite database that receives one row per validated block on
+    /// every build. Relative paths are resolved against the book root.
+    /// Requires the `sqlite` cargo feature.
+    #[serde(default)]
+    pub sqlite_path: Option<PathBuf>,
+
+    /// Write a `compile_commands.json` to the book root describing how each
+    /// C-family block is compiled, for clangd and similar tools. Defaults to
+    /// false.
+    #[serde(default)]
+    pub emit_compile_commands: bool,
+
+    /// Fence markers for prose-only blocks (diagrams, terminal output, diffs)
+    /// that are never compiled, even if a configured language claims them.
+    /// If None, [`DEFAULT_PROSE_MARKERS`] is used; set to `[]` to disable.
+    #[serde(default)]
+    pub prose_markers: Option<Vec<String>>,
+
+    /// Code placed before the preamble of every language and variant, such
+    /// as a license header. It is written verbatim into every source, so it
+    /// must be valid in all enabled languages.
+    #[serde(default)]
+    pub global_preamble: Option<String>,
+
+    /// Renderers for which code blocks are checked. For any other renderer
+    /// the book is passed through unchanged without compiling anything.
+    /// If None, [`DEFAULT_RENDERERS`] is used.
+    #[serde(default)]
+    pub renderers: Option<Vec<String>>,
+
+    /// Warn about each code block whose fence names no configured language,
+    /// such as a misspelled ```` ```rustt ````, since those blocks are never
+    /// checked. Blocks marked `ignore`, prose markers, and fences without a
+    /// language never warn. Defaults to false.
+    #[serde(default)]
+    pub warn_unconfigured: bool,
+
+    /// Fail the build for each code block whose fence names no configured
+    /// language, with the same exemptions as `warn_unconfigured`. Defaults
+    /// to false.
+    #[serde(default)]
+    pub strict: bool,
+
+    /// Fence markers that are never reported by `warn_unconfigured` or
+    /// `strict`, for languages that cannot be compiled (e.g. `json`).
+    #[serde(default)]
+    pub allowed_unconfigured: Vec<String>,
+
+    /// Fail the build when a block names a variant its language does not
+    /// define, e.g. a misspelled `variant=parsol`. Otherwise such blocks are
+    /// skipped with a warning. Defaults to false.
+    #[serde(default)]
+    pub strict_variants: bool,
+
+    /// Which book to return when some blocks fail to compile.
+    /// If None (default), failures abort the build. Otherwise failures are
+    /// reported but the build continues with the selected book, which keeps
+    /// `mdbook serve` rendering while snippets are broken.
+    #[serde(default)]
+    pub on_failure_return: Option<FailureReturn>,
+
+    /// Prefix each line of a failing code block with its line number when
+    /// reporting errors. Defaults to false.
+    #[serde(default)]
+    pub show_line_numbers: bool,
+
+    /// Copy the exact source given to the compiler for each failing block
+    /// (preamble and postamble included) into `check-code-failures/` under the
+    /// book root, and print its location. Defaults to false.
+    #[serde(default)]
+    pub keep_failed_sources: bool,
+
+    /// Number of failing blocks reported in full; the rest are only counted.
+    /// If None, [`DEFAULT_MAX_REPORTED_ERRORS`] is used; set to 0 to report all.
+    #[serde(default)]
+    pub max_reported_errors: Option<usize>,
+
+    /// How much is reported besides errors. Can be overridden with the
+    /// `--verbosity` command-line flag. Defaults to normal.
+    #[serde(default)]
+    pub verbosity: Verbosity,
+
+    /// Stop compiling as soon as one block fails, cancelling blocks still in
+    /// flight, and report only that failure. Can also be enabled with the
+    /// `--fail-fast` command-line flag. Defaults to false.
+    #[serde(default)]
+    pub fail_fast: bool,
+
+    /// Show a "compiled X/Y blocks" line on stderr while compiling. Only drawn
+    /// when stderr is a terminal. Can also be enabled with the `--progress`
+    /// command-line flag. Defaults to false.
+    #[serde(default)]
+    pub progress: bool,
+
+    /// List the compilations that would run, with their language, compiler,
+    /// and source file, without invoking any compiler. Can also be enabled
+    /// with the `--dry-run` command-line flag. Defaults to false.
+    #[serde(default)]
+    pub dry_run: bool,
+
+    /// Wrap per-chapter output in collapsible log groups when running under a
+    /// detected CI system (GitHub Actions or GitLab CI). Defaults to false.
+    #[serde(default)]
+    pub ci_groups: bool,
+
+    /// Print a GitHub Actions `::error` annotation for each failed block so
+    /// failures appear inline on pull request diffs. If None (default),
+    /// annotations are printed when running under GitHub Actions
+    /// (`GITHUB_ACTIONS=true`).
+    #[serde(default)]
+    pub github_annotations: Option<bool>,
+
+    /// Compile each chapter's accumulated propagated code on its own and,
+    /// if it fails, report only that failure instead of every dependent block.
+    /// Defaults to false.
+    #[serde(default)]
+    pub check_propagated_context: bool,
+
+    /// Also compile every block that uses propagated code without it, and
+    /// report blocks that only compile with the propagated code as
+    /// context-dependent. Informational only; doubles the work for such blocks.
+    /// Defaults to false.
+    #[serde(default)]
+    pub check_standalone: bool,
+
+    /// After collecting blocks, list configured variants of enabled languages
+    /// that no block uses. Informational only. Defaults to false.
+    #[serde(default)]
+    pub check_unused_variants: bool,
+
+    /// How blocks containing only whitespace are handled. Defaults to skipping
+    /// them, since empty blocks are usually drafting placeholders.
+    #[serde(default)]
+    pub empty_block: EmptyBlockPolicy,
+
+    /// Enable literate-programming tangle mode: blocks with a `name=<chunk>`
+    /// attribute define chunks that other blocks in the same chapter reference,
+    /// and references are expanded before compiling. Defaults to false.
+    #[serde(default)]
+    pub tangle: bool,
+
+    /// Regular expression matching a chunk reference in tangle mode, with a
+    /// capture group called `name`. If None, noweb-style `<<chunk-name>>` is used.
+    #[serde(default)]
+    pub tangle_reference: Option<String>,
+
+    /// Fence marker assumed for indented (four-space) code blocks, which carry
+    /// no info string. If None (default), indented blocks are not checked.
+    #[serde(default)]
+    pub default_indented_language: Option<String>,
+
+    /// Skip code blocks that compiled successfully in an earlier run with the
+    /// same content and language settings, speeding up `mdbook serve`
+    /// rebuilds. Defaults to false.
+    #[serde(default)]
+    pub block_cache: bool,
+
+    /// Skip chapters whose source is unchanged since a build in which every
+    /// block compiled, without extracting or compiling their blocks. Any
+    /// configuration change invalidates all chapters. Defaults to false.
+    #[serde(default)]
+    pub chapter_cache: bool,
+
+    /// Write each block's source file next to its chapter, under a hidden
+    /// `.check-code-` name, instead of in a temporary directory, so relative
+    /// includes and imports of sibling files resolve. The files are removed
+    /// once the block is compiled. Defaults to false.
+    #[serde(default)]
+    pub compile_in_place: bool,
+
+    /// Reuse successful compilations from earlier builds: a block whose code,
+    /// propagated code, and language settings (compiler, flags, preamble, ...)
+    /// are unchanged is not compiled again. Shared by all books. Defaults to
+    /// false.
+    #[serde(default)]
+    pub cache: bool,
+
+    /// Settings applied to every language (see [`LanguageDefaults`])
+    #[serde(default)]
+    pub defaults: LanguageDefaults,
+
     /// Language-specific configurations indexed by language name
     #[serde(default)]
     pub languages: HashMap<String, LanguageConfig>,
 }
 
+/// Settings shared by all languages, from the `defaults` section.
+///
+/// They are merged into each language when the configuration loads, after
+/// environment variable expansion:
+///
+/// - `flags` come before the language's own flags
+/// - `env` entries are added unless the language sets the same variable
+/// - `timeout_seconds` applies unless the language sets its own
+///
+/// Variants see the merged language: they inherit its `env` and
+/// `timeout_seconds` as usual, but only get the default flags with
+/// `inherit_flags = true`, since a variant's flags otherwise replace the
+/// language's.
+///
+/// # Example
+///
+/// ```toml
+/// [preprocessor.check-code.defaults]
+/// flags = ["-Wall", "-Werror"]
+/// timeout_seconds = 30
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct LanguageDefaults {
+    /// Flags placed before every language's flags
+    /// (supports ${VAR} environment variable expansion)
+    #[serde(default)]
+    pub flags: Vec<String>,
+
+    /// Environment variables for every language's compiler
+    /// (supports ${VAR} environment variable expansion)
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// Timeout for languages that do not set `timeout_seconds`
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+}
+
+impl LanguageDefaults {
+    /// Merges these defaults into `config`; the language's own settings
+    /// take precedence.
+    pub fn apply(&self, config: &mut LanguageConfig) {
+        config.flags.splice(0..0, self.flags.iter().cloned());
+        for (key, value) in &self.env {
+            config
+                .env
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+        config.timeout_seconds = config.timeout_seconds.or(self.timeout_seconds);
+    }
+}
+
+/// Book returned by the preprocessor when validation fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureReturn {
+    /// Return the book exactly as received, without any content transformations
+    Original,
+    /// Return the book with the preprocessor's content transformations applied
+    Processed,
+}
+
 /// Configuration for a language variant.
 ///
 /// Variants allow using different compilers or settings for the same base language.
@@ -60,6 +379,108 @@ pub struct VariantConfig {
     /// Optional preamble to prepend to all code blocks
     #[serde(default)]
     pub preamble: Option<String>,
+
+    /// File whose contents are used as the preamble, relative to the book
+    /// root. Cannot be combined with `preamble`.
+    #[serde(default)]
+    pub preamble_file: Option<PathBuf>,
+
+    /// Optional postamble to append to all code blocks
+    #[serde(default)]
+    pub postamble: Option<String>,
+
+    /// Fence markers that select this variant directly (e.g. ```` ```parasol ````),
+    /// in addition to the `variant=<name>` attribute on the base language's markers
+    #[serde(default)]
+    pub fence_markers: Vec<String>,
+
+    /// Overrides the base language's `timeout_seconds` for this variant
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+
+    /// Append this variant's `flags` to the base language's flags instead of
+    /// replacing them. Defaults to false.
+    #[serde(default)]
+    pub inherit_flags: bool,
+
+    /// Prepend the base language's preamble to this variant's `preamble`
+    /// instead of replacing it. Defaults to false.
+    #[serde(default)]
+    pub inherit_preamble: bool,
+
+    /// Environment variables merged over the base language's `env`
+    /// (supports ${VAR} environment variable expansion)
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+/// How empty (whitespace-only) code blocks are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EmptyBlockPolicy {
+    /// Do not validate empty blocks (default)
+    #[default]
+    Skip,
+    /// Report empty blocks as errors
+    Error,
+    /// Validate empty blocks like any other block
+    Compile,
+}
+
+/// How much the preprocessor reports besides errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    /// Print errors only
+    Quiet,
+    /// Print errors, the success summary, and informational messages (default)
+    #[default]
+    Normal,
+    /// Also print per-language timing
+    Verbose,
+}
+
+/// How code blocks of a language are validated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum CheckMode {
+    /// Run the compiler on the block and require a zero exit status (default)
+    #[default]
+    Compile,
+    /// Treat the compiler as a formatter that prints the formatted source to
+    /// stdout, run it twice, and require both passes to produce identical output
+    FormatStable,
+}
+
+/// Where code blocks of a language are validated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Run the configured compiler directly on the host (default)
+    #[default]
+    Native,
+    /// Run a WASM validator module inside a WASM runtime (the configured
+    /// `compiler`, e.g. `wasmtime`). Common flags granting filesystem,
+    /// environment or network access are rejected, but the runtime is trusted
+    /// like a native compiler. The block source is passed on stdin.
+    Wasm,
+}
+
+/// A part of the source file compiled for a code block.
+///
+/// `source_layout` lists these in the order they are written. Each non-empty
+/// segment is followed by a blank line when another segment comes after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceSegment {
+    /// The language's (or variant's) configured `preamble`
+    Preamble,
+    /// Code accumulated from earlier `propagate` blocks in the chapter
+    Propagated,
+    /// The code block itself
+    Block,
+    /// The language's (or variant's) configured `postamble`
+    Postamble,
 }
 
 /// Configuration for a specific language.
@@ -81,34 +502,316 @@ pub struct LanguageConfig {
     /// Compiler executable (supports ${VAR} environment variable expansion)
     pub compiler: String,
 
-    /// Compiler flags
+    /// Compiler flags. The source path is appended after them, unless a flag
+    /// contains `{file}`, which is replaced by the path instead. `{outdir}` is
+    /// replaced by an empty output directory created for the block inside the
+    /// temporary directory, so parallel blocks never write to the same place.
     #[serde(default)]
     pub flags: Vec<String>,
 
+    /// Prefixes of the flags code blocks may add with a `flags=` attribute,
+    /// e.g. `["-D", "-std="]`. Markdown is not covered by book approval, so
+    /// block flags are rejected unless they start with one of these.
+    #[serde(default)]
+    pub allowed_block_flags: Vec<String>,
+
     /// Optional preamble to prepend to all code blocks
     #[serde(default)]
     pub preamble: Option<String>,
 
+    /// File whose contents are used as the preamble, relative to the book
+    /// root. Cannot be combined with `preamble`.
+    #[serde(default)]
+    pub preamble_file: Option<PathBuf>,
+
+    /// Optional postamble to append to all code blocks (e.g. a `main`
+    /// function so that snippets without one still link)
+    #[serde(default)]
+    pub postamble: Option<String>,
+
     /// Fence markers that identify this language in markdown.
     /// If empty, defaults will be used based on the language name.
     #[serde(default)]
     pub fence_markers: Vec<String>,
 
+    /// Fence markers added to `fence_markers`, or to the defaults when
+    /// `fence_markers` is empty, rather than replacing them
+    #[serde(default)]
+    pub extra_fence_markers: Vec<String>,
+
     /// Variants of this language with different compilers or settings
     #[serde(default)]
     pub variants: HashMap<String, VariantConfig>,
+
+    /// Number of times to retry spawning the compiler when the OS reports a
+    /// transient resource shortage (`EAGAIN`). Compile failures are never retried.
+    #[serde(default = "default_spawn_retries")]
+    pub spawn_retries: u32,
+
+    /// How blocks are validated (`compile` or `format-stable`)
+    #[serde(default)]
+    pub mode: CheckMode,
+
+    /// Regular expressions that must not match any block of this language
+    #[serde(default)]
+    pub forbidden_patterns: Vec<Pattern>,
+
+    /// Regular expressions that must match every block of this language
+    #[serde(default)]
+    pub required_patterns: Vec<Pattern>,
+
+    /// Extension of the source files written for this language's blocks
+    /// (e.g. `".cc"`), overriding the default for the language name
+    #[serde(default)]
+    pub file_extension: Option<String>,
+
+    /// Exact name of the source file written for each block (e.g.
+    /// `"Dockerfile"`), for tools that expect a fixed file name. Each block
+    /// gets its own directory. Cannot be combined with `file_extension`.
+    #[serde(default)]
+    pub file_name: Option<String>,
+
+    /// Compile the preamble once per build into a precompiled header and
+    /// pass it to every block with `-include` instead of prepending it, which
+    /// speeds up preambles including heavy headers. Requires a gcc or clang
+    /// compatible compiler; if precompiling fails, a warning is printed and the
+    /// preamble is prepended as usual. Defaults to false.
+    #[serde(default)]
+    pub precompile_preamble: bool,
+
+    /// Pipe the assembled source into the compiler's stdin instead of writing
+    /// a temporary file and passing its path. `flags` then usually include
+    /// `-` (e.g. `["-xc", "-fsyntax-only", "-"]`). Requires the `compile`
+    /// mode and the native backend. Defaults to false.
+    #[serde(default)]
+    pub stdin: bool,
+
+    /// Keep CRLF line endings in the code given to the compiler. By default
+    /// they are converted to LF, so chapters saved on Windows don't produce
+    /// mixed line endings. Defaults to false.
+    #[serde(default)]
+    pub preserve_line_endings: bool,
+
+    /// Maximum bytes of compiler stdout and of stderr captured per block.
+    /// Output beyond the limit is discarded and noted in the error message.
+    /// If None, output is captured in full.
+    #[serde(default)]
+    pub max_output_bytes: Option<usize>,
+
+    /// Validation backend (`native` or `wasm`)
+    #[serde(default)]
+    pub backend: Backend,
+
+    /// WASM validator module for the `wasm` backend, relative to the book root
+    #[serde(default)]
+    pub module: Option<PathBuf>,
+
+    /// Compile this language's blocks one at a time, in document order, within
+    /// each chapter. Different chapters still compile concurrently.
+    #[serde(default)]
+    pub serial: bool,
+
+    /// Name of a shared resource (such as an output directory or cache) this
+    /// language's compiler uses. Blocks of all languages naming the same
+    /// resource compile one at a time; other blocks still run in parallel.
+    #[serde(default)]
+    pub resource: Option<String>,
+
+    /// Share of `max_memory_jobs` each block of this language holds while
+    /// compiling, for compilers that need a lot of memory. 0 (default) leaves
+    /// the language bounded only by `parallel_jobs`.
+    #[serde(default)]
+    pub memory_weight: u32,
+
+    /// Start each generated source file with a comment naming the chapter and
+    /// block it came from, so temporary files and diagnostics are traceable.
+    /// Shifts reported line numbers by one. Defaults to false.
+    #[serde(default)]
+    pub provenance_comment: bool,
+
+    /// Line-comment prefix for the provenance comment. If None, the prefix is
+    /// chosen from the language's file extension (`//`, `#`, `--`, `<!--`, ...).
+    #[serde(default)]
+    pub comment_prefix: Option<String>,
+
+    /// Text closing the provenance comment, for block-comment syntaxes (e.g. `-->`)
+    #[serde(default)]
+    pub comment_suffix: Option<String>,
+
+    /// Kill the compiler and fail the block if it runs longer than this many
+    /// seconds. If None (default), compilers may run indefinitely.
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+
+    /// Treat lines starting with `# ` as hidden: they are compiled but removed
+    /// from the rendered book, and `##` escapes a literal `#`. Defaults to false.
+    #[serde(default)]
+    pub hidden_lines: bool,
+
+    /// Allowed range for the number of this language's blocks (variants
+    /// included) across the whole book. If None (default), any count is allowed.
+    #[serde(default)]
+    pub expected_blocks: Option<BlockCountRange>,
+
+    /// Command that runs the program built from each block after it compiles,
+    /// failing the block on a nonzero exit status. `{artifact}` in this
+    /// command and in `flags` is replaced by the path the compiler should
+    /// write its output to (e.g. `flags = ["-o", "{artifact}"]`,
+    /// `run_command = ["{artifact}"]`); `{source}` in this command is replaced
+    /// by the block's source file. If None (default), nothing is run.
+    #[serde(default)]
+    pub run_command: Option<Vec<String>>,
+
+    /// Order in which the segments of a block's source file are assembled.
+    /// Must name `block` exactly once; omitted segments are left out. The
+    /// optional provenance comment always comes first.
+    #[serde(default = "default_source_layout")]
+    pub source_layout: Vec<SourceSegment>,
+
+    /// Environment variables set for the compiler and `run_command`, in
+    /// addition to the inherited environment (supports ${VAR} environment
+    /// variable expansion)
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+/// Inclusive range for the number of code blocks of a language.
+///
+/// # Example
+///
+/// ```toml
+/// [preprocessor.check-code.languages.typescript]
+/// expected_blocks = { min = 10 }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct BlockCountRange {
+    /// Minimum number of blocks, if any
+    #[serde(default)]
+    pub min: Option<usize>,
+    /// Maximum number of blocks, if any
+    #[serde(default)]
+    pub max: Option<usize>,
+}
+
+impl BlockCountRange {
+    /// Returns whether `count` lies within the range.
+    pub fn contains(&self, count: usize) -> bool {
+        self.min.is_none_or(|min| count >= min) && self.max.is_none_or(|max| count <= max)
+    }
+}
+
+impl fmt::Display for BlockCountRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => write!(f, "between {} and {}", min, max),
+            (Some(min), None) => write!(f, "at least {}", min),
+            (None, Some(max)) => write!(f, "at most {}", max),
+            (None, None) => write!(f, "any number of"),
+        }
+    }
+}
+
+/// A regular expression from the configuration, compiled once when it is
+/// parsed, so an invalid pattern fails as the configuration loads.
+///
+/// Serialized as the pattern string.
+#[derive(Clone)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    /// Compiles `pattern`.
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(Self)
+    }
+
+    /// Returns the compiled regular expression.
+    pub fn regex(&self) -> &Regex {
+        &self.0
+    }
+
+    /// Returns the pattern as written in the configuration.
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Self::new(&pattern)
+            .map_err(|e| serde::de::Error::custom(format!("invalid pattern '{}': {}", pattern, e)))
+    }
+}
+
+/// Ensures an absolute compiler path names an existing executable file.
+///
+/// PATH-relative names are left to the spawn-time not-found handling.
+fn check_executable(compiler: &str) -> Result<()> {
+    let path = Path::new(compiler);
+    if !path.is_absolute() {
+        return Ok(());
+    }
+
+    let metadata = std::fs::metadata(path)
+        .with_context(|| format!("Compiler path does not exist: {}", compiler))?;
+    if !metadata.is_file() {
+        anyhow::bail!("Compiler path is not an executable file: {}", compiler);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            anyhow::bail!(
+                "Compiler path is not an executable file (missing execute permission): {}",
+                compiler
+            );
+        }
+    }
+
+    Ok(())
 }
 
 fn default_true() -> bool {
     true
 }
 
+fn default_spawn_retries() -> u32 {
+    3
+}
+
+fn default_source_layout() -> Vec<SourceSegment> {
+    vec![
+        SourceSegment::Preamble,
+        SourceSegment::Propagated,
+        SourceSegment::Block,
+        SourceSegment::Postamble,
+    ]
+}
+
 impl VariantConfig {
     /// Validate the configuration for security and correctness
     pub fn validate(&self, variant_name: &str) -> Result<()> {
         // Ensure compiler path doesn't contain shell metacharacters
-        let dangerous_chars = [';', '|', '&', '`', '\n', '\r'];
-        for ch in dangerous_chars {
+        for ch in SHELL_METACHARACTERS {
             if self.compiler.contains(ch) {
                 anyhow::bail!(
                     "Variant '{}': Compiler path contains invalid character '{}': {}",
@@ -144,7 +847,8 @@ impl LanguageConfig {
     /// Get fence markers, using defaults if not specified.
     ///
     /// If the `fence_markers` field is empty, returns default fence markers
-    /// based on the language name using highlight.js aliases.
+    /// based on the language name using highlight.js aliases. The
+    /// `extra_fence_markers` are appended in either case.
     ///
     /// # Arguments
     ///
@@ -154,18 +858,31 @@ impl LanguageConfig {
     ///
     /// A vector of fence marker strings for this language.
     pub fn get_fence_markers(&self, lang_name: &str) -> Vec<String> {
-        if self.fence_markers.is_empty() {
+        let mut markers = if self.fence_markers.is_empty() {
             crate::language::get_language_metadata(lang_name).fence_markers
         } else {
             self.fence_markers.clone()
+        };
+        for marker in &self.extra_fence_markers {
+            if !markers.contains(marker) {
+                markers.push(marker.clone());
+            }
         }
+        markers
+    }
+
+    /// Returns whether a block's `flags=` attribute may pass `flag`, i.e. it
+    /// starts with one of the `allowed_block_flags` prefixes.
+    pub fn allows_block_flag(&self, flag: &str) -> bool {
+        self.allowed_block_flags
+            .iter()
+            .any(|prefix| !prefix.is_empty() && flag.starts_with(prefix.as_str()))
     }
 
     /// Validate the configuration for security and correctness
     pub fn validate(&self) -> Result<()> {
         // Ensure compiler path doesn't contain shell metacharacters
-        let dangerous_chars = [';', '|', '&', '`', '\n', '\r'];
-        for ch in dangerous_chars {
+        for ch in SHELL_METACHARACTERS {
             if self.compiler.contains(ch) {
                 anyhow::bail!(
                     "Compiler path contains invalid character '{}': {}",
@@ -190,29 +907,136 @@ impl LanguageConfig {
 
         // Note: fence_markers can be empty - defaults will be used based on language name
 
+        // Ensure the WASM backend knows which module to run, and keeps it
+        // away from the host
+        if self.backend == Backend::Wasm {
+            if self.module.is_none() {
+                anyhow::bail!("The wasm backend requires a 'module' path");
+            }
+            if let Some(flag) = wasm_host_access_flag(&self.flags) {
+                anyhow::bail!(
+                    "The wasm backend does not allow flag '{}', which gives the module access to the host",
+                    flag
+                );
+            }
+        }
+
+        if self.file_extension.is_some() && self.file_name.is_some() {
+            anyhow::bail!("file_extension and file_name cannot both be set");
+        }
+        if let Some(ref file_name) = self.file_name {
+            if Path::new(file_name).file_name() != Some(std::ffi::OsStr::new(file_name)) {
+                anyhow::bail!("file_name must be a plain file name: {}", file_name);
+            }
+        }
+        if let Some(ref extension) = self.file_extension {
+            if extension.contains(['/', '\\']) {
+                anyhow::bail!(
+                    "file_extension cannot contain path separators: {}",
+                    extension
+                );
+            }
+        }
+
+        if self.stdin && (self.backend == Backend::Wasm || self.mode != CheckMode::Compile) {
+            anyhow::bail!("stdin requires mode = \"compile\" and the native backend");
+        }
+
+        if let Some(ref run_command) = self.run_command {
+            if run_command.is_empty() {
+                anyhow::bail!("run_command cannot be empty");
+            }
+            if self.backend == Backend::Wasm || self.mode != CheckMode::Compile {
+                anyhow::bail!("run_command requires mode = \"compile\" and the host backend");
+            }
+        }
+
+        // Ensure every segment appears at most once and the block is included
+        for (i, segment) in self.source_layout.iter().enumerate() {
+            if self.source_layout[..i].contains(segment) {
+                anyhow::bail!("source_layout lists {:?} more than once", segment);
+            }
+        }
+        if !self.source_layout.contains(&SourceSegment::Block) {
+            anyhow::bail!("source_layout must include \"block\"");
+        }
+
+        // Catch absolute compiler paths that cannot be executed before spawning
+        if self.enabled {
+            check_executable(&self.compiler)?;
+            for (variant_name, variant) in &self.variants {
+                check_executable(&variant.compiler)
+                    .with_context(|| format!("Variant '{}'", variant_name))?;
+            }
+        }
+
+        if let Some(BlockCountRange {
+            min: Some(min),
+            max: Some(max),
+        }) = self.expected_blocks
+        {
+            if min > max {
+                anyhow::bail!(
+                    "expected_blocks min ({}) is greater than max ({})",
+                    min,
+                    max
+                );
+            }
+        }
+
         Ok(())
     }
 }
 
 impl CheckCodeConfig {
     /// Parse configuration from mdbook PreprocessorContext and expand environment variables
+    ///
+    /// The configuration is read from the `[preprocessor.<name>]` table, where
+    /// `name` is the preprocessor name (normally `check-code`).
     pub fn from_preprocessor_context(
         ctx: &mdbook::preprocess::PreprocessorContext,
+        name: &str,
     ) -> Result<Self> {
+        Self::from_book_config(&ctx.config, &ctx.root, name)
+    }
+
+    /// Parse configuration from a loaded `book.toml` whose book root is
+    /// `root`, exactly as [`Self::from_preprocessor_context`] does
+    pub fn from_book_config(book_config: &mdbook::Config, root: &Path, name: &str) -> Result<Self> {
+        let config = Self::load(book_config, root, name)?;
+        config.validate_fence_markers()?;
+        Ok(config)
+    }
+
+    /// Like [`Self::from_book_config`], but accepts fence markers claimed by
+    /// several languages so tooling can report every collision (see
+    /// [`Self::fence_marker_collisions`]).
+    pub fn load(book_config: &mdbook::Config, root: &Path, name: &str) -> Result<Self> {
         // Try to get our preprocessor's configuration
         let mut config: CheckCodeConfig =
-            if let Some(config_value) = ctx.config.get("preprocessor.check-code") {
+            if let Some(config_value) = book_config.get(&format!("preprocessor.{}", name)) {
                 config_value.clone().try_into()?
             } else {
                 Self::default()
             };
 
+        for flag in config.defaults.flags.iter_mut() {
+            *flag = expand_env_vars(flag);
+        }
+        for value in config.defaults.env.values_mut() {
+            *value = expand_env_vars(value);
+        }
+
         // Expand environment variables in all language configs and validate
         for (name, lang_config) in config.languages.iter_mut() {
             lang_config.compiler = expand_env_vars(&lang_config.compiler);
             for flag in lang_config.flags.iter_mut() {
                 *flag = expand_env_vars(flag);
             }
+            for value in lang_config.env.values_mut() {
+                *value = expand_env_vars(value);
+            }
+            config.defaults.apply(lang_config);
 
             // Expand environment variables in all variant configs and validate
             for (variant_name, variant_config) in lang_config.variants.iter_mut() {
@@ -220,6 +1044,9 @@ impl CheckCodeConfig {
                 for flag in variant_config.flags.iter_mut() {
                     *flag = expand_env_vars(flag);
                 }
+                for value in variant_config.env.values_mut() {
+                    *value = expand_env_vars(value);
+                }
 
                 // Validate the variant configuration for security
                 variant_config.validate(variant_name).with_context(|| {
@@ -228,6 +1055,40 @@ impl CheckCodeConfig {
                         name, variant_name
                     )
                 })?;
+                if lang_config.backend == Backend::Wasm {
+                    if let Some(flag) = wasm_host_access_flag(&variant_config.flags) {
+                        anyhow::bail!(
+                            "Invalid configuration for language '{}' variant '{}': the wasm backend does not allow flag '{}', which gives the module access to the host",
+                            name,
+                            variant_name,
+                            flag
+                        );
+                    }
+                }
+
+                load_preamble_file(
+                    &mut variant_config.preamble,
+                    variant_config.preamble_file.as_deref(),
+                    root,
+                )
+                .with_context(|| {
+                    format!(
+                        "Invalid configuration for language '{}' variant '{}'",
+                        name, variant_name
+                    )
+                })?;
+            }
+
+            load_preamble_file(
+                &mut lang_config.preamble,
+                lang_config.preamble_file.as_deref(),
+                root,
+            )
+            .with_context(|| format!("Invalid configuration for language '{}'", name))?;
+
+            if let Some(module) = lang_config.module.as_mut() {
+                resolve_module(module, root)
+                    .with_context(|| format!("Invalid configuration for language '{}'", name))?;
             }
 
             // Validate the configuration for security
@@ -236,17 +1097,246 @@ impl CheckCodeConfig {
                 .with_context(|| format!("Invalid configuration for language '{}'", name))?;
         }
 
+        config.validate_patterns()?;
+        config.validate_features()?;
+
         Ok(config)
     }
 
+    /// Reject settings that need a cargo feature this build lacks, so they
+    /// fail when the configuration loads rather than after every block has
+    /// compiled.
+    pub fn validate_features(&self) -> Result<()> {
+        if let Some(ref sqlite_path) = self.sqlite_path {
+            if !cfg!(feature = "sqlite") {
+                anyhow::bail!(
+                    "sqlite_path is set to {} but mdbook-check-code was built without the `sqlite` feature",
+                    sqlite_path.display()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validate book-wide pattern fields so malformed patterns fail when the
+    /// configuration loads rather than mid-build.
+    ///
+    /// Per-language patterns are [`Pattern`]s, which are compiled when they
+    /// are parsed.
+    pub fn validate_patterns(&self) -> Result<()> {
+        if let Some(ref reference) = self.tangle_reference {
+            crate::tangle::Tangler::new(reference).context("Invalid tangle_reference")?;
+        }
+
+        Ok(())
+    }
+
+    /// Ensure no fence marker is claimed by more than one enabled language or variant.
+    ///
+    /// A marker owned by two languages (or a language and a variant) would make
+    /// fence resolution depend on map iteration order, so collisions are rejected
+    /// with the marker and both owners named.
+    pub fn validate_fence_markers(&self) -> Result<()> {
+        match self.fence_marker_collisions().into_iter().next() {
+            Some(collision) => anyhow::bail!(collision),
+            None => Ok(()),
+        }
+    }
+
+    /// Describes every fence marker claimed by more than one enabled language
+    /// or variant, naming the marker and both owners.
+    pub fn fence_marker_collisions(&self) -> Vec<String> {
+        let mut collisions = Vec::new();
+        let mut owners: BTreeMap<String, String> = BTreeMap::new();
+        let mut languages: Vec<_> = self.languages.iter().filter(|(_, c)| c.enabled).collect();
+        languages.sort_by_key(|(name, _)| *name);
+
+        for (name, lang_config) in languages {
+            let mut claims: Vec<(String, String)> = lang_config
+                .get_fence_markers(name)
+                .into_iter()
+                .map(|marker| (marker, format!("language '{}'", name)))
+                .collect();
+
+            let mut variants: Vec<_> = lang_config.variants.iter().collect();
+            variants.sort_by_key(|(variant_name, _)| *variant_name);
+            for (variant_name, variant_config) in variants {
+                claims.extend(variant_config.fence_markers.iter().map(|marker| {
+                    (
+                        marker.clone(),
+                        format!("language '{}' variant '{}'", name, variant_name),
+                    )
+                }));
+            }
+
+            for (marker, owner) in claims {
+                match owners.get(&marker) {
+                    Some(existing) if *existing != owner => collisions.push(format!(
+                        "Fence marker '{}' is claimed by both {} and {}",
+                        marker, existing, owner
+                    )),
+                    Some(_) => {}
+                    None => {
+                        owners.insert(marker, owner);
+                    }
+                }
+            }
+        }
+
+        collisions
+    }
+
+    /// Finds problems that loading the configuration does not reject but
+    /// that would make builds fail, for the `validate` command.
+    ///
+    /// For each enabled language and variant this reports compilers that are
+    /// not found (on `PATH` for bare names), `${VAR}` references left
+    /// unexpanded in the compiler, flags, or `env`, and empty fence markers.
+    /// Returns one message per problem, sorted by language.
+    #[allow(dead_code)] // Used by CLI binary
+    pub fn problems(&self) -> Vec<String> {
+        let mut languages: Vec<_> = self.languages.iter().filter(|(_, c)| c.enabled).collect();
+        languages.sort_by_key(|(name, _)| *name);
+
+        let mut problems = Vec::new();
+        for (name, lang_config) in languages {
+            let mut variants: Vec<_> = lang_config.variants.iter().collect();
+            variants.sort_by_key(|(variant_name, _)| *variant_name);
+
+            let owners = std::iter::once((
+                format!("Language '{}'", name),
+                &lang_config.compiler,
+                &lang_config.flags,
+                &lang_config.env,
+                &lang_config.fence_markers,
+            ))
+            .chain(variants.into_iter().map(|(variant_name, v)| {
+                (
+                    format!("Language '{}' variant '{}'", name, variant_name),
+                    &v.compiler,
+                    &v.flags,
+                    &v.env,
+                    &v.fence_markers,
+                )
+            }));
+
+            for (owner, compiler, flags, env, fence_markers) in owners {
+                // An unexpanded compiler is reported below instead
+                if !compiler.contains("${") && !crate::language::compiler_exists(compiler) {
+                    problems.push(format!("{}: compiler not found: {}", owner, compiler));
+                }
+
+                let unexpanded = std::iter::once(compiler)
+                    .chain(flags)
+                    .chain(env.values())
+                    .filter(|value| value.contains("${"));
+                for value in unexpanded {
+                    problems.push(format!(
+                        "{}: unexpanded environment variable in '{}'",
+                        owner, value
+                    ));
+                }
+
+                if fence_markers.iter().any(|marker| marker.trim().is_empty()) {
+                    problems.push(format!("{}: empty fence marker", owner));
+                }
+            }
+
+            if lang_config
+                .extra_fence_markers
+                .iter()
+                .any(|marker| marker.trim().is_empty())
+            {
+                problems.push(format!("Language '{}': empty fence marker", name));
+            }
+        }
+        problems
+    }
+
     /// Get all configured languages
     pub fn languages(&self) -> &HashMap<String, LanguageConfig> {
         &self.languages
     }
+
+    /// Returns how many failures to report in full, or None for all of them.
+    pub fn max_reported_errors(&self) -> Option<usize> {
+        match self.max_reported_errors {
+            Some(0) => None,
+            Some(max) => Some(max),
+            None => Some(DEFAULT_MAX_REPORTED_ERRORS),
+        }
+    }
+
+    /// Returns whether blocks with this fence marker are prose-only and must
+    /// never be compiled.
+    pub fn is_prose_marker(&self, fence: &str) -> bool {
+        match &self.prose_markers {
+            Some(markers) => markers.iter().any(|m| m == fence),
+            None => DEFAULT_PROSE_MARKERS.contains(&fence),
+        }
+    }
+
+    /// Returns whether code blocks should be checked when building for
+    /// `renderer`.
+    pub fn checks_renderer(&self, renderer: &str) -> bool {
+        match &self.renderers {
+            Some(renderers) => renderers.iter().any(|r| r == renderer),
+            None => DEFAULT_RENDERERS.contains(&renderer),
+        }
+    }
+}
+
+/// Resolves the WASM `module` against the book root.
+///
+/// # Errors
+///
+/// Returns an error if `module` is absolute or contains `..`, since the
+/// module must live inside the book.
+fn resolve_module(module: &mut PathBuf, root: &Path) -> Result<()> {
+    if module.is_absolute()
+        || module
+            .components()
+            .any(|component| matches!(component, std::path::Component::ParentDir))
+    {
+        anyhow::bail!(
+            "module must be a relative path inside the book: {}",
+            module.display()
+        );
+    }
+    *module = root.join(&*module);
+    Ok(())
+}
+
+/// Replaces `preamble` with the contents of `preamble_file`, resolved
+/// against the book root, if one is configured.
+///
+/// # Errors
+///
+/// Returns an error if both a preamble and a preamble file are set, or if
+/// the file cannot be read.
+fn load_preamble_file(
+    preamble: &mut Option<String>,
+    preamble_file: Option<&Path>,
+    root: &Path,
+) -> Result<()> {
+    let Some(preamble_file) = preamble_file else {
+        return Ok(());
+    };
+    if preamble.is_some() {
+        anyhow::bail!("'preamble' and 'preamble_file' cannot both be set");
+    }
+
+    let path = root.join(preamble_file);
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read preamble_file: {}", path.display()))?;
+    *preamble = Some(contents);
+    Ok(())
 }
 
 /// Expand environment variables in a string
-/// Supports ${VAR_NAME} syntax
+/// Supports ${VAR_NAME} syntax, and ${VAR_NAME:-default} to substitute
+/// `default` when the variable is unset or empty (as in POSIX shells)
 /// This function processes the string in a single pass to avoid re-processing expanded values
 fn expand_env_vars(s: &str) -> String {
     let mut result = String::with_capacity(s.len());
@@ -269,10 +1359,16 @@ fn expand_env_vars(s: &str) -> String {
             }
 
             if found_close {
-                // Try to expand the variable
-                match env::var(&var_name) {
-                    Ok(value) => result.push_str(&value),
-                    Err(_) => {
+                // Try to expand the variable, falling back to its default
+                let (name, default) = match var_name.split_once(":-") {
+                    Some((name, default)) => (name, Some(default)),
+                    None => (var_name.as_str(), None),
+                };
+                match (env::var(name), default) {
+                    (Ok(value), Some(default)) if value.is_empty() => result.push_str(default),
+                    (Ok(value), _) => result.push_str(&value),
+                    (Err(_), Some(default)) => result.push_str(default),
+                    (Err(_), None) => {
                         log::warn!(
                             "Environment variable '{}' not found, leaving unexpanded",
                             var_name
@@ -314,6 +1410,449 @@ mod tests {
         assert_eq!(result, "${NONEXISTENT_VAR}");
     }
 
+    #[test]
+    fn test_expand_env_vars_with_default() {
+        env::set_var("TEST_DEFAULT_CLANG", "/opt/clang");
+        assert_eq!(
+            expand_env_vars("${TEST_DEFAULT_CLANG:-clang}"),
+            "/opt/clang"
+        );
+        env::remove_var("TEST_DEFAULT_CLANG");
+
+        env::remove_var("TEST_DEFAULT_MISSING");
+        assert_eq!(
+            expand_env_vars("${TEST_DEFAULT_MISSING:-clang} -O2"),
+            "clang -O2"
+        );
+        assert_eq!(expand_env_vars("${TEST_DEFAULT_MISSING:-}"), "");
+    }
+
+    #[test]
+    fn test_defaults_applied_overridden_and_extended() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [defaults]
+            flags = ["-Wall", "-Werror"]
+            env = { LANG = "C", TMPDIR = "/tmp" }
+            timeout_seconds = 30
+
+            [languages.c]
+            compiler = "gcc"
+
+            [languages.cpp]
+            compiler = "g++"
+            flags = ["-std=c++20"]
+            env = { TMPDIR = "/var/tmp" }
+            timeout_seconds = 120
+            "#,
+        )
+        .unwrap();
+
+        let mut c = config.languages["c"].clone();
+        config.defaults.apply(&mut c);
+        assert_eq!(c.flags, ["-Wall", "-Werror"]);
+        assert_eq!(c.env["TMPDIR"], "/tmp");
+        assert_eq!(c.timeout_seconds, Some(30));
+
+        let mut cpp = config.languages["cpp"].clone();
+        config.defaults.apply(&mut cpp);
+        assert_eq!(cpp.flags, ["-Wall", "-Werror", "-std=c++20"]);
+        assert_eq!(cpp.env["LANG"], "C");
+        assert_eq!(cpp.env["TMPDIR"], "/var/tmp");
+        assert_eq!(cpp.timeout_seconds, Some(120));
+    }
+
+    #[test]
+    fn test_problems() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "sh"
+            flags = ["-I${MISSING_INCLUDE_DIR}"]
+
+            [languages.c.variants.parasol]
+            compiler = "definitely-not-a-compiler"
+            fence_markers = [""]
+
+            [languages.solidity]
+            enabled = false
+            compiler = "solc-missing"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.problems(),
+            [
+                "Language 'c': unexpanded environment variable in '-I${MISSING_INCLUDE_DIR}'",
+                "Language 'c' variant 'parasol': compiler not found: definitely-not-a-compiler",
+                "Language 'c' variant 'parasol': empty fence marker",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_preamble_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("prelude.h"), "typedef int word;\n").unwrap();
+
+        let mut preamble = None;
+        load_preamble_file(&mut preamble, Some(Path::new("prelude.h")), dir.path()).unwrap();
+        assert_eq!(preamble.as_deref(), Some("typedef int word;\n"));
+
+        let err = load_preamble_file(&mut preamble, Some(Path::new("prelude.h")), dir.path())
+            .unwrap_err();
+        assert!(err.to_string().contains("cannot both be set"));
+
+        let mut preamble = None;
+        assert!(
+            load_preamble_file(&mut preamble, Some(Path::new("missing.h")), dir.path()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_wasm_backend_rejects_host_access() {
+        let config = |flags: &str| -> LanguageConfig {
+            toml::from_str(&format!(
+                "compiler = \"true\"\nbackend = \"wasm\"\nmodule = \"check.wasm\"\nflags = {}",
+                flags
+            ))
+            .unwrap()
+        };
+        assert!(config(r#"["--fuel=1000"]"#).validate().is_ok());
+        for flags in [
+            r#"["--dir", "/"]"#,
+            r#"["--dir=/"]"#,
+            r#"["--mapdir=/host::/"]"#,
+            r#"["-S", "inherit-network"]"#,
+            r#"["-Sinherit-network"]"#,
+            r#"["--env=HOME"]"#,
+        ] {
+            let err = config(flags).validate().unwrap_err();
+            assert!(err.to_string().contains("access to the host"), "{}", flags);
+        }
+        // The native backend passes flags through
+        let mut native = config(r#"["--dir=/"]"#);
+        native.backend = Backend::Native;
+        assert!(native.validate().is_ok());
+
+        let root = Path::new("/book");
+        let mut module = PathBuf::from("validators/check.wasm");
+        resolve_module(&mut module, root).unwrap();
+        assert_eq!(module, Path::new("/book/validators/check.wasm"));
+        for escaping in ["/usr/lib/check.wasm", "../check.wasm", "a/../../check.wasm"] {
+            assert!(resolve_module(&mut PathBuf::from(escaping), root).is_err());
+        }
+    }
+
+    #[test]
+    fn test_invalid_pattern_rejected_at_parse() {
+        let err = toml::from_str::<LanguageConfig>(
+            r#"
+            compiler = "gcc"
+            forbidden_patterns = ["eval\\("]
+            required_patterns = ["("]
+            "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("invalid pattern '('"), "{}", err);
+
+        let config: LanguageConfig = toml::from_str(
+            r#"
+            compiler = "gcc"
+            forbidden_patterns = ["eval\\("]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.forbidden_patterns[0].as_str(), "eval\\(");
+        assert_eq!(
+            serde_json::to_value(&config).unwrap()["forbidden_patterns"],
+            serde_json::json!(["eval\\("])
+        );
+    }
+
+    #[test]
+    fn test_validate_patterns_rejects_invalid_tangle_reference() {
+        assert!(CheckCodeConfig::default().validate_patterns().is_ok());
+
+        let config: CheckCodeConfig = toml::from_str(r#"tangle_reference = "<<(\\w+>>""#).unwrap();
+        let err = config.validate_patterns().unwrap_err();
+        assert!(format!("{:#}", err).contains("Invalid tangle_reference"));
+    }
+
+    #[test]
+    fn test_validate_source_layout() {
+        let parse = |layout: &str| -> LanguageConfig {
+            toml::from_str(&format!("compiler = \"gcc\"\nsource_layout = {}", layout)).unwrap()
+        };
+
+        assert!(parse(r#"["block", "propagated"]"#).validate().is_ok());
+        assert!(parse(r#"["preamble", "propagated"]"#).validate().is_err());
+        assert!(parse(r#"["block", "preamble", "block"]"#)
+            .validate()
+            .is_err());
+        assert!(toml::from_str::<LanguageConfig>(
+            "compiler = \"gcc\"\nsource_layout = [\"footer\"]"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_max_reported_errors() {
+        assert_eq!(
+            CheckCodeConfig::default().max_reported_errors(),
+            Some(DEFAULT_MAX_REPORTED_ERRORS)
+        );
+
+        let config: CheckCodeConfig = toml::from_str("max_reported_errors = 3").unwrap();
+        assert_eq!(config.max_reported_errors(), Some(3));
+
+        let config: CheckCodeConfig = toml::from_str("max_reported_errors = 0").unwrap();
+        assert_eq!(config.max_reported_errors(), None);
+    }
+
+    #[test]
+    fn test_renderers_default_and_override() {
+        let config = CheckCodeConfig::default();
+        assert!(config.checks_renderer("html"));
+        assert!(!config.checks_renderer("markdown"));
+
+        let config: CheckCodeConfig = toml::from_str(r#"renderers = ["html", "epub"]"#).unwrap();
+        assert!(config.checks_renderer("epub"));
+        assert!(!config.checks_renderer("linkcheck"));
+    }
+
+    #[test]
+    fn test_prose_markers_default_and_override() {
+        let config = CheckCodeConfig::default();
+        assert!(config.is_prose_marker("mermaid"));
+        assert!(config.is_prose_marker("diff"));
+        assert!(!config.is_prose_marker("c"));
+
+        let config: CheckCodeConfig = toml::from_str(r#"prose_markers = ["graphviz"]"#).unwrap();
+        assert!(config.is_prose_marker("graphviz"));
+        assert!(!config.is_prose_marker("diff"));
+    }
+
+    #[test]
+    fn test_on_failure_return_parsing() {
+        assert_eq!(CheckCodeConfig::default().on_failure_return, None);
+
+        let config: CheckCodeConfig = toml::from_str(r#"on_failure_return = "original""#).unwrap();
+        assert_eq!(config.on_failure_return, Some(FailureReturn::Original));
+
+        let config: CheckCodeConfig = toml::from_str(r#"on_failure_return = "processed""#).unwrap();
+        assert_eq!(config.on_failure_return, Some(FailureReturn::Processed));
+
+        assert!(toml::from_str::<CheckCodeConfig>(r#"on_failure_return = "other""#).is_err());
+    }
+
+    #[test]
+    fn test_sqlite_path_requires_feature() {
+        assert!(CheckCodeConfig::default().validate_features().is_ok());
+
+        let config: CheckCodeConfig = toml::from_str(r#"sqlite_path = "results.db""#).unwrap();
+        assert_eq!(config.validate_features().is_ok(), cfg!(feature = "sqlite"));
+    }
+
+    #[test]
+    fn test_junit_file_alias() {
+        let config: CheckCodeConfig = toml::from_str(r#"junit_file = "junit.xml""#).unwrap();
+        assert_eq!(config.junit_path, Some(PathBuf::from("junit.xml")));
+    }
+
+    #[test]
+    fn test_parallel_jobs_round_trip() {
+        assert_eq!(CheckCodeConfig::default().parallel_jobs, None);
+
+        let config: CheckCodeConfig = toml::from_str("parallel_jobs = 4").unwrap();
+        assert_eq!(config.parallel_jobs, Some(4));
+
+        let serialized = toml::to_string(&config).unwrap();
+        let config: CheckCodeConfig = toml::from_str(&serialized).unwrap();
+        assert_eq!(config.parallel_jobs, Some(4));
+    }
+
+    #[test]
+    fn test_empty_block_policy_parsing() {
+        assert_eq!(
+            CheckCodeConfig::default().empty_block,
+            EmptyBlockPolicy::Skip
+        );
+
+        let config: CheckCodeConfig = toml::from_str(r#"empty_block = "error""#).unwrap();
+        assert_eq!(config.empty_block, EmptyBlockPolicy::Error);
+
+        let config: CheckCodeConfig = toml::from_str(r#"empty_block = "compile""#).unwrap();
+        assert_eq!(config.empty_block, EmptyBlockPolicy::Compile);
+    }
+
+    #[test]
+    fn test_verbosity_parsing() {
+        assert_eq!(CheckCodeConfig::default().verbosity, Verbosity::Normal);
+
+        let config: CheckCodeConfig = toml::from_str(r#"verbosity = "quiet""#).unwrap();
+        assert_eq!(config.verbosity, Verbosity::Quiet);
+
+        let config: CheckCodeConfig = toml::from_str(r#"verbosity = "verbose""#).unwrap();
+        assert_eq!(config.verbosity, Verbosity::Verbose);
+
+        assert!(toml::from_str::<CheckCodeConfig>(r#"verbosity = "loud""#).is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_non_executable_compiler() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config_for = |compiler: &Path| -> LanguageConfig {
+            toml::from_str(&format!("compiler = {:?}", compiler.display().to_string())).unwrap()
+        };
+
+        let err = config_for(temp_dir.path()).validate().unwrap_err();
+        assert!(err.to_string().contains("not an executable file"));
+
+        let plain_file = temp_dir.path().join("not-a-compiler");
+        std::fs::write(&plain_file, "").unwrap();
+        #[cfg(unix)]
+        {
+            let err = config_for(&plain_file).validate().unwrap_err();
+            assert!(err.to_string().contains("not an executable file"));
+        }
+
+        let mut disabled = config_for(temp_dir.path());
+        disabled.enabled = false;
+        assert!(disabled.validate().is_ok());
+
+        // PATH-relative names are resolved when spawning
+        assert!(config_for(Path::new("gcc")).validate().is_ok());
+    }
+
+    #[test]
+    fn test_block_count_range() {
+        let range: BlockCountRange = toml::from_str("min = 2\nmax = 4").unwrap();
+        assert!(!range.contains(1));
+        assert!(range.contains(2));
+        assert!(range.contains(4));
+        assert!(!range.contains(5));
+        assert_eq!(range.to_string(), "between 2 and 4");
+
+        let range: BlockCountRange = toml::from_str("min = 1").unwrap();
+        assert!(!range.contains(0));
+        assert!(range.contains(100));
+
+        let config: LanguageConfig = toml::from_str(
+            r#"
+            compiler = "gcc"
+            expected_blocks = { min = 3, max = 1 }
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_extra_fence_markers_extend_defaults() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "gcc"
+            extra_fence_markers = ["parasol-c"]
+
+            [languages.cpp]
+            compiler = "g++"
+            fence_markers = ["cpp"]
+            extra_fence_markers = ["parasol-cpp"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.languages["c"].get_fence_markers("c"),
+            vec!["c", "h", "parasol-c"]
+        );
+        assert_eq!(
+            config.languages["cpp"].get_fence_markers("cpp"),
+            vec!["cpp", "parasol-cpp"]
+        );
+
+        let registry = crate::language::LanguageRegistry::from_config(&config).unwrap();
+        for marker in ["parasol-c", "c", "h"] {
+            let language = registry.find_by_fence(marker, None).unwrap();
+            assert_eq!(language.to_string(), "c");
+        }
+        assert!(registry.find_by_fence("hpp", None).is_err());
+    }
+
+    #[test]
+    fn test_variant_fence_marker_collision_rejected() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "gcc"
+
+            [languages.c.variants.parasol]
+            compiler = "clang"
+            fence_markers = ["fhe"]
+
+            [languages.cpp]
+            compiler = "g++"
+
+            [languages.cpp.variants.parasol]
+            compiler = "clang++"
+            fence_markers = ["fhe"]
+            "#,
+        )
+        .unwrap();
+
+        let err = config.validate_fence_markers().unwrap_err().to_string();
+        assert!(err.contains("'fhe'"), "{}", err);
+        assert!(err.contains("language 'c' variant 'parasol'"), "{}", err);
+        assert!(err.contains("language 'cpp' variant 'parasol'"), "{}", err);
+    }
+
+    #[test]
+    fn test_variant_fence_marker_colliding_with_base_rejected() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "gcc"
+
+            [languages.c.variants.parasol]
+            compiler = "clang"
+            fence_markers = ["h"]
+            "#,
+        )
+        .unwrap();
+
+        let err = config.validate_fence_markers().unwrap_err().to_string();
+        assert!(err.contains("language 'c' and language 'c' variant 'parasol'"));
+    }
+
+    #[test]
+    fn test_fence_marker_collisions_lists_all() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "gcc"
+
+            [languages.cpp]
+            compiler = "g++"
+            fence_markers = ["cpp", "h"]
+
+            [languages.objc]
+            compiler = "clang"
+            fence_markers = ["h"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.fence_marker_collisions(),
+            [
+                "Fence marker 'h' is claimed by both language 'c' and language 'cpp'",
+                "Fence marker 'h' is claimed by both language 'c' and language 'objc'",
+            ]
+        );
+    }
+
     #[test]
     fn test_expand_env_vars_no_expansion() {
         let result = expand_env_vars("/usr/bin/gcc");
@@ -0,0 +1,64 @@
+/// Produces a line-oriented diff between `old` and `new`.
+///
+/// Unchanged lines are prefixed with two spaces, removed lines with `- ` and
+/// added lines with `+ `. The diff is computed from the longest common
+/// subsequence of lines, which is adequate for code-block sized inputs.
+///
+/// # Example
+///
+/// ```ignore
+/// let diff = line_diff("a\nb\n", "a\nc\n");
+/// assert_eq!(diff, "  a\n- b\n+ c\n");
+/// ```
+pub fn line_diff(old: &str, new: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    // lcs[i][j] = length of the LCS of old_lines[i..] and new_lines[j..]
+    let mut lcs = vec![vec![0usize; new_lines.len() + 1]; old_lines.len() + 1];
+    for i in (0..old_lines.len()).rev() {
+        for j in (0..new_lines.len()).rev() {
+            lcs[i][j] = if old_lines[i] == new_lines[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old_lines.len() && j < new_lines.len() {
+        if old_lines[i] == new_lines[j] {
+            diff.push_str(&format!("  {}\n", old_lines[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            diff.push_str(&format!("- {}\n", old_lines[i]));
+            i += 1;
+        } else {
+            diff.push_str(&format!("+ {}\n", new_lines[j]));
+            j += 1;
+        }
+    }
+    for line in &old_lines[i..] {
+        diff.push_str(&format!("- {}\n", line));
+    }
+    for line in &new_lines[j..] {
+        diff.push_str(&format!("+ {}\n", line));
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_diff() {
+        assert_eq!(line_diff("a\nb\n", "a\nc\n"), "  a\n- b\n+ c\n");
+        assert_eq!(line_diff("a\n", "a\nb\n"), "  a\n+ b\n");
+        assert_eq!(line_diff("same\n", "same\n"), "  same\n");
+    }
+}
@@ -1,8 +1,11 @@
 use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag, TagEnd};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 
 /// A code block extracted from markdown with its metadata.
 ///
-/// Code blocks are identified by fenced code syntax in markdown:
+/// Code blocks are identified by fenced code syntax in markdown (indented
+/// blocks are included when [`ExtractOptions::default_indented_language`] is set):
 ///
 /// ````markdown
 /// ```c
@@ -15,8 +18,34 @@ use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag, TagEnd};
 /// Code blocks can have comma-separated attributes in the fence info string:
 ///
 /// - `ignore` - Skip compilation for this block
-/// - `propagate` - Make code available to subsequent blocks in the same file
+/// - `ignore=<reason>` - Like `ignore`, recording why the block is skipped.
+///   Quote the reason if it contains spaces or commas
+/// - `propagate` - Make code available to subsequent blocks of the same language
+///   and variant in the same file
 /// - `variant=<name>` - Use a specific variant of the language
+/// - `name=<chunk>` - Name the block as a chunk for tangle mode
+/// - `file=<name>` - Write the block to its own file `<name>` (e.g. `point.h`)
+///   and compile it together with the neighbouring `file=` blocks as one
+///   multi-file compilation unit
+/// - `should_fail` - The block must fail to compile; compiling cleanly is an error
+/// - `expect_error="<text>"` - Like `should_fail`, and the compiler output must
+///   also contain `<text>`. Quote the text if it contains commas
+/// - `no_run` - In run mode, only compile the block and never execute it
+/// - `flags="<flags>"` - Extra compiler flags for this block only, separated
+///   by whitespace (a flag cannot contain spaces) and appended to the
+///   language's configured flags, if its `allowed_block_flags` permits them
+/// - `expect_output="<text>"` - In run mode, the program's stdout must equal
+///   `<text>` (see below)
+///
+/// Quoted values may contain commas, and `\n` inside them stands for a line
+/// break, so `expect_output="1\n2"` expects two lines of output.
+///
+/// # Hidden Lines
+///
+/// For fence markers listed in [`ExtractOptions::hidden_line_fences`], lines
+/// starting with `# ` (or consisting of a lone `#`) are compiled but hidden
+/// from the rendered book, and `##` escapes a literal `#`. See
+/// [`split_hidden_lines`].
 ///
 /// # Example
 ///
@@ -30,18 +59,66 @@ use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag, TagEnd};
 /// [[clang::fhe_program]] uint8_t add(uint8_t a, uint8_t b) { return a + b; }
 /// ```
 /// ````
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct CodeBlock {
+    /// Zero-based position of the block among all code blocks in the file,
+    /// counting ignored blocks
+    pub index: usize,
     /// The programming language from the fence marker (e.g., "c", "typescript", "rust")
     pub language: String,
-    /// The actual code content
-    pub code: String,
+    /// The code as shown in the rendered book, with hidden lines removed
+    #[allow(dead_code)] // Public API; the rendered book itself is rewritten by rewrite_fences
+    pub display_code: String,
+    /// The code sent to the compiler, including hidden lines
+    pub compile_code: String,
     /// Whether this block should be ignored (skipped during compilation)
     pub ignore: bool,
+    /// Why the block is ignored, from an `ignore=` attribute (implies `ignore`)
+    pub ignore_reason: Option<String>,
     /// Whether this block's code should be propagated to subsequent blocks
     pub propagate: bool,
+    /// Whether this block is expected to fail compilation
+    pub should_fail: bool,
+    /// Text the compiler output must contain when the block fails, from an
+    /// `expect_error=` attribute (implies `should_fail`)
+    pub expect_error: Option<String>,
+    /// Expected stdout of the block's program in run mode, from an
+    /// `expect_output=` attribute
+    pub expect_output: Option<String>,
+    /// Whether run mode should skip executing this block
+    pub no_run: bool,
+    /// Extra compiler flags for this block, from a `flags=` attribute
+    pub flags: Vec<String>,
     /// The variant of the language to use (e.g., "parasol" for C)
     pub variant: Option<String>,
+    /// Chunk name from a `name=<chunk>` attribute, used by tangle mode
+    pub name: Option<String>,
+    /// File name from a `file=<name>` attribute, making the block part of a
+    /// multi-file compilation unit
+    pub file: Option<String>,
+    /// Text of the closest heading preceding this block, if any
+    pub heading: Option<String>,
+    /// One-based line of the block's opening fence in the chapter source
+    /// (for indented blocks, of their first line)
+    pub line: usize,
+}
+
+impl CodeBlock {
+    /// Identity under which propagated code is shared: the fence marker and
+    /// variant. Propagated code only flows between blocks with equal keys.
+    pub fn propagation_key(&self) -> (String, Option<String>) {
+        (self.language.clone(), self.variant.clone())
+    }
+}
+
+/// Options controlling how code blocks are extracted.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    /// Fence markers whose blocks support hidden lines (see [`split_hidden_lines`])
+    pub hidden_line_fences: HashSet<String>,
+    /// Language assumed for indented code blocks, which have no info string.
+    /// If None, indented blocks are not extracted.
+    pub default_indented_language: Option<String>,
 }
 
 /// Extracts code blocks from markdown content using pulldown-cmark.
@@ -53,6 +130,7 @@ pub struct CodeBlock {
 /// # Arguments
 ///
 /// * `content` - The markdown content to parse
+/// * `options` - Extraction options such as hidden-line support
 ///
 /// # Returns
 ///
@@ -60,7 +138,9 @@ pub struct CodeBlock {
 ///
 /// # Example
 ///
-/// ````ignore
+/// ````
+/// use mdbook_check_code::{extract_code_blocks, ExtractOptions};
+///
 /// let markdown = r#"# My Code
 ///
 /// ```c
@@ -68,53 +148,186 @@ pub struct CodeBlock {
 /// ```
 /// "#;
 ///
-/// let blocks = extract_code_blocks(markdown);
+/// let blocks = extract_code_blocks(markdown, &ExtractOptions::default());
 /// assert_eq!(blocks.len(), 1);
 /// assert_eq!(blocks[0].language, "c");
 /// ````
-pub fn extract_code_blocks(content: &str) -> Vec<CodeBlock> {
+pub fn extract_code_blocks(content: &str, options: &ExtractOptions) -> Vec<CodeBlock> {
     let parser = Parser::new(content);
     let mut code_blocks = Vec::new();
     let mut in_code_block = false;
     let mut current_code = String::new();
     let mut current_language = String::new();
     let mut current_ignore = false;
+    let mut current_ignore_reason = None;
     let mut current_propagate = false;
+    let mut current_should_fail = false;
+    let mut current_expect_error = None;
+    let mut current_expect_output = None;
+    let mut current_no_run = false;
+    let mut current_flags = Vec::new();
     let mut current_variant = None;
+    let mut current_name = None;
+    let mut current_file = None;
+    let mut in_heading = false;
+    let mut heading_text = String::new();
+    let mut current_heading: Option<String> = None;
+    let mut in_html_block = false;
+    let mut html_text = String::new();
+    let mut html_line = 1;
+    let mut current_line = 1;
+
+    // Line numbers are counted incrementally, since event offsets only grow
+    let mut counted = (0, 1);
+    let mut line_at = |offset: usize| {
+        let (counted_offset, counted_line) = &mut counted;
+        if offset > *counted_offset {
+            *counted_line += content[*counted_offset..offset].matches('\n').count();
+            *counted_offset = offset;
+        }
+        *counted_line
+    };
 
-    for event in parser {
+    for (event, range) in parser.into_offset_iter() {
         match event {
             Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
                 in_code_block = true;
                 current_code.clear();
+                current_line = line_at(range.start);
 
                 // Parse the fence info string (e.g., "c", "typescript,ignore", "c,variant=parasol")
                 let info_str = info.as_ref();
                 let (lang, flags, variant) = parse_fence_info(info_str);
 
+                let has_flag = |name: &str| flags.iter().any(|flag| flag == name);
+
                 current_language = lang;
-                current_ignore = flags.contains(&"ignore");
-                current_propagate = flags.contains(&"propagate");
+                current_ignore_reason = flags
+                    .iter()
+                    .find_map(|flag| flag.strip_prefix("ignore="))
+                    .map(unquote);
+                current_ignore = has_flag("ignore") || current_ignore_reason.is_some();
+                current_propagate = has_flag("propagate");
+                current_expect_error = flags
+                    .iter()
+                    .find_map(|flag| flag.strip_prefix("expect_error="))
+                    .map(unquote);
+                current_should_fail = has_flag("should_fail") || current_expect_error.is_some();
+                current_expect_output = flags
+                    .iter()
+                    .find_map(|flag| flag.strip_prefix("expect_output="))
+                    .map(unquote);
+                current_no_run = has_flag("no_run");
+                current_flags = flags
+                    .iter()
+                    .find_map(|flag| flag.strip_prefix("flags="))
+                    .map(|value| {
+                        unquote(value)
+                            .split_whitespace()
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default();
                 current_variant = variant;
+                current_name = flags
+                    .iter()
+                    .find_map(|flag| flag.strip_prefix("name="))
+                    .map(str::to_string);
+                current_file = flags
+                    .iter()
+                    .find_map(|flag| flag.strip_prefix("file="))
+                    .map(unquote);
             }
 
-            Event::End(TagEnd::CodeBlock) => {
-                if in_code_block {
-                    code_blocks.push(CodeBlock {
-                        language: current_language.clone(),
-                        code: current_code.clone(),
-                        ignore: current_ignore,
-                        propagate: current_propagate,
-                        variant: current_variant.clone(),
-                    });
-
-                    in_code_block = false;
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Indented)) => {
+                if let Some(language) = &options.default_indented_language {
+                    in_code_block = true;
+                    current_code.clear();
+                    current_line = line_at(range.start);
+                    current_language = language.clone();
+                    current_ignore = false;
+                    current_ignore_reason = None;
+                    current_propagate = false;
+                    current_should_fail = false;
+                    current_expect_error = None;
+                    current_expect_output = None;
+                    current_no_run = false;
+                    current_flags = Vec::new();
+                    current_variant = None;
+                    current_name = None;
+                    current_file = None;
                 }
             }
 
-            Event::Text(text) => {
-                if in_code_block {
-                    current_code.push_str(&text);
+            Event::End(TagEnd::CodeBlock) if in_code_block => {
+                let (display_code, compile_code) =
+                    if options.hidden_line_fences.contains(&current_language) {
+                        split_hidden_lines(&current_code)
+                    } else {
+                        (current_code.clone(), current_code.clone())
+                    };
+
+                code_blocks.push(CodeBlock {
+                    index: code_blocks.len(),
+                    language: current_language.clone(),
+                    display_code,
+                    compile_code,
+                    ignore: current_ignore,
+                    ignore_reason: current_ignore_reason.clone(),
+                    propagate: current_propagate,
+                    should_fail: current_should_fail,
+                    expect_error: current_expect_error.clone(),
+                    expect_output: current_expect_output.clone(),
+                    no_run: current_no_run,
+                    flags: current_flags.clone(),
+                    variant: current_variant.clone(),
+                    name: current_name.clone(),
+                    file: current_file.clone(),
+                    heading: current_heading.clone(),
+                    line: current_line,
+                });
+
+                in_code_block = false;
+            }
+
+            Event::Text(text) if in_code_block => {
+                current_code.push_str(&text);
+            }
+
+            Event::Start(Tag::Heading { .. }) => {
+                in_heading = true;
+                heading_text.clear();
+            }
+
+            Event::End(TagEnd::Heading(_)) => {
+                in_heading = false;
+                current_heading = Some(heading_text.trim().to_string());
+            }
+
+            Event::Text(text) | Event::Code(text) if in_heading => {
+                heading_text.push_str(&text);
+            }
+
+            // Fences written directly inside container markup (as emitted by
+            // tabs plugins) become part of the HTML block, so recover them here
+            Event::Start(Tag::HtmlBlock) => {
+                in_html_block = true;
+                html_text.clear();
+                html_line = line_at(range.start);
+            }
+
+            Event::Html(html) if in_html_block => {
+                html_text.push_str(&html);
+            }
+
+            Event::End(TagEnd::HtmlBlock) => {
+                in_html_block = false;
+                for mut block in extract_from_html_block(&html_text, options) {
+                    if block.heading.is_none() {
+                        block.heading = current_heading.clone();
+                    }
+                    block.line += html_line - 1;
+                    code_blocks.push(block);
                 }
             }
 
@@ -122,17 +335,135 @@ pub fn extract_code_blocks(content: &str) -> Vec<CodeBlock> {
         }
     }
 
+    for (index, block) in code_blocks.iter_mut().enumerate() {
+        block.index = index;
+    }
+
     code_blocks
 }
 
+/// Extracts fenced code blocks embedded in a raw HTML block.
+///
+/// CommonMark ends an HTML block only at a blank line, so a fence placed
+/// directly after a container tag such as `<div class="tab">` is swallowed into
+/// the HTML block. Lines consisting solely of HTML tags (outside of fences) are
+/// blanked and the remainder is parsed again as markdown.
+///
+/// Fences follow CommonMark closing rules: a fence opened with backticks or
+/// tildes is only closed by a line of the same character that is at least as
+/// long as the opening run, so `` ``` `` inside a `~~~` block is content.
+///
+/// Comments and raw text elements such as `<pre>` are not markdown, so fences
+/// inside them are left alone. The result is only parsed again if a line was
+/// blanked, since unchanged text would form the same HTML block.
+fn extract_from_html_block(html: &str, options: &ExtractOptions) -> Vec<CodeBlock> {
+    if (!html.contains("```") && !html.contains("~~~")) || is_raw_html_block(html) {
+        return Vec::new();
+    }
+
+    let mut markdown = String::with_capacity(html.len());
+    let mut blanked = false;
+    let mut open_fence: Option<(char, usize)> = None;
+    for line in html.lines() {
+        let trimmed = line.trim();
+        match (open_fence, fence_run(trimmed)) {
+            (None, Some(fence)) => open_fence = Some(fence),
+            (Some((open_char, open_len)), Some((close_char, close_len)))
+                if close_char == open_char
+                    && close_len >= open_len
+                    && trimmed[close_len..].trim().is_empty() =>
+            {
+                open_fence = None;
+            }
+            _ => {}
+        }
+        if open_fence.is_none() && trimmed.starts_with('<') && trimmed.ends_with('>') {
+            markdown.push('\n');
+            blanked = true;
+        } else {
+            markdown.push_str(line);
+            markdown.push('\n');
+        }
+    }
+
+    if !blanked {
+        return Vec::new();
+    }
+    extract_code_blocks(&markdown, options)
+}
+
+/// Returns whether `html` starts a comment or a raw text element (CommonMark
+/// HTML block types 1 and 2), whose content is never parsed as markdown.
+fn is_raw_html_block(html: &str) -> bool {
+    let html = html.trim_start().to_ascii_lowercase();
+    html.starts_with("<!--")
+        || ["<pre", "<script", "<style", "<textarea"]
+            .iter()
+            .any(|tag| {
+                html.strip_prefix(tag).is_some_and(|rest| {
+                    rest.is_empty() || rest.starts_with(|c: char| c == '>' || c.is_whitespace())
+                })
+            })
+}
+
+/// Returns the fence character and run length if `line` starts with a code
+/// fence (at least three backticks or tildes).
+fn fence_run(line: &str) -> Option<(char, usize)> {
+    let fence_char = line.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let len = line.len() - line.trim_start_matches(fence_char).len();
+    (len >= 3).then_some((fence_char, len))
+}
+
+/// Splits code into its displayed and compiled forms.
+///
+/// Following mdBook's rustdoc convention, a line whose first non-whitespace
+/// characters are `# ` (or that is just `#`) is hidden: it is compiled with
+/// the marker removed but not displayed. A leading `##` is an escape for a
+/// literal `#` in both forms.
+///
+/// Returns `(display_code, compile_code)`.
+pub fn split_hidden_lines(code: &str) -> (String, String) {
+    let mut display = String::with_capacity(code.len());
+    let mut compile = String::with_capacity(code.len());
+
+    for line in code.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+
+        if trimmed.starts_with("##") {
+            let unescaped = format!("{}{}", indent, &trimmed[1..]);
+            display.push_str(&unescaped);
+            compile.push_str(&unescaped);
+        } else if let Some(hidden) = trimmed.strip_prefix("# ") {
+            compile.push_str(indent);
+            compile.push_str(hidden);
+        } else if trimmed.trim_end() == "#" {
+            compile.push_str(indent);
+            compile.push_str(&trimmed[1..]);
+        } else {
+            display.push_str(line);
+            compile.push_str(line);
+        }
+    }
+
+    (display, compile)
+}
+
 /// Parse fence info string into language, flags, and variant
 /// Examples:
 /// - "c" -> ("c", [], None)
 /// - "typescript,ignore" -> ("typescript", ["ignore"], None)
+/// - "c,ignore=\"needs network\"" -> ("c", ["ignore=\"needs network\""], None)
 /// - "c,variant=parasol" -> ("c", [], Some("parasol"))
 /// - "c,propagate,variant=parasol" -> ("c", ["propagate"], Some("parasol"))
-fn parse_fence_info(info: &str) -> (String, Vec<&str>, Option<String>) {
-    let parts: Vec<&str> = info.split(',').map(|s| s.trim()).collect();
+///
+/// Attributes may appear in any order, quoted values may contain commas, and
+/// whitespace around `=` is ignored, so `variant = parasol` is `variant=parasol`.
+///
+/// The info string is the same for backtick and tilde fences, so `~~~c,ignore`
+/// parses exactly like ```` ```c,ignore ````.
+fn parse_fence_info(info: &str) -> (String, Vec<Cow<'_, str>>, Option<String>) {
+    let parts = split_info(info);
 
     if parts.is_empty() {
         return (String::new(), Vec::new(), None);
@@ -144,39 +475,180 @@ fn parse_fence_info(info: &str) -> (String, Vec<&str>, Option<String>) {
 
     // Parse attributes (flags and variant)
     for part in &parts[1..] {
+        let part = normalize_attribute(part);
         if let Some(variant_value) = part.strip_prefix("variant=") {
             variant = Some(variant_value.to_string());
         } else {
-            flags.push(*part);
+            flags.push(part);
         }
     }
 
     (language, flags, variant)
 }
 
+/// Splits a fence info string on commas that are not inside double quotes,
+/// trimming each part.
+fn split_info(info: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+
+    for (i, c) in info.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                parts.push(info[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(info[start..].trim());
+    parts
+}
+
+/// Removes whitespace around the `=` of a `key = value` attribute.
+fn normalize_attribute(attribute: &str) -> Cow<'_, str> {
+    match attribute.split_once('=') {
+        Some((key, value))
+            if !key.contains('"')
+                && (key.ends_with(char::is_whitespace)
+                    || value.starts_with(char::is_whitespace)) =>
+        {
+            Cow::Owned(format!("{}={}", key.trim_end(), value.trim_start()))
+        }
+        _ => Cow::Borrowed(attribute),
+    }
+}
+
+/// Removes one pair of surrounding double quotes from an attribute value,
+/// turning each `\n` inside them into a line break. Unquoted values are
+/// returned as-is.
+///
+/// CommonMark backslash escapes (such as `\\`) are already resolved in the
+/// info string, while `\n` is not a CommonMark escape and reaches us verbatim.
+fn unquote(value: &str) -> String {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .map_or_else(|| value.to_string(), |inner| inner.replace("\\n", "\n"))
+}
+
+/// Attributes understood by this preprocessor, removed by [`rewrite_fences`].
+fn is_recognized_attribute(attribute: &str) -> bool {
+    matches!(attribute, "ignore" | "propagate" | "should_fail" | "no_run")
+        || attribute.starts_with("ignore=")
+        || attribute.starts_with("variant=")
+        || attribute.starts_with("name=")
+        || attribute.starts_with("file=")
+        || attribute.starts_with("expect_error=")
+        || attribute.starts_with("expect_output=")
+        || attribute.starts_with("flags=")
+}
+
+/// Rewrites fenced code blocks into the form shown in the rendered book.
+///
+/// Renderers such as highlight.js only recognize a bare language marker, so
+/// `rust,propagate,variant=foo` is rewritten to `rust`; unrecognized
+/// attributes are kept. Blocks whose marker is in
+/// [`ExtractOptions::hidden_line_fences`] also have their hidden lines
+/// removed (see [`split_hidden_lines`]). Only fences whose language marker
+/// satisfies `is_configured` are touched, and everything else, including
+/// indentation and the fence characters, is preserved exactly.
+pub fn rewrite_fences(
+    content: &str,
+    options: &ExtractOptions,
+    is_configured: impl Fn(&str) -> bool,
+) -> String {
+    let mut edits = Vec::new();
+    let mut hides_lines = false;
+
+    for (event, range) in Parser::new(content).into_offset_iter() {
+        match event {
+            Event::Text(text) if hides_lines => {
+                let (display, _) = split_hidden_lines(&text);
+                // Only rewrite text that maps one-to-one onto the source
+                if display != *text && content[range.clone()] == *text {
+                    edits.push((range, display));
+                }
+                continue;
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                hides_lines = false;
+                continue;
+            }
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(_))) => {}
+            _ => continue,
+        }
+
+        let fence_line = content[range.start..].lines().next().unwrap_or_default();
+        let after_indent = fence_line.trim_start();
+        let fence_char = match after_indent.chars().next() {
+            Some(c @ ('`' | '~')) => c,
+            _ => continue,
+        };
+        let info = after_indent.trim_start_matches(fence_char);
+        let info_start = range.start + (fence_line.len() - info.len());
+        let info = info.trim_end();
+
+        let mut parts = split_info(info).into_iter();
+        let language = parts.next().unwrap_or_default();
+        if !is_configured(language) {
+            continue;
+        }
+        hides_lines = options.hidden_line_fences.contains(language);
+        let kept: Vec<&str> = parts
+            .filter(|p| !is_recognized_attribute(&normalize_attribute(p)))
+            .collect();
+        let rewritten = std::iter::once(language)
+            .chain(kept)
+            .collect::<Vec<_>>()
+            .join(",");
+
+        if rewritten != info {
+            edits.push((info_start..info_start + info.len(), rewritten));
+        }
+    }
+
+    let mut output = content.to_string();
+    for (range, replacement) in edits.into_iter().rev() {
+        output.replace_range(range, &replacement);
+    }
+    output
+}
+
 /// Extracts code blocks with propagation support.
 ///
 /// This function handles the `propagate` attribute, which allows code from earlier
 /// blocks to be automatically included in later blocks within the same file.
-/// Propagation never leaks between different markdown files.
+/// Propagation never leaks between different markdown files, nor between
+/// blocks of different languages or variants (see [`CodeBlock::propagation_key`]).
 ///
 /// # Propagation Behavior
 ///
-/// - Blocks marked with `propagate` have their code accumulated
-/// - Non-propagated blocks receive all accumulated code as a preamble
+/// - Blocks marked with `propagate` have their code accumulated per language and variant
+/// - Non-propagated blocks receive the code accumulated for their language and
+///   variant
 /// - Propagated blocks do NOT receive accumulated code (they only contribute)
 /// - Blocks marked with `ignore` are skipped entirely
 ///
 /// # Arguments
 ///
 /// * `content` - The markdown content to parse
+/// * `options` - Extraction options such as hidden-line support
 ///
 /// # Returns
 ///
-/// A vector of tuples `(final_code, original_block)` where:
-/// - `final_code` includes any propagated code prepended
+/// A vector of tuples `(propagated_code, original_block)` where:
+/// - `propagated_code` is the code this block receives from earlier blocks
+///   (empty if none)
 /// - `original_block` is the original code block metadata
 ///
+/// The propagated code is combined with the block when its source file is
+/// assembled, see [`ConfiguredLanguage::assemble_source`].
+///
+/// [`ConfiguredLanguage::assemble_source`]: crate::language::ConfiguredLanguage::assemble_source
+///
 /// # Example
 ///
 /// ````markdown
@@ -190,39 +662,37 @@ fn parse_fence_info(info: &str) -> (String, Vec<&str>, Option<String>) {
 /// ```
 /// ````
 ///
-/// The second block will be compiled with:
+/// With the default `source_layout`, the second block will be compiled with:
 /// ```c
 /// struct Point { int x, y; };
 ///
 /// struct Point p = {1, 2};
 /// ```
-pub fn extract_code_blocks_with_propagation(content: &str) -> Vec<(String, CodeBlock)> {
-    let code_blocks = extract_code_blocks(content);
+pub fn extract_code_blocks_with_propagation(
+    content: &str,
+    options: &ExtractOptions,
+) -> Vec<(String, CodeBlock)> {
+    let code_blocks = extract_code_blocks(content, options);
     let mut result = Vec::new();
-    let mut propagated_code = String::new();
+    let mut propagated_code: HashMap<(String, Option<String>), String> = HashMap::new();
 
     for block in code_blocks {
         if block.ignore {
             continue;
         }
 
-        let mut final_code = String::new();
+        let accumulated = propagated_code.entry(block.propagation_key()).or_default();
 
-        // If this is not a propagated block, prepend accumulated propagated code
-        if !block.propagate && !propagated_code.is_empty() {
-            final_code.push_str(&propagated_code);
-            final_code.push('\n');
-        }
-
-        final_code.push_str(&block.code);
-
-        // If this is a propagated block, add to accumulated code
-        if block.propagate {
-            propagated_code.push_str(&block.code);
-            propagated_code.push('\n');
-        }
+        // Propagated blocks only contribute; other blocks receive the accumulated code
+        let received = if block.propagate {
+            accumulated.push_str(&block.compile_code);
+            accumulated.push('\n');
+            String::new()
+        } else {
+            accumulated.clone()
+        };
 
-        result.push((final_code, block));
+        result.push((received, block));
     }
 
     result
@@ -244,13 +714,14 @@ int main() {
 ```
 "#;
 
-        let blocks = extract_code_blocks(markdown);
+        let blocks = extract_code_blocks(markdown, &ExtractOptions::default());
         assert_eq!(blocks.len(), 1);
         assert_eq!(blocks[0].language, "c");
         assert!(!blocks[0].ignore);
         assert!(!blocks[0].propagate);
         assert_eq!(blocks[0].variant, None);
-        assert!(blocks[0].code.contains("int main()"));
+        assert_eq!(blocks[0].heading.as_deref(), Some("Test"));
+        assert!(blocks[0].compile_code.contains("int main()"));
     }
 
     #[test]
@@ -261,11 +732,75 @@ This is ignored
 ```
 "#;
 
-        let blocks = extract_code_blocks(markdown);
+        let blocks = extract_code_blocks(markdown, &ExtractOptions::default());
         assert_eq!(blocks.len(), 1);
         assert!(blocks[0].ignore);
     }
 
+    #[test]
+    fn test_ignore_reason() {
+        let markdown = "```c,ignore\na\n```\n\n```c,ignore=requires-hardware\nb\n```\n\n```c,ignore=\"needs network, sometimes\"\nc\n```\n\n```c\nd\n```\n";
+
+        let blocks = extract_code_blocks(markdown, &ExtractOptions::default());
+        let ignored: Vec<_> = blocks
+            .iter()
+            .map(|block| (block.ignore, block.ignore_reason.as_deref()))
+            .collect();
+        assert_eq!(
+            ignored,
+            vec![
+                (true, None),
+                (true, Some("requires-hardware")),
+                (true, Some("needs network, sometimes")),
+                (false, None),
+            ]
+        );
+        assert_eq!(
+            extract_code_blocks_with_propagation(markdown, &ExtractOptions::default()).len(),
+            1
+        );
+
+        let rewritten = rewrite_fences(
+            "```c,ignore=\"needs network\",editable\nint x\n```\n",
+            &ExtractOptions::default(),
+            |_| true,
+        );
+        assert_eq!(rewritten, "```c,editable\nint x\n```\n");
+    }
+
+    #[test]
+    fn test_flags_attribute() {
+        let blocks = extract_code_blocks(
+            "```c,flags=\"-std=c2x  -DNAME=1\"\nint x;\n```\n\n```c,flags=-O2\nint y;\n```\n\n```c\nint z;\n```\n",
+            &ExtractOptions::default(),
+        );
+        let flags: Vec<_> = blocks.iter().map(|block| block.flags.clone()).collect();
+        assert_eq!(
+            flags,
+            vec![
+                vec!["-std=c2x".to_string(), "-DNAME=1".to_string()],
+                vec!["-O2".to_string()],
+                vec![],
+            ]
+        );
+
+        let rewritten = rewrite_fences(
+            "```c,flags=\"-std=c2x\"\nint x;\n```\n",
+            &ExtractOptions::default(),
+            |_| true,
+        );
+        assert_eq!(rewritten, "```c\nint x;\n```\n");
+    }
+
+    #[test]
+    fn test_block_index_counts_ignored_blocks() {
+        let markdown = "```c,ignore\na\n```\n\n```c,ignore\nb\n```\n\n```c\nc\n```\n";
+
+        let blocks = extract_code_blocks_with_propagation(markdown, &ExtractOptions::default());
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].1.index, 2);
+    }
+
     #[test]
     fn test_extract_with_propagate_flag() {
         let markdown = r#"
@@ -278,15 +813,249 @@ Point p;
 ```
 "#;
 
-        let blocks = extract_code_blocks_with_propagation(markdown);
+        let blocks = extract_code_blocks_with_propagation(markdown, &ExtractOptions::default());
         assert_eq!(blocks.len(), 2);
 
         // First block (propagate)
         assert!(blocks[0].1.propagate);
 
-        // Second block should have propagated code prepended
-        assert!(blocks[1].0.contains("typedef struct"));
-        assert!(blocks[1].0.contains("Point p;"));
+        // Second block should receive the propagated code
+        assert_eq!(blocks[0].0, "");
+        assert_eq!(blocks[1].0, "typedef struct { int x; } Point;\n\n");
+        assert_eq!(blocks[1].1.compile_code, "Point p;\n");
+    }
+
+    #[test]
+    fn test_propagation_isolated_per_language_and_variant() {
+        let markdown = r#"
+```rust,propagate
+struct Point { x: i32 }
+```
+
+```c,propagate
+typedef int point_t;
+```
+
+```c,variant=parasol
+uint8_t f(void);
+```
+
+```rust
+let p = Point { x: 1 };
+```
+
+```c
+point_t p;
+```
+"#;
+
+        let blocks = extract_code_blocks_with_propagation(markdown, &ExtractOptions::default());
+        assert_eq!(blocks.len(), 5);
+        assert_eq!(blocks[4].1.index, 4);
+
+        // Variant blocks do not see the base language's propagated code
+        assert_eq!(blocks[2].0, "");
+
+        assert!(blocks[3].0.contains("struct Point"));
+        assert!(!blocks[3].0.contains("point_t"));
+
+        assert!(blocks[4].0.contains("typedef int point_t;"));
+        assert!(!blocks[4].0.contains("struct Point"));
+    }
+
+    #[test]
+    fn test_extract_blocks_inside_tab_containers() {
+        // Output of a typical tabs plugin: fences directly inside containers
+        let markdown = r#"# Tabbed Examples
+
+<div class="mdbook-tabs-container">
+<nav class="mdbook-tabs">
+<button class="mdbook-tab active" data-tabname="C">C</button>
+<button class="mdbook-tab" data-tabname="C++">C++</button>
+</nav>
+<div class="mdbook-tab-content" data-tabname="C">
+```c
+int add(int a, int b) { return a + b; }
+```
+</div>
+<div class="mdbook-tab-content hidden" data-tabname="C++">
+```cpp,ignore
+auto add(int a, int b) { return a + b; }
+```
+</div>
+</div>
+
+<div class="tab">
+
+```c
+int sub(int a, int b) { return a - b; }
+```
+
+</div>
+"#;
+
+        let blocks = extract_code_blocks(markdown, &ExtractOptions::default());
+        assert_eq!(blocks.len(), 3);
+        let indices: Vec<_> = blocks.iter().map(|b| b.index).collect();
+        assert_eq!(indices, vec![0, 1, 2]);
+        let lines: Vec<_> = blocks.iter().map(|b| b.line).collect();
+        assert_eq!(lines, vec![9, 14, 22]);
+
+        assert_eq!(blocks[0].language, "c");
+        assert_eq!(
+            blocks[0].compile_code,
+            "int add(int a, int b) { return a + b; }\n"
+        );
+        assert_eq!(blocks[0].heading.as_deref(), Some("Tabbed Examples"));
+
+        assert_eq!(blocks[1].language, "cpp");
+        assert!(blocks[1].ignore);
+
+        assert_eq!(blocks[2].language, "c");
+        assert!(blocks[2].compile_code.contains("int sub"));
+    }
+
+    #[test]
+    fn test_rewrite_fences_strips_attributes() {
+        let markdown = "# Chapter\n\n```rust,propagate,variant=foo\nfn main() {}\n```\n\n- item\n\n  ````c,ignore,editable\n  int x;\n  ````\n\n```python,ignore\nprint()\n```\n";
+
+        let stripped = rewrite_fences(markdown, &ExtractOptions::default(), |lang| {
+            lang == "rust" || lang == "c"
+        });
+        assert_eq!(
+            stripped,
+            "# Chapter\n\n```rust\nfn main() {}\n```\n\n- item\n\n  ````c,editable\n  int x;\n  ````\n\n```python,ignore\nprint()\n```\n"
+        );
+
+        // Already-clean content is returned unchanged
+        assert_eq!(
+            rewrite_fences(&stripped, &ExtractOptions::default(), |lang| lang == "rust"
+                || lang == "c"),
+            stripped
+        );
+    }
+
+    #[test]
+    fn test_split_hidden_lines() {
+        let code =
+            "# #include <stdio.h>\n#\nint main() {\n    # int hidden = 0;\n    ## literal\n}\n";
+        let (display, compile) = split_hidden_lines(code);
+        assert_eq!(display, "int main() {\n    # literal\n}\n");
+        assert_eq!(
+            compile,
+            "#include <stdio.h>\n\nint main() {\n    int hidden = 0;\n    # literal\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_hidden_lines_only_for_configured_fences() {
+        let markdown = "```rust,propagate\n# fn helper() {}\n```\n\n```rust\nhelper();\n```\n\n```c\n# define X 1\n```\n";
+        let options = ExtractOptions {
+            hidden_line_fences: HashSet::from(["rust".to_string()]),
+            ..Default::default()
+        };
+
+        let blocks = extract_code_blocks_with_propagation(markdown, &options);
+        assert_eq!(blocks[0].1.display_code, "");
+        assert_eq!(blocks[0].1.compile_code, "fn helper() {}\n");
+        // Propagation carries the compiled form, hidden lines included
+        assert_eq!(blocks[1].0, "fn helper() {}\n\n");
+        // Fences without hidden-line support keep `# ` lines
+        assert_eq!(blocks[2].1.compile_code, "# define X 1\n");
+
+        let rendered = rewrite_fences(markdown, &options, |_| true);
+        assert_eq!(
+            rendered,
+            "```rust\n```\n\n```rust\nhelper();\n```\n\n```c\n# define X 1\n```\n"
+        );
+    }
+
+    #[test]
+    fn test_extract_indented_blocks_with_default_language() {
+        let markdown =
+            "# Old Chapter\n\n    int x = 1;\n    int y = 2;\n\n```c,ignore\nskipped\n```\n";
+
+        let blocks = extract_code_blocks(markdown, &ExtractOptions::default());
+        assert_eq!(blocks.len(), 1);
+        assert!(blocks[0].ignore);
+
+        let options = ExtractOptions {
+            default_indented_language: Some("c".to_string()),
+            ..Default::default()
+        };
+        let blocks = extract_code_blocks(markdown, &options);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].language, "c");
+        assert_eq!(blocks[0].compile_code, "int x = 1;\nint y = 2;\n");
+        assert!(!blocks[0].ignore);
+        assert!(blocks[1].ignore);
+    }
+
+    #[test]
+    fn test_extract_tilde_fences() {
+        let markdown = "~~~c,ignore\nint a, b, c;\n~~~\n\n~~~~c,propagate,variant=parasol\nint x, y;\n~~~\n```\n~~~~\n";
+
+        let blocks = extract_code_blocks(markdown, &ExtractOptions::default());
+        assert_eq!(blocks.len(), 2);
+
+        assert_eq!(blocks[0].language, "c");
+        assert!(blocks[0].ignore);
+        assert_eq!(blocks[0].compile_code, "int a, b, c;\n");
+
+        // A shorter closing run and a backtick line do not close a `~~~~` fence
+        assert_eq!(blocks[1].language, "c");
+        assert!(blocks[1].propagate);
+        assert_eq!(blocks[1].variant.as_deref(), Some("parasol"));
+        assert_eq!(blocks[1].compile_code, "int x, y;\n~~~\n```\n");
+    }
+
+    #[test]
+    fn test_extract_longer_backtick_fence_keeps_inner_fence() {
+        let markdown =
+            "````c\nconst char *doc =\n```\n\"inner\";\n```c\n````\n\n```c\nint x;\n```\n";
+
+        let blocks = extract_code_blocks(markdown, &ExtractOptions::default());
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(
+            blocks[0].compile_code,
+            "const char *doc =\n```\n\"inner\";\n```c\n"
+        );
+        assert_eq!(blocks[1].compile_code, "int x;\n");
+    }
+
+    #[test]
+    fn test_extract_tilde_fences_inside_html_block() {
+        let markdown = "<div class=\"tab\">\n~~~c\n```\n<b>kept</b>\n~~~\n</div>\n";
+
+        let blocks = extract_code_blocks(markdown, &ExtractOptions::default());
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].compile_code, "```\n<b>kept</b>\n");
+    }
+
+    #[test]
+    fn test_extract_skips_fences_in_comments_and_pre() {
+        for markdown in [
+            "<!--\n```c\nint x\n```\n-->\n",
+            "<div>\n<!--\n```c\nint x\n```\n-->\n</div>\n",
+            "<pre>\n```c\nint x\n```\n</pre>\n",
+            "<script type=\"text/plain\">\n```c\nint x\n```\n</script>\n",
+        ] {
+            let blocks = extract_code_blocks(markdown, &ExtractOptions::default());
+            assert!(blocks.is_empty(), "{:?}", markdown);
+        }
+
+        // Fences after the comment are still found
+        let markdown = "<!--\n```c\nint x\n```\n-->\n\n```c\nint y;\n```\n";
+        let blocks = extract_code_blocks(markdown, &ExtractOptions::default());
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].compile_code, "int y;\n");
+    }
+
+    #[test]
+    fn test_extract_html_block_without_tag_lines() {
+        // Nothing to blank, so the block must not be parsed again forever
+        let markdown = "<div>text\n```c\nint x;\n```\n</div> more\n";
+        assert!(extract_code_blocks(markdown, &ExtractOptions::default()).is_empty());
     }
 
     #[test]
@@ -296,6 +1065,11 @@ Point p;
         assert!(flags.is_empty());
         assert_eq!(variant, None);
 
+        let (lang, flags, variant) = parse_fence_info("c,should_fail");
+        assert_eq!(lang, "c");
+        assert_eq!(flags, vec!["should_fail"]);
+        assert_eq!(variant, None);
+
         let (lang, flags, variant) = parse_fence_info("typescript,ignore");
         assert_eq!(lang, "typescript");
         assert_eq!(flags, vec!["ignore"]);
@@ -315,5 +1089,88 @@ Point p;
         assert_eq!(lang, "c");
         assert_eq!(flags, vec!["propagate"]);
         assert_eq!(variant, Some("parasol".to_string()));
+
+        let (lang, flags, variant) = parse_fence_info(r#"c,variant=parasol,propagate,flags="-O0""#);
+        assert_eq!(lang, "c");
+        assert_eq!(flags, vec!["propagate", r#"flags="-O0""#]);
+        assert_eq!(variant, Some("parasol".to_string()));
+
+        let (lang, flags, variant) = parse_fence_info(r#"c,flags="-a,-b",propagate"#);
+        assert_eq!(lang, "c");
+        assert_eq!(flags, vec![r#"flags="-a,-b""#, "propagate"]);
+        assert_eq!(variant, None);
+
+        let (lang, flags, variant) =
+            parse_fence_info(r#"c , variant = parasol, flags= "-O0, -g" ,propagate"#);
+        assert_eq!(lang, "c");
+        assert_eq!(flags, vec![r#"flags="-O0, -g""#, "propagate"]);
+        assert_eq!(variant, Some("parasol".to_string()));
+
+        let markdown = "```c,flags = \"-a -b\",variant=parasol,propagate,expect_error = \"x = y, z\"\nint x;\n```\n";
+        let blocks = extract_code_blocks(markdown, &ExtractOptions::default());
+        assert_eq!(blocks[0].flags, vec!["-a", "-b"]);
+        assert_eq!(blocks[0].variant.as_deref(), Some("parasol"));
+        assert!(blocks[0].propagate);
+        assert_eq!(blocks[0].expect_error.as_deref(), Some("x = y, z"));
+        assert_eq!(
+            rewrite_fences(markdown, &ExtractOptions::default(), |_| true),
+            "```c\nint x;\n```\n"
+        );
+
+        let blocks =
+            extract_code_blocks("```c,name=setup\nint x;\n```\n", &ExtractOptions::default());
+        assert_eq!(blocks[0].name.as_deref(), Some("setup"));
+
+        let blocks = extract_code_blocks(
+            "```c,file=point.h\nstruct point;\n```\n",
+            &ExtractOptions::default(),
+        );
+        assert_eq!(blocks[0].file.as_deref(), Some("point.h"));
+        let rewritten = rewrite_fences(
+            "```c,file=point.h\nstruct point;\n```\n",
+            &ExtractOptions::default(),
+            |_| true,
+        );
+        assert_eq!(rewritten, "```c\nstruct point;\n```\n");
+    }
+
+    #[test]
+    fn test_expect_error_attribute() {
+        let (lang, flags, _) = parse_fence_info(r#"c,expect_error="expected ',' or ';'",ignore"#);
+        assert_eq!(lang, "c");
+        assert_eq!(
+            flags,
+            vec![r#"expect_error="expected ',' or ';'""#, "ignore"]
+        );
+
+        let blocks = extract_code_blocks(
+            "```c,expect_error=\"a, b\"\nint x\n```\n",
+            &ExtractOptions::default(),
+        );
+        assert_eq!(blocks[0].expect_error.as_deref(), Some("a, b"));
+        assert!(blocks[0].should_fail);
+
+        let rewritten = rewrite_fences(
+            "```c,expect_error=\"a, b\",editable\nint x\n```\n",
+            &ExtractOptions::default(),
+            |_| true,
+        );
+        assert_eq!(rewritten, "```c,editable\nint x\n```\n");
+    }
+
+    #[test]
+    fn test_expect_output_attribute() {
+        let blocks = extract_code_blocks(
+            "```python,expect_output=\"a, 1\\n2\",ignore\nprint(1)\n```\n",
+            &ExtractOptions::default(),
+        );
+        assert_eq!(blocks[0].expect_output.as_deref(), Some("a, 1\n2"));
+        assert!(blocks[0].ignore);
+        assert!(!blocks[0].should_fail);
+
+        assert_eq!(unquote("42"), "42");
+
+        let blocks = extract_code_blocks("```c,no_run\nint x;\n```\n", &ExtractOptions::default());
+        assert!(blocks[0].no_run);
     }
 }
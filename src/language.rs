@@ -1,11 +1,22 @@
-use crate::config::{CheckCodeConfig, LanguageConfig};
+use crate::config::{
+    Backend, CheckCodeConfig, CheckMode, LanguageConfig, SourceSegment, VariantConfig,
+};
+use crate::diff::line_diff;
 use anyhow::{Context, Result};
 use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
-use std::path::Path;
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
+use std::time::Duration;
 use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
-use tokio::process::Command;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, ChildStdin, Command};
+
+/// Base delay before retrying a compiler spawn that failed with `EAGAIN`.
+const SPAWN_RETRY_BASE_DELAY: Duration = Duration::from_millis(10);
 
 /// Metadata for a programming language including fence markers and file extension.
 ///
@@ -46,6 +57,31 @@ impl LanguageMetadata {
     }
 }
 
+/// Returns the line-comment delimiters `(prefix, suffix)` conventionally used
+/// by files with the given extension, or None if the family is unknown.
+///
+/// # Examples
+///
+/// ```ignore
+/// assert_eq!(comment_style(".c"), Some(("//", "")));
+/// assert_eq!(comment_style(".html"), Some(("<!--", "-->")));
+/// ```
+pub fn comment_style(file_extension: &str) -> Option<(&'static str, &'static str)> {
+    let style = match file_extension {
+        ".c" | ".cpp" | ".cs" | ".dart" | ".go" | ".java" | ".js" | ".kt" | ".rs" | ".scala"
+        | ".sol" | ".swift" | ".ts" | ".zig" | ".v" | ".proto" => ("//", ""),
+        ".sh" | ".py" | ".rb" | ".pl" | ".r" | ".toml" | ".yaml" | ".yml" | ".cmake" | ".conf"
+        | ".nix" | ".ps1" | ".jl" | ".ex" | "Makefile" => ("#", ""),
+        ".sql" | ".hs" | ".lua" | ".elm" | ".adb" | ".vhd" => ("--", ""),
+        ".html" | ".xml" | ".svg" | ".md" | ".vue" => ("<!--", "-->"),
+        ".tex" | ".erl" | ".m" => ("%", ""),
+        ".lisp" | ".clj" | ".scm" | ".el" | ".asm" | ".s" | ".ini" => (";", ""),
+        ".css" => ("/*", "*/"),
+        _ => return None,
+    };
+    Some(style)
+}
+
 /// Get language metadata (fence markers and file extension) for a language.
 ///
 /// This function returns the canonical language name plus common aliases that highlight.js
@@ -357,6 +393,45 @@ pub fn get_language_metadata(lang_name: &str) -> LanguageMetadata {
     }
 }
 
+/// Summary of a language configured in `book.toml`, as returned by
+/// [`CheckCodePreprocessor::configured_languages`](crate::CheckCodePreprocessor::configured_languages).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguageSummary {
+    /// Language name, as configured under `languages`
+    pub name: String,
+    /// Fence markers selecting the language (configured, or its defaults)
+    pub fence_markers: Vec<String>,
+    /// Extension of the source files given to the compiler (e.g. ".c")
+    pub file_extension: String,
+    /// Names of the language's variants, sorted
+    pub variants: Vec<String>,
+    /// Whether blocks of the language are validated
+    pub enabled: bool,
+}
+
+impl LanguageSummary {
+    /// Summarizes every language in `config`, enabled or not, sorted by name.
+    pub fn from_config(config: &CheckCodeConfig) -> Vec<Self> {
+        let mut summaries: Vec<_> = config
+            .languages
+            .iter()
+            .map(|(name, lang_config)| {
+                let mut variants: Vec<_> = lang_config.variants.keys().cloned().collect();
+                variants.sort();
+                Self {
+                    name: name.clone(),
+                    fence_markers: lang_config.get_fence_markers(name),
+                    file_extension: resolve_file_extension(name, lang_config),
+                    variants,
+                    enabled: lang_config.enabled,
+                }
+            })
+            .collect();
+        summaries.sort_by(|a, b| a.name.cmp(&b.name));
+        summaries
+    }
+}
+
 /// A language implementation configured from `book.toml`.
 ///
 /// This struct represents a language whose behavior is entirely determined by
@@ -368,18 +443,22 @@ pub fn get_language_metadata(lang_name: &str) -> LanguageMetadata {
 /// All compilation behavior comes from `book.toml`:
 /// - Compiler path (with `${VAR}` environment variable expansion)
 /// - Compiler flags (array of strings)
-/// - Optional preamble (prepended to all blocks)
+/// - Optional preamble (prepended to all blocks, see [`ConfiguredLanguage::assemble_source`])
 /// - Fence markers (which markdown fences map to this language)
 ///
 /// # Display
 ///
 /// The language can be formatted for display using the `Display` trait, which
 /// combines the base language and variant (if present) into a string like "c" or "c-parasol".
+#[derive(Clone)]
 pub struct ConfiguredLanguage {
     base_language: String,
     variant: Option<String>,
     config: LanguageConfig,
     file_extension: String,
+    /// Header compiled from the preamble by
+    /// [`LanguageRegistry::precompile_preambles`], included instead of the preamble
+    precompiled_preamble: Option<PathBuf>,
 }
 
 impl fmt::Display for ConfiguredLanguage {
@@ -392,46 +471,311 @@ impl fmt::Display for ConfiguredLanguage {
     }
 }
 
+/// Returns the extension (or, for complete file names such as `Makefile`,
+/// the file name) of source files written for a language: the configured
+/// `file_name` or `file_extension`, else the default for the language name.
+fn resolve_file_extension(language: &str, config: &LanguageConfig) -> String {
+    match (&config.file_name, &config.file_extension) {
+        (Some(file_name), _) => file_name.clone(),
+        (None, Some(extension)) if extension.starts_with('.') => extension.clone(),
+        (None, Some(extension)) => format!(".{}", extension),
+        (None, None) => get_language_metadata(language).file_extension.into_owned(),
+    }
+}
+
 impl ConfiguredLanguage {
     pub fn new(base_language: String, variant: Option<String>, config: LanguageConfig) -> Self {
-        // Get metadata for the base language to determine file extension
-        let metadata = get_language_metadata(&base_language);
-        let file_extension = metadata.file_extension.into_owned();
+        let file_extension = resolve_file_extension(&base_language, &config);
 
         Self {
             base_language,
             variant,
             config,
             file_extension,
+            precompiled_preamble: None,
+        }
+    }
+
+    /// Returns the configured language name, without any variant.
+    pub fn base_language(&self) -> &str {
+        &self.base_language
+    }
+
+    /// Returns the variant name, if this is a variant of the base language.
+    pub fn variant(&self) -> Option<&str> {
+        self.variant.as_deref()
+    }
+
+    /// Returns the resolved compiler executable used for this language.
+    pub fn compiler(&self) -> &str {
+        &self.config.compiler
+    }
+
+    /// Returns the resolved compiler flags used for this language.
+    pub fn flags(&self) -> &[String] {
+        &self.config.flags
+    }
+
+    /// Returns the first of a block's `flags` that the language's
+    /// `allowed_block_flags` does not permit.
+    pub fn disallowed_block_flag<'a>(&self, flags: &'a [String]) -> Option<&'a str> {
+        flags
+            .iter()
+            .map(String::as_str)
+            .find(|flag| !self.config.allows_block_flag(flag))
+    }
+
+    /// Appends `flags` to the configured compiler flags, for a block with a
+    /// `flags=` attribute.
+    pub fn with_extra_flags(mut self, flags: &[String]) -> Self {
+        self.config.flags.extend(flags.iter().cloned());
+        self
+    }
+
+    /// Returns whether blocks of this language must compile sequentially per chapter.
+    pub fn is_serial(&self) -> bool {
+        self.config.serial
+    }
+
+    /// Returns the configured compile timeout in seconds, if any.
+    pub fn timeout_seconds(&self) -> Option<u64> {
+        self.config.timeout_seconds
+    }
+
+    /// Returns the shared resource this language's compiler uses, if any.
+    pub fn resource(&self) -> Option<&str> {
+        self.config.resource.as_deref()
+    }
+
+    /// Returns the share of the memory budget each block of this language holds.
+    pub fn memory_weight(&self) -> u32 {
+        self.config.memory_weight
+    }
+
+    /// Returns a string identifying every setting that affects how a block of
+    /// this language is checked, for use in cache keys, including how its
+    /// source file is named and written. The compiler binary and WASM module
+    /// are included by their size and modification time, so upgrading or
+    /// rebuilding them invalidates entries. Files a block reads itself, such
+    /// as included headers, are not covered.
+    pub fn fingerprint(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n{}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}",
+            self,
+            self.file_extension,
+            self.config.stdin,
+            self.config.preserve_line_endings,
+            self.config.compiler,
+            compiler_identity(&self.config.compiler),
+            self.config.flags,
+            self.config.preamble,
+            self.config.postamble,
+            self.config.mode,
+            self.config.backend,
+            self.config.module,
+            self.config
+                .module
+                .as_deref()
+                .map_or_else(String::new, file_identity),
+            self.config.forbidden_patterns,
+            self.config.required_patterns,
+            self.config.run_command,
+            self.config.source_layout,
+            self.config.env.iter().collect::<BTreeMap<_, _>>(),
+        )
+    }
+
+    /// Assembles the source file for a block from its segments in
+    /// `source_layout` order (by default preamble, propagated code, block,
+    /// postamble).
+    ///
+    /// Empty segments are skipped. Every other segment except the last is
+    /// followed by a blank line: a newline is added if the segment does not
+    /// already end with one, then an empty line. CRLF line endings are
+    /// converted to LF unless `preserve_line_endings` is set.
+    pub fn assemble_source(&self, propagated: &str, block: &str) -> String {
+        self.assemble(propagated, block, true).0
+    }
+
+    /// Assembles the code shown for a block in reports: like
+    /// [`Self::assemble_source`], but without the preamble and postamble.
+    pub fn block_source(&self, propagated: &str, block: &str) -> String {
+        self.assemble(propagated, block, false).0
+    }
+
+    /// Returns the one-based line at which the block's own code starts in
+    /// the source file written for it, after the provenance comment and any
+    /// segments placed before the block.
+    pub fn block_start_line(&self, propagated: &str, block: &str) -> usize {
+        let provenance_lines = usize::from(self.provenance_line("").is_some());
+        provenance_lines + self.assemble(propagated, block, true).1 + 1
+    }
+
+    /// Returns the configured postamble, if any.
+    pub fn postamble(&self) -> Option<&str> {
+        self.config.postamble.as_deref()
+    }
+
+    /// Assembles the segments, returning the source and the number of lines
+    /// preceding the block within it.
+    fn assemble(&self, propagated: &str, block: &str, with_ambles: bool) -> (String, usize) {
+        // A precompiled preamble is passed with -include rather than written
+        let preamble = self
+            .config
+            .preamble
+            .as_deref()
+            .filter(|_| with_ambles && self.precompiled_preamble.is_none())
+            .unwrap_or_default();
+        let postamble = self
+            .config
+            .postamble
+            .as_deref()
+            .filter(|_| with_ambles)
+            .unwrap_or_default();
+        let segments: Vec<(SourceSegment, &str)> = self
+            .config
+            .source_layout
+            .iter()
+            .map(|&segment| {
+                let text = match segment {
+                    SourceSegment::Preamble => preamble,
+                    SourceSegment::Propagated => propagated,
+                    SourceSegment::Block => block,
+                    SourceSegment::Postamble => postamble,
+                };
+                (segment, text)
+            })
+            .filter(|(_, text)| !text.is_empty())
+            .collect();
+
+        let mut source = String::new();
+        let mut lines_before_block = 0;
+        for (i, (kind, segment)) in segments.iter().enumerate() {
+            if *kind == SourceSegment::Block {
+                lines_before_block = source.matches('\n').count();
+            }
+            source.push_str(segment);
+            if i + 1 < segments.len() {
+                if !segment.ends_with('\n') {
+                    source.push('\n');
+                }
+                source.push('\n');
+            }
+        }
+        (self.normalize_line_endings(source), lines_before_block)
+    }
+
+    /// Converts CRLF line endings to LF, unless `preserve_line_endings` is set.
+    fn normalize_line_endings(&self, text: String) -> String {
+        if self.config.preserve_line_endings || !text.contains('\r') {
+            text
+        } else {
+            text.replace("\r\n", "\n")
         }
     }
 
-    /// Returns the file extension for this language (e.g., ".c", ".ts").
+    /// Returns the file extension for this language (e.g., ".c", ".ts"), or
+    /// the complete file name (e.g. "Makefile") for languages whose files
+    /// need an exact name.
     pub fn file_extension(&self) -> &str {
         &self.file_extension
     }
 
-    /// Writes source code with optional preamble to a temporary file.
+    /// Returns the path of the source file for `name` in `dir`: `name` with
+    /// the file extension appended, or, for complete file names, the file
+    /// name inside a directory called `name`.
+    pub fn source_path(&self, dir: &Path, name: &str) -> PathBuf {
+        if self.file_extension.starts_with('.') {
+            dir.join(format!("{}{}", name, self.file_extension))
+        } else {
+            dir.join(name).join(&self.file_extension)
+        }
+    }
+
+    /// Checks `code` against the configured forbidden and required patterns.
+    ///
+    /// Returns a description of each violation: every forbidden pattern that
+    /// matches (with the matched text) and every required pattern that does not.
+    /// Patterns are compiled once, when the configuration is parsed.
+    pub fn pattern_violations(&self, code: &str) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        for pattern in &self.config.forbidden_patterns {
+            if let Some(m) = pattern.regex().find(code) {
+                violations.push(format!(
+                    "matches forbidden pattern '{}' ({:?})",
+                    pattern,
+                    m.as_str()
+                ));
+            }
+        }
+
+        for pattern in &self.config.required_patterns {
+            if !pattern.regex().is_match(code) {
+                violations.push(format!("does not match required pattern '{}'", pattern));
+            }
+        }
+
+        violations
+    }
+
+    /// Returns the provenance comment line written at the top of the source
+    /// file, or None if provenance comments are disabled or no comment syntax
+    /// is known for the language.
+    fn provenance_line(&self, origin: &str) -> Option<String> {
+        if !self.config.provenance_comment {
+            return None;
+        }
+
+        let (prefix, suffix) = match &self.config.comment_prefix {
+            Some(prefix) => (
+                prefix.as_str(),
+                self.config.comment_suffix.as_deref().unwrap_or(""),
+            ),
+            None => comment_style(&self.file_extension)?,
+        };
+        let text = format!("generated from {} by mdbook-check-code", origin);
+        Some(if suffix.is_empty() {
+            format!("{} {}\n", prefix, text)
+        } else {
+            format!("{} {} {}\n", prefix, text, suffix)
+        })
+    }
+
+    /// Writes the assembled source (see [`Self::assemble_source`]) to a
+    /// temporary file, after the optional provenance comment.
     ///
     /// # Arguments
     ///
-    /// * `code` - The source code to write
+    /// * `propagated` - Code propagated into the block from earlier blocks
+    /// * `code` - The block's own code
     /// * `temp_file` - Path where the code should be written
     ///
     /// # Errors
     ///
     /// Returns an error if the file cannot be created or written.
-    async fn write_source_file(&self, code: &str, temp_file: &Path) -> Result<()> {
+    async fn write_source_file(
+        &self,
+        propagated: &str,
+        code: &str,
+        temp_file: &Path,
+        origin: &str,
+    ) -> Result<()> {
+        if let Some(parent) = temp_file.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
         let mut file = File::create(temp_file)
             .await
             .with_context(|| format!("Failed to create temporary file: {}", temp_file.display()))?;
 
-        if let Some(ref preamble) = self.config.preamble {
-            file.write_all(preamble.as_bytes()).await?;
-            file.write_all(b"\n\n").await?;
+        if let Some(line) = self.provenance_line(origin) {
+            file.write_all(line.as_bytes()).await?;
         }
 
-        file.write_all(code.as_bytes()).await?;
+        file.write_all(self.assemble_source(propagated, code).as_bytes())
+            .await?;
         file.flush().await?;
 
         Ok(())
@@ -441,12 +785,17 @@ impl ConfiguredLanguage {
     ///
     /// # Arguments
     ///
-    /// * `code` - The source code to validate (may include preambles)
+    /// * `propagated` - Code propagated into the block from earlier blocks
+    /// * `code` - The block's own code
     /// * `temp_file` - Path where the code should be written for compilation
+    /// * `origin` - Where the block came from (e.g. `intro.md block #3`), used
+    ///   for the optional provenance comment
     ///
     /// # Returns
     ///
-    /// * `Ok(())` if compilation succeeds
+    /// * `Ok(None)` if compilation succeeds
+    /// * `Ok(Some(artifact))` if compilation succeeds and `run_command` is
+    ///   configured, with the path the compiler was asked to write its output to
     /// * `Err` with compilation error details if it fails
     ///
     /// # Errors
@@ -455,46 +804,781 @@ impl ConfiguredLanguage {
     /// - The temporary file cannot be created or written
     /// - The compiler executable cannot be found or executed
     /// - The code fails to compile
-    pub async fn compile(&self, code: &str, temp_file: &Path) -> Result<()> {
-        // Write code with optional preamble to temp file
-        self.write_source_file(code, temp_file).await?;
+    pub async fn compile(
+        &self,
+        propagated: &str,
+        code: &str,
+        temp_file: &Path,
+        origin: &str,
+    ) -> Result<Option<PathBuf>> {
+        if self.config.stdin {
+            let mut source = self.provenance_line(origin).unwrap_or_default();
+            source.push_str(&self.assemble_source(propagated, code));
+            let artifact = artifact_path(temp_file);
+            self.check_compiles(&[], &artifact, Some(source.into_bytes()))
+                .await?;
+            return Ok(self.config.run_command.is_some().then_some(artifact));
+        }
 
-        // Execute compiler with configured flags
-        let output = Command::new(&self.config.compiler)
-            .args(&self.config.flags)
-            .arg(temp_file)
-            .output()
+        // Write the assembled source with optional provenance comment to temp file
+        self.write_source_file(propagated, code, temp_file, origin)
+            .await?;
+
+        if self.config.backend == Backend::Wasm {
+            return self.check_wasm(temp_file).await.map(|()| None);
+        }
+
+        match self.config.mode {
+            CheckMode::Compile => {
+                self.check_compiles(&[temp_file], &artifact_path(temp_file), None)
+                    .await?
+            }
+            CheckMode::FormatStable => self.check_format_stable(temp_file).await?,
+        }
+
+        Ok(self
+            .config
+            .run_command
+            .is_some()
+            .then(|| artifact_path(temp_file)))
+    }
+
+    /// Compiles a multi-file compilation unit asynchronously.
+    ///
+    /// Each file is written verbatim into `unit_dir`, without preamble,
+    /// postamble or provenance comment, and all of them are passed to the
+    /// compiler together, in order. The artifact path is derived from
+    /// `unit_dir`. Returns the same values as [`Self::compile`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the language does not use the `compile` mode with
+    /// the native backend, a file cannot be written, or compilation fails.
+    pub async fn compile_unit(
+        &self,
+        files: &[SourceFile],
+        unit_dir: &Path,
+    ) -> Result<Option<PathBuf>> {
+        if self.config.backend == Backend::Wasm
+            || self.config.mode != CheckMode::Compile
+            || self.config.stdin
+        {
+            anyhow::bail!(
+                "Language '{}' cannot compile file= units: they require mode = \"compile\", the native backend, and stdin = false",
+                self
+            );
+        }
+
+        tokio::fs::create_dir_all(unit_dir)
+            .await
+            .with_context(|| format!("Failed to create directory: {}", unit_dir.display()))?;
+        let mut sources = Vec::with_capacity(files.len());
+        for file in files {
+            let path = unit_dir.join(&file.name);
+            tokio::fs::write(&path, self.normalize_line_endings(file.code.clone()))
+                .await
+                .with_context(|| format!("Failed to write temporary file: {}", path.display()))?;
+            sources.push(path);
+        }
+
+        let artifact = artifact_path(unit_dir);
+        let sources: Vec<&Path> = sources.iter().map(PathBuf::as_path).collect();
+        self.check_compiles(&sources, &artifact, None).await?;
+
+        Ok(self.config.run_command.is_some().then_some(artifact))
+    }
+
+    /// Compiles the preamble into a precompiled header in `dir`, returning the
+    /// header's path. Passing it to the compiler with `-include` makes gcc and
+    /// clang load the precompiled `.gch` file next to it.
+    ///
+    /// The header is compiled with the language's flags, except those that
+    /// only make sense for blocks: `-fsyntax-only`, `-c`, `-o` with its
+    /// argument, `-`, and flags containing placeholders.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no preamble, the header cannot be written,
+    /// or the compiler fails.
+    pub async fn precompile_preamble(&self, dir: &Path) -> Result<PathBuf> {
+        let preamble = self
+            .config
+            .preamble
+            .as_deref()
+            .context("No preamble to precompile")?;
+        let cpp = matches!(
+            self.file_extension.as_str(),
+            ".cpp" | ".cc" | ".cxx" | ".c++" | ".hpp"
+        );
+        let header = dir.join(format!(
+            "{}_preamble{}",
+            self,
+            if cpp { ".hpp" } else { ".h" }
+        ));
+        tokio::fs::write(&header, preamble)
             .await
+            .with_context(|| format!("Failed to write temporary file: {}", header.display()))?;
+
+        let mut flags = Vec::new();
+        let mut skip_next = false;
+        for flag in &self.config.flags {
+            match flag.as_str() {
+                _ if std::mem::take(&mut skip_next) => {}
+                "-o" => skip_next = true,
+                "-fsyntax-only" | "-c" | "-" => {}
+                _ if flag.contains('{') => {}
+                _ => flags.push(flag.as_str()),
+            }
+        }
+
+        let mut command = Command::new(&self.config.compiler);
+        command
+            .args(flags)
+            .args(["-x", if cpp { "c++-header" } else { "c-header" }])
+            .arg(&header)
+            .arg("-o")
+            .arg(header.with_extension(if cpp { "hpp.gch" } else { "h.gch" }))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let output = self
+            .found(self.run_command(&mut command, None).await)?
             .with_context(|| {
                 format!(
-                    "Failed to execute compiler '{}' for language '{}'\nFlags: {:?}\nFile: {}",
+                    "Failed to execute compiler '{}' for language '{}'",
+                    self.config.compiler, self
+                )
+            })?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} preamble failed to precompile\nCompiler: {}\n\n{}",
+                self,
+                self.config.compiler,
+                failure_output(&output)
+            );
+        }
+
+        Ok(header)
+    }
+
+    /// Runs the program built from a block using the configured `run_command`,
+    /// returning its captured output.
+    ///
+    /// `{artifact}` and `{source}` in the command are replaced by the compiled
+    /// program's path and the block's source file, so interpreted languages
+    /// can run the source directly.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no `run_command` is configured or the program cannot
+    /// be started. A nonzero exit status is reported through the returned
+    /// [`Output`], not as an error.
+    pub async fn run_artifact(&self, artifact: &Path, source: &Path) -> Result<Output> {
+        let run_command = self
+            .config
+            .run_command
+            .as_deref()
+            .context("No run_command configured")?;
+        let artifact = artifact.display().to_string();
+        let source = source.display().to_string();
+        let argv: Vec<String> = run_command
+            .iter()
+            .map(|arg| {
+                arg.replace(ARTIFACT_PLACEHOLDER, &artifact)
+                    .replace(SOURCE_PLACEHOLDER, &source)
+            })
+            .collect();
+
+        let mut command = Command::new(&argv[0]);
+        command
+            .args(&argv[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        self.run_command(&mut command, None)
+            .await
+            .with_context(|| format!("Failed to run program for language '{}': {:?}", self, argv))
+    }
+
+    /// Validates the source by running the configured WASM module.
+    ///
+    /// The configured `compiler` is the WASM runtime (e.g. `wasmtime`), invoked as
+    /// `<compiler> run <flags...> <module>` with the full source on stdin; a
+    /// zero exit status means the block is valid. The module lives inside the
+    /// book, and the common flags that would preopen directories or grant
+    /// environment or network access ([`crate::config::WASM_HOST_ACCESS_FLAGS`])
+    /// are refused. This is not a sandbox: the runtime and its remaining
+    /// options are trusted like a native compiler.
+    async fn check_wasm(&self, temp_file: &Path) -> Result<()> {
+        let module = self
+            .config
+            .module
+            .as_deref()
+            .context("WASM backend requires a 'module' path")?;
+        // Checked again here since block flags are added after loading
+        if let Some(flag) = crate::config::wasm_host_access_flag(&self.config.flags) {
+            anyhow::bail!(
+                "Language '{}' cannot pass '{}' to the WASM runtime, since it gives the module access to the host",
+                self,
+                flag
+            );
+        }
+        let source = tokio::fs::read(temp_file)
+            .await
+            .with_context(|| format!("Failed to read temporary file: {}", temp_file.display()))?;
+
+        let mut command = Command::new(&self.config.compiler);
+        command
+            .arg("run")
+            .args(&self.config.flags)
+            .arg(module)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let output = self
+            .found(self.run_command(&mut command, Some(source)).await)?
+            .with_context(|| {
+                format!(
+                    "Failed to execute WASM runtime '{}' for language '{}'\nModule: {}",
                     self.config.compiler,
                     self,
-                    self.config.flags,
-                    temp_file.display()
+                    module.display()
                 )
             })?;
 
         if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let error_msg = if !stderr.is_empty() {
-                stderr.to_string()
-            } else {
-                stdout.to_string()
-            };
+            anyhow::bail!(
+                "{} validation failed\nRuntime: {}\nModule: {}\nFile: {}\n\n{}",
+                self,
+                self.config.compiler,
+                module.display(),
+                temp_file.display(),
+                failure_output(&output)
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Runs the compiler on `sources` (or with `input` on stdin) and fails if
+    /// it exits unsuccessfully.
+    async fn check_compiles(
+        &self,
+        sources: &[&Path],
+        artifact: &Path,
+        input: Option<Vec<u8>>,
+    ) -> Result<()> {
+        let output = self.invoke(sources, artifact, input).await?;
+
+        if !output.status.success() {
             anyhow::bail!(
                 "{} compilation failed\nCompiler: {}\nFlags: {:?}\nFile: {}\n\n{}",
                 self,
                 self.config.compiler,
                 self.config.flags,
+                display_paths(sources),
+                failure_output(&output)
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Runs the configured formatter twice and fails if the second pass changes
+    /// the output of the first.
+    ///
+    /// The formatter is expected to print the formatted source to stdout. The
+    /// first pass formats the block; its output is written back to `temp_file`
+    /// and formatted again. A formatter is stable when the second pass is a no-op.
+    async fn check_format_stable(&self, temp_file: &Path) -> Result<()> {
+        let first = self.format_pass(temp_file, "first").await?;
+        tokio::fs::write(temp_file, &first)
+            .await
+            .with_context(|| format!("Failed to write temporary file: {}", temp_file.display()))?;
+        let second = self.format_pass(temp_file, "second").await?;
+
+        if first != second {
+            anyhow::bail!(
+                "{} formatting is not stable\nFormatter: {}\nFlags: {:?}\nFile: {}\n\n\
+                 Diff between first and second formatting pass:\n{}",
+                self,
+                self.config.compiler,
+                self.config.flags,
                 temp_file.display(),
-                error_msg
+                line_diff(&first, &second)
             );
         }
 
         Ok(())
     }
+
+    /// Runs one formatter pass over `temp_file`, returning the formatted source.
+    async fn format_pass(&self, temp_file: &Path, pass: &str) -> Result<String> {
+        let output = self
+            .invoke(&[temp_file], &artifact_path(temp_file), None)
+            .await?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} formatter failed on the {} pass\nFormatter: {}\nFlags: {:?}\nFile: {}\n\n{}",
+                self,
+                pass,
+                self.config.compiler,
+                self.config.flags,
+                temp_file.display(),
+                failure_output(&output)
+            );
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Executes the configured compiler with its flags on `sources`, writing
+    /// `input`, if any, to its stdin.
+    ///
+    /// See [`Self::compiler_args`] for how the arguments are built.
+    async fn invoke(
+        &self,
+        sources: &[&Path],
+        artifact: &Path,
+        input: Option<Vec<u8>>,
+    ) -> Result<Output> {
+        let outdir = artifact.with_extension("outdir");
+        if self
+            .config
+            .flags
+            .iter()
+            .any(|flag| flag.contains(OUTDIR_PLACEHOLDER))
+        {
+            tokio::fs::create_dir_all(&outdir)
+                .await
+                .with_context(|| format!("Failed to create directory: {}", outdir.display()))?;
+        }
+
+        let mut command = Command::new(&self.config.compiler);
+        command
+            .args(&self.build_command(sources, artifact)[1..])
+            .stdin(if input.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        self.found(self.run_command(&mut command, input).await)?
+            .with_context(|| {
+                format!(
+                    "Failed to execute compiler '{}' for language '{}'\nFlags: {:?}\nFile: {}",
+                    self.config.compiler,
+                    self,
+                    self.config.flags,
+                    display_paths(sources)
+                )
+            })
+    }
+
+    /// Returns the full command line, compiler first, that compiles `sources`
+    /// into `artifact`. See [`Self::compiler_args`] for how the arguments are
+    /// built.
+    pub fn build_command(&self, sources: &[&Path], artifact: &Path) -> Vec<String> {
+        let outdir = artifact.with_extension("outdir");
+        std::iter::once(self.config.compiler.clone())
+            .chain(self.compiler_args(sources, artifact, &outdir))
+            .collect()
+    }
+
+    /// Returns whether blocks are C or C++ sources compiled natively from
+    /// files, the blocks listed in `compile_commands.json`.
+    pub fn is_c_family(&self) -> bool {
+        matches!(
+            self.file_extension.as_str(),
+            ".c" | ".h" | ".cc" | ".cpp" | ".cxx" | ".c++" | ".hh" | ".hpp" | ".hxx"
+        ) && self.config.mode == CheckMode::Compile
+            && self.config.backend == Backend::Native
+            && !self.config.stdin
+    }
+
+    /// Builds the compiler's arguments: `-include` with the precompiled
+    /// preamble, if any, then the flags with `{artifact}` and `{outdir}`
+    /// substituted, followed by the source paths.
+    ///
+    /// If any flag contains `{file}`, the sources are placed there instead of
+    /// at the end: a flag that is exactly `{file}` expands to every source, and
+    /// within a longer flag `{file}` is replaced by the first one. Without
+    /// sources (the code is on stdin), `{file}` stands for `-`.
+    fn compiler_args(&self, sources: &[&Path], artifact: &Path, outdir: &Path) -> Vec<String> {
+        let mut sources: Vec<String> = sources
+            .iter()
+            .map(|source| source.display().to_string())
+            .collect();
+        if sources.is_empty() && self.config.stdin {
+            sources.push("-".to_string());
+        }
+        let artifact = artifact.display().to_string();
+        let outdir = outdir.display().to_string();
+
+        let mut args = Vec::new();
+        if let Some(ref header) = self.precompiled_preamble {
+            args.extend(["-include".to_string(), header.display().to_string()]);
+        }
+        let mut placed = false;
+        for flag in &self.config.flags {
+            if flag == FILE_PLACEHOLDER {
+                args.extend(sources.iter().cloned());
+                placed = true;
+                continue;
+            }
+            let mut arg = flag
+                .replace(ARTIFACT_PLACEHOLDER, &artifact)
+                .replace(OUTDIR_PLACEHOLDER, &outdir);
+            if arg.contains(FILE_PLACEHOLDER) {
+                arg = arg.replace(FILE_PLACEHOLDER, sources.first().map_or("", String::as_str));
+                placed = true;
+            }
+            args.push(arg);
+        }
+        if !placed && !self.config.stdin {
+            args.extend(sources);
+        }
+        args
+    }
+
+    /// Fails with [`CompilerNotFound`] if running the compiler failed because
+    /// its executable does not exist, and passes any other outcome through.
+    fn found<T>(&self, result: io::Result<T>) -> Result<io::Result<T>> {
+        match result {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(CompilerNotFound {
+                compiler: self.config.compiler.clone(),
+                language: self.to_string(),
+            }
+            .into()),
+            result => Ok(result),
+        }
+    }
+
+    /// Runs the command to completion, capturing its output.
+    ///
+    /// The configured `env` variables are added to the inherited environment.
+    ///
+    /// Only the spawn step is retried: when the OS refuses to create the
+    /// process with `EAGAIN` (common when many compilers launch at once on a
+    /// loaded machine), it is retried up to `spawn_retries` times with jittered
+    /// exponential backoff. Errors after the process has started are returned as-is.
+    ///
+    /// When `max_output_bytes` is configured, at most that many bytes of each of
+    /// stdout and stderr are kept; the rest is drained and discarded so that
+    /// very chatty compilers cannot exhaust memory.
+    ///
+    /// If `input` is given it is written to the child's stdin concurrently with
+    /// reading its output, so a process that stops reading early cannot deadlock
+    /// us; a resulting broken pipe is ignored.
+    ///
+    /// When `timeout_seconds` is configured, a process still running after that
+    /// long is killed and an [`io::ErrorKind::TimedOut`] error is returned.
+    async fn run_command(
+        &self,
+        command: &mut Command,
+        input: Option<Vec<u8>>,
+    ) -> io::Result<Output> {
+        command.envs(&self.config.env);
+        // Dropping a cancelled compilation must not leave the compiler running
+        command.kill_on_drop(true);
+        let mut child = self.spawn_with_retries(command).await?;
+        let limit = self.config.max_output_bytes.unwrap_or(usize::MAX);
+
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        let run = async {
+            futures::join!(
+                write_input(stdin, input),
+                read_capped(stdout, limit),
+                read_capped(stderr, limit),
+                child.wait()
+            )
+        };
+
+        let (written, stdout, stderr, status) = match self.config.timeout_seconds {
+            Some(seconds) => match tokio::time::timeout(Duration::from_secs(seconds), run).await {
+                Ok(finished) => finished,
+                Err(_) => {
+                    let _ = child.kill().await;
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("timed out after {} seconds", seconds),
+                    ));
+                }
+            },
+            None => run.await,
+        };
+        written?;
+
+        Ok(Output {
+            status: status?,
+            stdout: stdout?,
+            stderr: stderr?,
+        })
+    }
+
+    async fn spawn_with_retries(&self, command: &mut Command) -> io::Result<Child> {
+        retry_spawn(self.config.spawn_retries, &self.config.compiler, || {
+            command.spawn()
+        })
+        .await
+    }
+}
+
+/// Calls `spawn` until it returns anything but a `WouldBlock` error, retrying
+/// at most `retries` times with exponential backoff and jitter.
+async fn retry_spawn<T>(
+    retries: u32,
+    program: &str,
+    mut spawn: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match spawn() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if attempt >= retries {
+                    return Err(e);
+                }
+                let base = SPAWN_RETRY_BASE_DELAY * 2u32.pow(attempt.min(10));
+                let jitter = base.mul_f64(fastrand::f64());
+                log::debug!(
+                    "Spawning '{}' failed ({}), retrying in {:?}",
+                    program,
+                    e,
+                    base + jitter
+                );
+                tokio::time::sleep(base + jitter).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Writes `input` to the child's stdin and closes it.
+///
+/// A broken pipe means the child exited or closed stdin without reading
+/// everything, which is the child's prerogative and not an error here.
+async fn write_input(stdin: Option<ChildStdin>, input: Option<Vec<u8>>) -> io::Result<()> {
+    let (Some(mut stdin), Some(input)) = (stdin, input) else {
+        return Ok(());
+    };
+
+    match stdin.write_all(&input).await {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+/// Reads `reader` to the end, keeping at most `limit` bytes.
+///
+/// The beginning of the stream (usually the most relevant diagnostics) is
+/// preserved; if anything was dropped, a marker noting how many bytes were
+/// discarded is appended.
+async fn read_capped<R: AsyncRead + Unpin>(reader: Option<R>, limit: usize) -> io::Result<Vec<u8>> {
+    let Some(mut reader) = reader else {
+        return Ok(Vec::new());
+    };
+
+    let mut kept = Vec::new();
+    let mut dropped = 0usize;
+    let mut chunk = [0u8; 8192];
+    loop {
+        let n = reader.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        let room = limit.saturating_sub(kept.len()).min(n);
+        kept.extend_from_slice(&chunk[..room]);
+        dropped += n - room;
+    }
+
+    if dropped > 0 {
+        kept.extend_from_slice(format!("\n...(truncated, {} bytes dropped)\n", dropped).as_bytes());
+    }
+
+    Ok(kept)
+}
+
+/// Placeholder in `flags` and `run_command` for the compiled program's path.
+const ARTIFACT_PLACEHOLDER: &str = "{artifact}";
+
+/// Placeholder in `run_command` for the block's source file.
+const SOURCE_PLACEHOLDER: &str = "{source}";
+
+/// Placeholder in `flags` for the source file(s) given to the compiler. When
+/// no flag contains it, the source paths are appended after the flags.
+const FILE_PLACEHOLDER: &str = "{file}";
+
+/// Placeholder in `flags` for a per-block output directory.
+const OUTDIR_PLACEHOLDER: &str = "{outdir}";
+
+/// Returns where the compiler should write the program built from `temp_file`.
+pub(crate) fn artifact_path(temp_file: &Path) -> PathBuf {
+    temp_file.with_extension("out")
+}
+
+/// Joins paths for display in error messages; no paths means the source was
+/// passed on stdin.
+fn display_paths(paths: &[&Path]) -> String {
+    if paths.is_empty() {
+        return "<stdin>".to_string();
+    }
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// One file of a multi-file compilation unit, from a block's `file=` attribute.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    /// File name the code is written to, e.g. `point.h`
+    pub name: String,
+    /// The block's code
+    pub code: String,
+}
+
+impl SourceFile {
+    /// Renders the files of a unit as one listing for reports, each preceded
+    /// by a `==> name <==` line.
+    pub fn listing(files: &[SourceFile]) -> String {
+        files
+            .iter()
+            .map(|file| format!("==> {} <==\n{}", file.name, file.code))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Returns whether `compiler` names an existing file, looking bare names up
+/// on `PATH` the way spawning the compiler would. Nothing is executed.
+pub fn compiler_exists(compiler: &str) -> bool {
+    find_compiler(compiler).is_some()
+}
+
+/// Returns the file `compiler` names, looking bare names up on `PATH`.
+fn find_compiler(compiler: &str) -> Option<PathBuf> {
+    if compiler.contains(std::path::MAIN_SEPARATOR) || compiler.contains('/') {
+        return Path::new(compiler)
+            .is_file()
+            .then(|| PathBuf::from(compiler));
+    }
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(compiler))
+        .find(|path| path.is_file())
+}
+
+/// Identifies the compiler binary by its resolved path, size and
+/// modification time (following symlinks), so that replacing or upgrading
+/// it changes cache keys. Empty if the compiler does not exist.
+pub fn compiler_identity(compiler: &str) -> String {
+    find_compiler(compiler).map_or_else(String::new, |path| file_identity(&path))
+}
+
+/// Identifies the file at `path` by the path, size and modification time
+/// (following symlinks). Empty if the file does not exist.
+fn file_identity(path: &Path) -> String {
+    match std::fs::metadata(path) {
+        Ok(metadata) => format!(
+            "{} {} {:?}",
+            path.display(),
+            metadata.len(),
+            metadata.modified().ok()
+        ),
+        Err(_) => String::new(),
+    }
+}
+
+/// Error returned when a language's compiler (or WASM runtime) does not exist.
+///
+/// Failures carrying this error are reported once per compiler rather than
+/// once per block, since every block of the language fails the same way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompilerNotFound {
+    /// The configured compiler, as written in `book.toml` after expansion
+    pub compiler: String,
+    /// The language (and variant) using it, e.g. `c-parasol`
+    pub language: String,
+}
+
+impl fmt::Display for CompilerNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.compiler.contains(std::path::MAIN_SEPARATOR) || self.compiler.contains('/') {
+            write!(
+                f,
+                "Compiler '{}' for language '{}' does not exist — install it or fix the path in book.toml",
+                self.compiler, self.language
+            )
+        } else {
+            write!(
+                f,
+                "Compiler '{}' for language '{}' was not found on PATH — install it or set an absolute path in book.toml",
+                self.compiler, self.language
+            )
+        }
+    }
+}
+
+impl std::error::Error for CompilerNotFound {}
+
+/// Why [`LanguageRegistry::find_by_fence`] found no language for a fence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FenceResolution {
+    /// No enabled language claims the fence marker, so its blocks are not
+    /// checked
+    NoLanguage,
+    /// The fence's language is configured but does not define the requested
+    /// variant, usually a typo in `variant=`
+    UnknownVariant {
+        /// The language owning the fence marker
+        language: String,
+        /// The variant the block asked for
+        variant: String,
+    },
+}
+
+impl fmt::Display for FenceResolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FenceResolution::NoLanguage => write!(f, "no enabled language claims the fence"),
+            FenceResolution::UnknownVariant { language, variant } => write!(
+                f,
+                "uses variant '{}', which language '{}' does not define",
+                variant, language
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FenceResolution {}
+
+/// Formats the output of a failed process, since compilers differ in which
+/// stream carries their diagnostics.
+///
+/// Each non-empty stream gets its own section headed `stdout:` or `stderr:`;
+/// empty streams are left out.
+fn failure_output(output: &Output) -> String {
+    [("stdout", &output.stdout), ("stderr", &output.stderr)]
+        .into_iter()
+        .filter(|(_, stream)| !stream.is_empty())
+        .map(|(label, stream)| {
+            format!(
+                "{}:\n{}",
+                label,
+                String::from_utf8_lossy(stream).trim_end_matches('\n')
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
 }
 
 /// Registry of available languages for code validation.
@@ -506,7 +1590,7 @@ impl ConfiguredLanguage {
 ///
 /// ```ignore
 /// let config = CheckCodeConfig::from_preprocessor_context(&ctx)?;
-/// let registry = LanguageRegistry::from_config(&config);
+/// let registry = LanguageRegistry::from_config(&config)?;
 ///
 /// // Find a language by fence marker
 /// if let Some(lang) = registry.find_by_fence("c", None) {
@@ -515,17 +1599,45 @@ impl ConfiguredLanguage {
 /// ```
 pub struct LanguageRegistry {
     config: CheckCodeConfig,
+    /// Owning language and, for variant markers, variant of each fence marker
+    fence_index: HashMap<String, (String, Option<String>)>,
+    /// Precompiled preamble header of each language (e.g. `c-parasol`)
+    precompiled_preambles: HashMap<String, PathBuf>,
 }
 
 impl LanguageRegistry {
     /// Creates a new language registry from configuration.
     ///
-    /// The registry stores the configuration and creates language instances
-    /// on demand when `find_by_fence` is called.
-    pub fn from_config(config: &CheckCodeConfig) -> Self {
-        Self {
-            config: config.clone(),
+    /// The registry stores the configuration, indexes the fence markers of
+    /// enabled languages and their variants, and creates language instances on
+    /// demand when `find_by_fence` is called.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming both owners if a fence marker is claimed by
+    /// more than one enabled language or variant, for example `ml`, which
+    /// both `ocaml` and `sml` use by default. Setting `fence_markers`
+    /// explicitly on one of them resolves the collision.
+    pub fn from_config(config: &CheckCodeConfig) -> Result<Self> {
+        config.validate_fence_markers()?;
+
+        let mut fence_index = HashMap::new();
+        for (name, lang_config) in config.languages().iter().filter(|(_, c)| c.enabled) {
+            for marker in lang_config.get_fence_markers(name) {
+                fence_index.insert(marker, (name.clone(), None));
+            }
+            for (variant_name, variant_config) in &lang_config.variants {
+                for marker in &variant_config.fence_markers {
+                    fence_index.insert(marker.clone(), (name.clone(), Some(variant_name.clone())));
+                }
+            }
         }
+
+        Ok(Self {
+            config: config.clone(),
+            fence_index,
+            precompiled_preambles: HashMap::new(),
+        })
     }
 
     /// Finds a language by its fence marker and optional variant.
@@ -535,10 +1647,12 @@ impl LanguageRegistry {
     /// * `fence` - The fence marker from a markdown code block (e.g., "c", "ts")
     /// * `variant` - Optional variant name (e.g., Some("parasol") for C with Parasol compiler)
     ///
-    /// # Returns
+    /// # Errors
     ///
-    /// * `Some(Box<dyn Language>)` if a language with this fence marker exists
-    /// * `None` if no language is configured for this fence marker
+    /// * [`FenceResolution::NoLanguage`] if no enabled language claims the
+    ///   fence marker
+    /// * [`FenceResolution::UnknownVariant`] if the language exists but does
+    ///   not define the requested variant
     ///
     /// # Variant Handling
     ///
@@ -546,75 +1660,1109 @@ impl LanguageRegistry {
     /// overrides the base language configuration. The variant inherits fence_markers and
     /// file_extension from the base language.
     ///
+    /// A fence marker declared in a variant's own `fence_markers` selects that variant
+    /// directly, unless the block names a variant explicitly.
+    ///
     /// # Example
     ///
     /// ```ignore
     /// // Base C language
-    /// if let Some(lang) = registry.find_by_fence("c", None) {
+    /// if let Ok(lang) = registry.find_by_fence("c", None) {
     ///     println!("Found language: {}", lang);
     /// }
     ///
     /// // Parasol variant of C
-    /// if let Some(lang) = registry.find_by_fence("c", Some("parasol")) {
+    /// if let Ok(lang) = registry.find_by_fence("c", Some("parasol")) {
     ///     println!("Found language: {}", lang);
     /// }
     /// ```
-    pub fn find_by_fence(&self, fence: &str, variant: Option<&str>) -> Option<ConfiguredLanguage> {
-        // Find the base language config by fence marker (using resolved fence markers)
-        let (lang_name, base_config) = self.config.languages().iter().find(|(name, config)| {
-            if !config.enabled {
-                return false;
+    pub fn find_by_fence(
+        &self,
+        fence: &str,
+        variant: Option<&str>,
+    ) -> Result<ConfiguredLanguage, FenceResolution> {
+        let (lang_name, base_config, variant) = self.resolve(fence, variant)?;
+        let mut language = self.configure_with_global(lang_name, base_config, variant);
+        language.precompiled_preamble = self
+            .precompiled_preambles
+            .get(&language.to_string())
+            .cloned();
+        Ok(language)
+    }
+
+    /// Precompiles the preamble of every enabled language and variant with
+    /// `precompile_preamble` set into `dir`, so that languages found afterwards
+    /// include the precompiled header instead of the preamble.
+    ///
+    /// Languages whose preamble fails to precompile keep prepending it; the
+    /// returned errors say why, for reporting as warnings.
+    pub async fn precompile_preambles(&mut self, dir: &Path) -> Vec<anyhow::Error> {
+        let mut errors = Vec::new();
+        for (language, _) in self.enabled_languages() {
+            if !language.config.precompile_preamble || language.config.preamble.is_none() {
+                continue;
             }
-            let fence_markers = config.get_fence_markers(name);
-            fence_markers.contains(&fence.to_string())
-        })?;
+            match language.precompile_preamble(dir).await {
+                Ok(header) => {
+                    self.precompiled_preambles
+                        .insert(language.to_string(), header);
+                }
+                Err(error) => errors.push(error),
+            }
+        }
+        errors
+    }
 
-        // If no variant is specified, create base language with resolved fence markers
-        let variant_name = match variant {
-            None => {
-                // Get resolved fence markers for the base language
-                let resolved_fence_markers = base_config.get_fence_markers(lang_name);
-
-                // Create config with resolved fence markers
-                let resolved_config = crate::config::LanguageConfig {
-                    enabled: base_config.enabled,
-                    compiler: base_config.compiler.clone(),
-                    flags: base_config.flags.clone(),
-                    preamble: base_config.preamble.clone(),
-                    fence_markers: resolved_fence_markers,
-                    variants: base_config.variants.clone(),
-                };
+    /// Returns every enabled language and each of its variants, sorted by
+    /// name, with the fence markers that select it directly.
+    ///
+    /// A variant is listed with its own `fence_markers` only; it can also be
+    /// selected with `variant=<name>` on any of the base language's markers.
+    #[allow(dead_code)] // Used by CLI binary
+    pub fn enabled_languages(&self) -> Vec<(ConfiguredLanguage, Vec<String>)> {
+        let mut languages: Vec<_> = self
+            .config
+            .languages()
+            .iter()
+            .filter(|(_, config)| config.enabled)
+            .collect();
+        languages.sort_by_key(|(name, _)| *name);
 
-                return Some(ConfiguredLanguage::new(
-                    lang_name.clone(),
-                    None,
-                    resolved_config,
+        let mut enabled = Vec::new();
+        for (name, config) in languages {
+            enabled.push((
+                self.configure_with_global(name, config, None),
+                config.get_fence_markers(name),
+            ));
+
+            let mut variants: Vec<_> = config.variants.iter().collect();
+            variants.sort_by_key(|(variant_name, _)| *variant_name);
+            for (variant_name, variant_config) in variants {
+                enabled.push((
+                    self.configure_with_global(name, config, Some((variant_name, variant_config))),
+                    variant_config.fence_markers.clone(),
                 ));
             }
-            Some(v) => v,
-        };
+        }
+        enabled
+    }
 
-        // Look up the variant config
-        let variant_config = base_config.variants.get(variant_name)?;
+    /// Returns every enabled language and variant whose compiler does not
+    /// exist (see [`compiler_exists`]), so a build can fail before collecting
+    /// any blocks. No compiler is run.
+    pub fn missing_compilers(&self) -> Vec<CompilerNotFound> {
+        self.enabled_languages()
+            .into_iter()
+            .filter(|(language, _)| !compiler_exists(language.compiler()))
+            .map(|(language, _)| CompilerNotFound {
+                compiler: language.compiler().to_string(),
+                language: language.to_string(),
+            })
+            .collect()
+    }
 
-        // Get resolved fence markers from base config
+    /// Renders each enabled language and variant with its resolved compiler,
+    /// flags, allowed block flags, backend, environment, run command, and
+    /// fence markers, as shown by the `validate` command and `allow --print`.
+    #[allow(dead_code)] // Used by CLI binary
+    pub fn describe(&self) -> String {
+        let mut out = String::new();
+        for (language, fence_markers) in self.enabled_languages() {
+            let _ = writeln!(out, "{}", language);
+            let _ = writeln!(out, "  compiler: {}", language.compiler());
+            let _ = writeln!(out, "  flags: {:?}", language.flags());
+            if !language.config.allowed_block_flags.is_empty() {
+                let _ = writeln!(
+                    out,
+                    "  allowed block flags: {:?}",
+                    language.config.allowed_block_flags
+                );
+            }
+            if language.config.backend == Backend::Wasm {
+                let _ = writeln!(out, "  backend: wasm");
+            }
+            if let Some(ref module) = language.config.module {
+                let _ = writeln!(out, "  module: {}", module.display());
+            }
+            if !language.config.env.is_empty() {
+                let env: BTreeMap<_, _> = language.config.env.iter().collect();
+                let env: Vec<_> = env
+                    .into_iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect();
+                let _ = writeln!(out, "  env: {}", env.join(", "));
+            }
+            if let Some(ref run_command) = language.config.run_command {
+                let _ = writeln!(out, "  run_command: {:?}", run_command);
+            }
+            let _ = match language.variant() {
+                Some(variant) if fence_markers.is_empty() => {
+                    writeln!(out, "  fence markers: (variant={} only)", variant)
+                }
+                _ => writeln!(out, "  fence markers: {}", fence_markers.join(", ")),
+            };
+        }
+        out
+    }
+
+    /// Like [`Self::configure`], with the book-wide `global_preamble` placed
+    /// before the language's preamble.
+    fn configure_with_global(
+        &self,
+        lang_name: &str,
+        base_config: &LanguageConfig,
+        variant: Option<(&str, &VariantConfig)>,
+    ) -> ConfiguredLanguage {
+        let mut language = Self::configure(lang_name, base_config, variant);
+        if let Some(global) = self.config.global_preamble.as_deref() {
+            language.config.preamble = Some(match language.config.preamble.take() {
+                Some(preamble) => {
+                    let separator = if global.ends_with('\n') { "" } else { "\n" };
+                    format!("{}{}{}", global, separator, preamble)
+                }
+                None => global.to_string(),
+            });
+        }
+        language
+    }
+
+    /// Builds the language for `lang_name`, merging in the variant if one
+    /// is given.
+    fn configure(
+        lang_name: &str,
+        base_config: &LanguageConfig,
+        variant: Option<(&str, &VariantConfig)>,
+    ) -> ConfiguredLanguage {
+        // Get resolved fence markers for the base language
         let resolved_fence_markers = base_config.get_fence_markers(lang_name);
 
-        // Create merged config: variant settings override base settings
+        // If no variant is specified, create base language with resolved fence markers
+        let Some((variant_name, variant_config)) = variant else {
+            let resolved_config = crate::config::LanguageConfig {
+                fence_markers: resolved_fence_markers,
+                ..base_config.clone()
+            };
+            return ConfiguredLanguage::new(lang_name.to_string(), None, resolved_config);
+        };
+
+        // Create merged config: variant settings override base settings, or
+        // extend them when the variant opts into inheriting flags or preamble.
+        // Other settings (spawn retries, mode, patterns) are inherited from the base,
+        // the timeout falls back to the base when the variant sets none, and
+        // the variant's environment variables are merged over the base's
+        let flags = if variant_config.inherit_flags {
+            [base_config.flags.as_slice(), &variant_config.flags].concat()
+        } else {
+            variant_config.flags.clone()
+        };
+        let preamble = match (&base_config.preamble, &variant_config.preamble) {
+            (Some(base), Some(variant)) if variant_config.inherit_preamble => {
+                let separator = if base.ends_with('\n') { "" } else { "\n" };
+                Some(format!("{}{}{}", base, separator, variant))
+            }
+            (Some(base), None) if variant_config.inherit_preamble => Some(base.clone()),
+            _ => variant_config.preamble.clone(),
+        };
         let merged_config = crate::config::LanguageConfig {
-            enabled: base_config.enabled,
             compiler: variant_config.compiler.clone(),
-            flags: variant_config.flags.clone(),
-            preamble: variant_config.preamble.clone(),
+            flags,
+            preamble,
+            postamble: variant_config.postamble.clone(),
             fence_markers: resolved_fence_markers,
-            variants: std::collections::HashMap::new(), // Variants don't inherit variants
+            timeout_seconds: variant_config
+                .timeout_seconds
+                .or(base_config.timeout_seconds),
+            env: base_config
+                .env
+                .iter()
+                .chain(&variant_config.env)
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            variants: HashMap::new(), // Variants don't inherit variants
+            ..base_config.clone()
         };
 
         // Create a new language with the base language and variant
-        Some(ConfiguredLanguage::new(
-            lang_name.clone(),
+        ConfiguredLanguage::new(
+            lang_name.to_string(),
             Some(variant_name.to_string()),
             merged_config,
+        )
+    }
+
+    /// Returns the name a fence marker resolves to, such as `c` or
+    /// `c-parasol`, without building a [`ConfiguredLanguage`].
+    ///
+    /// Resolution follows exactly the same rules as [`Self::find_by_fence`],
+    /// so tooling can ask which language a block would be checked as.
+    /// Returns `None` if the fence (or the requested variant) is not
+    /// configured.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let registry = LanguageRegistry::from_config(&config)?;
+    /// assert_eq!(registry.resolve_language_name("c", Some("parasol")).as_deref(), Some("c-parasol"));
+    /// ```
+    #[allow(dead_code)] // Public library API for external tooling
+    pub fn resolve_language_name(&self, fence: &str, variant: Option<&str>) -> Option<String> {
+        let (lang_name, _, variant) = self.resolve(fence, variant).ok()?;
+        Some(match variant {
+            Some((variant_name, _)) => format!("{}-{}", lang_name, variant_name),
+            None => lang_name.clone(),
+        })
+    }
+
+    /// Returns the fence markers (including variant markers) of enabled
+    /// languages that use hidden lines.
+    pub fn hidden_line_fences(&self) -> HashSet<String> {
+        self.config
+            .languages()
+            .iter()
+            .filter(|(_, config)| config.enabled && config.hidden_lines)
+            .flat_map(|(name, config)| {
+                config.get_fence_markers(name).into_iter().chain(
+                    config
+                        .variants
+                        .values()
+                        .flat_map(|v| v.fence_markers.iter().cloned()),
+                )
+            })
+            .collect()
+    }
+
+    /// Resolves `fence` and an optional explicit variant to the owning
+    /// language and, if any, the selected variant.
+    ///
+    /// The base language config is found by fence marker (using resolved
+    /// fence markers), falling back to a variant that declares the marker
+    /// itself; an explicit variant takes precedence over the implied one.
+    #[allow(clippy::type_complexity)]
+    fn resolve<'a>(
+        &'a self,
+        fence: &str,
+        variant: Option<&'a str>,
+    ) -> Result<
+        (
+            &'a String,
+            &'a LanguageConfig,
+            Option<(&'a str, &'a VariantConfig)>,
+        ),
+        FenceResolution,
+    > {
+        let (lang_name, base_config, implied_variant) = self
+            .lookup_fence(fence)
+            .ok_or(FenceResolution::NoLanguage)?;
+        match variant.or(implied_variant) {
+            None => Ok((lang_name, base_config, None)),
+            Some(variant_name) => {
+                let variant_config = base_config.variants.get(variant_name).ok_or_else(|| {
+                    FenceResolution::UnknownVariant {
+                        language: lang_name.clone(),
+                        variant: variant_name.to_string(),
+                    }
+                })?;
+                Ok((lang_name, base_config, Some((variant_name, variant_config))))
+            }
+        }
+    }
+
+    /// Finds the enabled language owning `fence`, plus the variant implied by the
+    /// marker when it is declared by a variant rather than the base language.
+    fn lookup_fence(&self, fence: &str) -> Option<(&String, &LanguageConfig, Option<&str>)> {
+        let (name, variant) = self.fence_index.get(fence)?;
+        let (name, config) = self.config.languages().get_key_value(name)?;
+        Some((name, config, variant.as_deref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_capped_truncates_and_reports_dropped_bytes() {
+        let output = read_capped(Some(&b"error: first\nerror: second\n"[..]), 12)
+            .await
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("error: first"));
+        assert!(output.contains("...(truncated, 15 bytes dropped)"));
+
+        let output = read_capped(Some(&b"short"[..]), 12).await.unwrap();
+        assert_eq!(output, b"short");
+    }
+
+    #[test]
+    fn test_provenance_line() {
+        let mut config: LanguageConfig = toml::from_str(r#"compiler = "gcc""#).unwrap();
+        let language = ConfiguredLanguage::new("c".to_string(), None, config.clone());
+        assert_eq!(language.provenance_line("a.md block #3"), None);
+
+        config.provenance_comment = true;
+        let language = ConfiguredLanguage::new("c".to_string(), None, config.clone());
+        assert_eq!(
+            language.provenance_line("a.md block #3").as_deref(),
+            Some("// generated from a.md block #3 by mdbook-check-code\n")
+        );
+
+        config.comment_prefix = Some("(*".to_string());
+        config.comment_suffix = Some("*)".to_string());
+        let language = ConfiguredLanguage::new("ocaml".to_string(), None, config);
+        assert_eq!(
+            language.provenance_line("b.md block #0").as_deref(),
+            Some("(* generated from b.md block #0 by mdbook-check-code *)\n")
+        );
+    }
+
+    #[test]
+    fn test_resolve_language_name() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "gcc"
+            fence_markers = ["c"]
+
+            [languages.c.variants.parasol]
+            compiler = "clang"
+            fence_markers = ["parasol-c"]
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+
+        assert_eq!(
+            registry.resolve_language_name("c", None).as_deref(),
+            Some("c")
+        );
+        assert_eq!(
+            registry
+                .resolve_language_name("c", Some("parasol"))
+                .as_deref(),
+            Some("c-parasol")
+        );
+        assert_eq!(
+            registry.resolve_language_name("parasol-c", None).as_deref(),
+            Some("c-parasol")
+        );
+        assert_eq!(registry.resolve_language_name("c", Some("missing")), None);
+        assert_eq!(registry.resolve_language_name("rust", None), None);
+    }
+
+    #[test]
+    fn test_registry_rejects_shared_default_fence_marker() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.ocaml]
+            compiler = "ocamlc"
+
+            [languages.sml]
+            compiler = "mlton"
+            "#,
+        )
+        .unwrap();
+        let err = LanguageRegistry::from_config(&config)
+            .err()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            err,
+            "Fence marker 'ml' is claimed by both language 'ocaml' and language 'sml'"
+        );
+
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.ocaml]
+            compiler = "ocamlc"
+
+            [languages.sml]
+            compiler = "mlton"
+            fence_markers = ["sml"]
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+        assert_eq!(
+            registry.resolve_language_name("ml", None).as_deref(),
+            Some("ocaml")
+        );
+        assert_eq!(
+            registry.resolve_language_name("sml", None).as_deref(),
+            Some("sml")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failure_reports_both_streams() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let compile = |script: &str| {
+            let mut config: LanguageConfig = toml::from_str(r#"compiler = "sh""#).unwrap();
+            config.flags = vec!["-c".to_string(), script.to_string()];
+            let language = ConfiguredLanguage::new("typescript".to_string(), None, config);
+            let temp_file = temp_dir.path().join("block.ts");
+            async move { language.compile("", "", &temp_file, "a.md block #0").await }
+        };
+
+        // tsc-style: diagnostics on stdout, unrelated noise on stderr
+        let err = compile("echo 'a.ts(1,1): error TS2304'; echo 'warning: noise' >&2; exit 2")
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("stdout:\na.ts(1,1): error TS2304\n\nstderr:\nwarning: noise"));
+
+        let err = compile("echo 'a.ts(1,1): error TS2304'; exit 2")
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("\n\nstdout:\na.ts(1,1): error TS2304"));
+        assert!(!err.to_string().contains("stderr:"));
+    }
+
+    #[test]
+    fn test_variant_flags_and_preamble_inheritance() {
+        let config: CheckCodeConfig = toml::from_str(
+            r##"
+            [languages.c]
+            compiler = "gcc"
+            flags = ["-Wall", "-Werror"]
+            preamble = "#include <stdio.h>"
+            fence_markers = ["c"]
+
+            [languages.c.variants.replace]
+            compiler = "clang"
+            flags = ["-target", "parasol"]
+            preamble = "#include <parasol.h>"
+
+            [languages.c.variants.inherit]
+            compiler = "clang"
+            flags = ["-target", "parasol"]
+            preamble = "#include <parasol.h>"
+            inherit_flags = true
+            inherit_preamble = true
+            "##,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+
+        let replace = registry.find_by_fence("c", Some("replace")).unwrap();
+        assert_eq!(replace.flags(), ["-target", "parasol"]);
+        assert_eq!(
+            replace.config.preamble.as_deref(),
+            Some("#include <parasol.h>")
+        );
+
+        let inherit = registry.find_by_fence("c", Some("inherit")).unwrap();
+        assert_eq!(inherit.flags(), ["-Wall", "-Werror", "-target", "parasol"]);
+        assert_eq!(
+            inherit.config.preamble.as_deref(),
+            Some("#include <stdio.h>\n#include <parasol.h>")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_env_reaches_compiler_and_variants_merge_it() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "sh"
+            flags = ["-c", "echo \"$GREETING $TARGET\"; exit 1"]
+            fence_markers = ["c"]
+            env = { GREETING = "hello", TARGET = "base" }
+
+            [languages.c.variants.parasol]
+            compiler = "sh"
+            flags = ["-c", "echo \"$GREETING $TARGET\"; exit 1"]
+            env = { TARGET = "variant" }
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let temp_file = temp_dir.path().join("block.c");
+
+        for (variant, expected) in [(None, "hello base"), (Some("parasol"), "hello variant")] {
+            let language = registry.find_by_fence("c", variant).unwrap();
+            let err = language
+                .compile("", "", &temp_file, "a.md block #0")
+                .await
+                .unwrap_err();
+            assert!(
+                err.to_string().ends_with(&format!("stdout:\n{}", expected)),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_assemble_source_follows_layout() {
+        let mut config: LanguageConfig = toml::from_str(
+            r##"
+            compiler = "gcc"
+            preamble = "#include <stdio.h>"
+            postamble = "int main(void) { return 0; }"
+            "##,
+        )
+        .unwrap();
+        let language = ConfiguredLanguage::new("c".to_string(), None, config.clone());
+        assert_eq!(
+            language.assemble_source("int x;\n", "x = 1;\n"),
+            "#include <stdio.h>\n\nint x;\n\nx = 1;\n\nint main(void) { return 0; }"
+        );
+        assert_eq!(language.block_source("", "x = 1;\n"), "x = 1;\n");
+        assert_eq!(language.block_start_line("int x;\n", "x = 1;\n"), 5);
+
+        config.source_layout = vec![
+            SourceSegment::Propagated,
+            SourceSegment::Block,
+            SourceSegment::Preamble,
+        ];
+        let language = ConfiguredLanguage::new("c".to_string(), None, config);
+        assert_eq!(
+            language.assemble_source("int x;\n", "x = 1;"),
+            "int x;\n\nx = 1;\n\n#include <stdio.h>"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_precompile_preambles() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "true"
+            flags = ["-fsyntax-only", "-o", "{artifact}", "-std=c11"]
+            preamble = "int shared;"
+            precompile_preamble = true
+
+            [languages.c.variants.broken]
+            compiler = "false"
+            preamble = "int shared;"
+            "#,
+        )
+        .unwrap();
+        let mut registry = LanguageRegistry::from_config(&config).unwrap();
+
+        let errors = registry.precompile_preambles(temp_dir.path()).await;
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].to_string().contains("c-broken preamble failed"));
+
+        // The precompiled header is included instead of prepending the preamble
+        let c = registry.find_by_fence("c", None).unwrap();
+        let header = temp_dir.path().join("c_preamble.h");
+        assert_eq!(std::fs::read_to_string(&header).unwrap(), "int shared;");
+        assert_eq!(c.assemble_source("", "int x;"), "int x;");
+        let args = c.compiler_args(
+            &[Path::new("a.c")],
+            Path::new("a.out"),
+            Path::new("a.outdir"),
+        );
+        assert_eq!(
+            args[..2],
+            ["-include".to_string(), header.display().to_string()]
+        );
+
+        // A failed variant falls back to prepending its preamble
+        let broken = registry.find_by_fence("c", Some("broken")).unwrap();
+        assert_eq!(
+            broken.assemble_source("", "int x;"),
+            "int shared;\n\nint x;"
+        );
+    }
+
+    #[test]
+    fn test_find_by_fence_resolution() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "gcc"
+
+            [languages.c.variants.parasol]
+            compiler = "clang"
+            fence_markers = ["parasol-c"]
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+
+        assert!(registry.find_by_fence("c", Some("parasol")).is_ok());
+        assert_eq!(
+            registry.find_by_fence("rust", None).err(),
+            Some(FenceResolution::NoLanguage)
+        );
+        assert_eq!(
+            registry.find_by_fence("rust", Some("nightly")).err(),
+            Some(FenceResolution::NoLanguage)
+        );
+        let unknown = registry.find_by_fence("c", Some("parsol")).err().unwrap();
+        assert_eq!(
+            unknown,
+            FenceResolution::UnknownVariant {
+                language: "c".to_string(),
+                variant: "parsol".to_string(),
+            }
+        );
+        assert_eq!(
+            unknown.to_string(),
+            "uses variant 'parsol', which language 'c' does not define"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_global_preamble_precedes_language_preamble() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config: CheckCodeConfig = toml::from_str(
+            r##"
+            global_preamble = "/* SPDX-License-Identifier: MIT */"
+
+            [languages.c]
+            compiler = "true"
+            preamble = "#include <stdio.h>"
+
+            [languages.c.variants.bare]
+            compiler = "true"
+            "##,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+
+        let path = temp_dir.path().join("block_0.c");
+        registry
+            .find_by_fence("c", None)
+            .unwrap()
+            .compile("", "int x;", &path, "a.md")
+            .await
+            .unwrap();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .ends_with("/* SPDX-License-Identifier: MIT */\n#include <stdio.h>\n\nint x;"));
+
+        // A variant without a preamble still gets the global preamble
+        let path = temp_dir.path().join("block_1.c");
+        registry
+            .find_by_fence("c", Some("bare"))
+            .unwrap()
+            .compile("", "int x;", &path, "a.md")
+            .await
+            .unwrap();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .ends_with("/* SPDX-License-Identifier: MIT */\n\nint x;"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_wasm_backend_runs_module() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Stand-in runtime recording its arguments and accepting sources
+        // containing an `ok` line
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let runtime = temp_dir.path().join("runtime");
+        std::fs::write(
+            &runtime,
+            "#!/bin/sh\nprintf '%s\\n' \"$@\" > \"$(dirname \"$0\")/args\"\ngrep -qx ok\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&runtime, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let module = temp_dir.path().join("check.wasm");
+
+        let mut config: LanguageConfig = toml::from_str(
+            r#"
+            compiler = "runtime"
+            backend = "wasm"
+            flags = ["--fuel=1000"]
+            "#,
+        )
+        .unwrap();
+        config.compiler = runtime.display().to_string();
+        config.module = Some(module.clone());
+        let language = ConfiguredLanguage::new("wat".to_string(), None, config);
+
+        let path = temp_dir.path().join("block_0.wat");
+        assert_eq!(
+            language.compile("", "ok", &path, "a.md").await.unwrap(),
+            None
+        );
+        assert_eq!(
+            std::fs::read_to_string(temp_dir.path().join("args")).unwrap(),
+            format!("run\n--fuel=1000\n{}\n", module.display())
+        );
+
+        let err = language
+            .compile("", "bad", &path, "a.md")
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("validation failed"));
+
+        // Block flags cannot grant host access either
+        let err = language
+            .with_extra_flags(&["--dir=/".to_string()])
+            .compile("", "ok", &path, "a.md")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("access to the host"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_fingerprint_covers_source_settings() {
+        let fingerprint = |settings: &str| {
+            let config: LanguageConfig =
+                toml::from_str(&format!("compiler = \"true\"\n{}", settings)).unwrap();
+            ConfiguredLanguage::new("c".to_string(), None, config).fingerprint()
+        };
+
+        let base = fingerprint("");
+        for settings in [
+            "file_extension = \".cc\"",
+            "file_name = \"Dockerfile\"",
+            "stdin = true",
+            "preserve_line_endings = true",
+        ] {
+            assert_ne!(fingerprint(settings), base, "{}", settings);
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_tracks_compiler_binary() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let compiler = temp_dir.path().join("cc");
+        std::fs::write(&compiler, "#!/bin/sh\n").unwrap();
+        let config: LanguageConfig =
+            toml::from_str(&format!("compiler = {:?}", compiler.display().to_string())).unwrap();
+        let language = ConfiguredLanguage::new("c".to_string(), None, config);
+
+        let before = language.fingerprint();
+        assert_eq!(language.fingerprint(), before);
+        // An upgraded binary differs in size
+        std::fs::write(&compiler, "#!/bin/sh\nexit 0\n").unwrap();
+        assert_ne!(language.fingerprint(), before);
+
+        assert_eq!(compiler_identity("no-such-compiler-xyz"), "");
+
+        // So does a rebuilt WASM module
+        let module = temp_dir.path().join("check.wasm");
+        std::fs::write(&module, "\0asm").unwrap();
+        let config: LanguageConfig = toml::from_str(&format!(
+            "compiler = \"wasmtime\"\nbackend = \"wasm\"\nmodule = {:?}",
+            module.display().to_string()
         ))
+        .unwrap();
+        let language = ConfiguredLanguage::new("wat".to_string(), None, config);
+        let before = language.fingerprint();
+        std::fs::write(&module, "\0asm\x01").unwrap();
+        assert_ne!(language.fingerprint(), before);
+    }
+
+    #[tokio::test]
+    async fn test_retry_spawn() {
+        let would_block = || io::Error::from(io::ErrorKind::WouldBlock);
+
+        // Succeeds after two WouldBlock errors
+        let mut calls = 0;
+        let result = retry_spawn(3, "cc", || {
+            calls += 1;
+            if calls <= 2 {
+                Err(would_block())
+            } else {
+                Ok(calls)
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+
+        // Gives up after the initial attempt and `retries` retries
+        let mut calls = 0;
+        let result: io::Result<()> = retry_spawn(2, "cc", || {
+            calls += 1;
+            Err(would_block())
+        })
+        .await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(calls, 3);
+
+        // Other errors are returned at once
+        let mut calls = 0;
+        let result: io::Result<()> = retry_spawn(5, "cc", || {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        })
+        .await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(calls, 1);
+    }
+
+    #[tokio::test]
+    async fn test_crlf_normalized_to_lf() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let language = |settings: &str| {
+            let config: LanguageConfig = toml::from_str(&format!(
+                "compiler = \"true\"\npreamble = \"#include <stdio.h>\\r\"\n{}",
+                settings
+            ))
+            .unwrap();
+            ConfiguredLanguage::new("c".to_string(), None, config)
+        };
+        let code = "int x;\r\nint y;\r\n";
+
+        let path = temp_dir.path().join("block_0.c");
+        language("")
+            .compile("struct p;\r\n", code, &path, "a.md")
+            .await
+            .unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(!written.contains('\r'), "{:?}", written);
+        assert!(written.ends_with("#include <stdio.h>\n\nstruct p;\n\nint x;\nint y;\n"));
+
+        let path = temp_dir.path().join("block_1.c");
+        language("preserve_line_endings = true")
+            .compile("", code, &path, "a.md")
+            .await
+            .unwrap();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .ends_with("int x;\r\nint y;\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_file_extension_and_file_name_overrides() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let language = |settings: &str| {
+            let config: LanguageConfig = toml::from_str(&format!(
+                "compiler = \"sh\"\nflags = [\"-c\", \"test -f \\\"$0\\\"\"]\n{}",
+                settings
+            ))
+            .unwrap();
+            config.validate().unwrap();
+            ConfiguredLanguage::new("cpp".to_string(), None, config)
+        };
+
+        let default = language("");
+        assert_eq!(
+            default.source_path(temp_dir.path(), "block_0"),
+            temp_dir.path().join("block_0.cpp")
+        );
+
+        let cc = language(r#"file_extension = ".cc""#);
+        let path = cc.source_path(temp_dir.path(), "block_0");
+        assert_eq!(path, temp_dir.path().join("block_0.cc"));
+        assert_eq!(
+            language(r#"file_extension = "cxx""#).source_path(temp_dir.path(), "block_0"),
+            temp_dir.path().join("block_0.cxx")
+        );
+        cc.compile("", "int x;", &path, "a.md").await.unwrap();
+        assert!(path.exists());
+
+        // A complete file name gets a directory per block
+        let dockerfile = language(r#"file_name = "Dockerfile""#);
+        let path = dockerfile.source_path(temp_dir.path(), "block_1");
+        assert_eq!(path, temp_dir.path().join("block_1").join("Dockerfile"));
+        dockerfile
+            .compile("", "FROM scratch", &path, "a.md")
+            .await
+            .unwrap();
+        assert!(path.exists());
+
+        let both: LanguageConfig = toml::from_str(
+            "compiler = \"sh\"\nfile_name = \"Dockerfile\"\nfile_extension = \".cc\"",
+        )
+        .unwrap();
+        assert!(both.validate().is_err());
+        let nested: LanguageConfig =
+            toml::from_str("compiler = \"sh\"\nfile_name = \"a/Dockerfile\"").unwrap();
+        assert!(nested.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_command_times_out() {
+        let mut config: LanguageConfig = toml::from_str(r#"compiler = "sh""#).unwrap();
+        config.timeout_seconds = Some(1);
+        let language = ConfiguredLanguage::new("c".to_string(), None, config);
+
+        let mut command = Command::new("sleep");
+        command
+            .arg("30")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let start = std::time::Instant::now();
+        let err = language.run_command(&mut command, None).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_run_command_pipes_input_to_stdin() {
+        let config: LanguageConfig = toml::from_str(r#"compiler = "sh""#).unwrap();
+        let language = ConfiguredLanguage::new("c".to_string(), None, config);
+
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", "grep -q token"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let output = language
+            .run_command(&mut command, Some(b"a token here\n".to_vec()))
+            .await
+            .unwrap();
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_compiler_args_place_sources() {
+        let language = |flags: &[&str], stdin: bool| {
+            let mut config: LanguageConfig = toml::from_str(r#"compiler = "solc""#).unwrap();
+            config.flags = flags.iter().map(|flag| flag.to_string()).collect();
+            config.stdin = stdin;
+            ConfiguredLanguage::new("solidity".to_string(), None, config)
+        };
+        let (a, b) = (Path::new("/tmp/a.sol"), Path::new("/tmp/b.sol"));
+        let (artifact, outdir) = (Path::new("/tmp/a.out"), Path::new("/tmp/a.outdir"));
+
+        // Appended after the flags by default
+        assert_eq!(
+            language(&["-o", "{artifact}"], false).compiler_args(&[a], artifact, outdir),
+            vec!["-o", "/tmp/a.out", "/tmp/a.sol"]
+        );
+        // Placed at {file}, with {outdir} substituted
+        assert_eq!(
+            language(&["--bin", "{file}", "-o", "{outdir}"], false).compiler_args(
+                &[a, b],
+                artifact,
+                outdir
+            ),
+            vec!["--bin", "/tmp/a.sol", "/tmp/b.sol", "-o", "/tmp/a.outdir"]
+        );
+        assert_eq!(
+            language(&["--input={file}"], false).compiler_args(&[a], artifact, outdir),
+            vec!["--input=/tmp/a.sol"]
+        );
+        // Code on stdin has no path: {file} stands for -, and nothing is appended
+        assert_eq!(
+            language(&["{file}"], true).compiler_args(&[], artifact, outdir),
+            vec!["-"]
+        );
+        assert_eq!(
+            language(&["-fsyntax-only", "-"], true).compiler_args(&[], artifact, outdir),
+            vec!["-fsyntax-only", "-"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compile_from_stdin() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let temp_file = temp_dir.path().join("block.c");
+        let compile = |script: &str| {
+            let mut config: LanguageConfig = toml::from_str(r#"compiler = "sh""#).unwrap();
+            config.flags = vec!["-c".to_string(), script.to_string()];
+            config.stdin = true;
+            ConfiguredLanguage::new("c".to_string(), None, config)
+        };
+
+        let grep = compile("grep -q token");
+        assert!(grep
+            .compile("", "a token", &temp_file, "a.md")
+            .await
+            .is_ok());
+        let err = grep
+            .compile("", "nothing", &temp_file, "a.md")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("File: <stdin>"));
+        assert!(!temp_file.exists());
+
+        // A compiler that exits without reading its input must not deadlock
+        let large = "x".repeat(4 * 1024 * 1024);
+        assert!(compile("exit 0")
+            .compile("", &large, &temp_file, "a.md")
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn test_describe_shows_resolved_settings() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "gcc"
+            flags = ["-fsyntax-only"]
+            allowed_block_flags = ["-D"]
+            env = { LANG = "C", CC_OPTS = "-x" }
+            run_command = ["{artifact}"]
+
+            [languages.c.variants.clang]
+            compiler = "clang"
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+
+        assert_eq!(
+            registry.describe(),
+            "c\n  compiler: gcc\n  flags: [\"-fsyntax-only\"]\n  allowed block flags: [\"-D\"]\n  env: CC_OPTS=-x, LANG=C\n  run_command: [\"{artifact}\"]\n  fence markers: c, h\n\
+             c-clang\n  compiler: clang\n  flags: []\n  allowed block flags: [\"-D\"]\n  env: CC_OPTS=-x, LANG=C\n  run_command: [\"{artifact}\"]\n  fence markers: (variant=clang only)\n"
+        );
+    }
+
+    #[test]
+    fn test_describe_shows_wasm_backend() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "wasmtime"
+            backend = "wasm"
+            module = "validators/c.wasm"
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+
+        assert_eq!(
+            registry.describe(),
+            "c\n  compiler: wasmtime\n  flags: []\n  backend: wasm\n  module: validators/c.wasm\n  fence markers: c, h\n"
+        );
+    }
+
+    #[test]
+    fn test_missing_compilers_reports_all() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "sh"
+
+            [languages.c.variants.parasol]
+            compiler = "/nonexistent/bin/clang"
+
+            [languages.typescript]
+            compiler = "mdbook-check-code-no-such-tsc"
+
+            [languages.solidity]
+            enabled = false
+            compiler = "mdbook-check-code-no-such-solc"
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+
+        let missing: Vec<_> = registry
+            .missing_compilers()
+            .into_iter()
+            .map(|m| (m.language, m.compiler))
+            .collect();
+        assert_eq!(
+            missing,
+            [
+                (
+                    "c-parasol".to_string(),
+                    "/nonexistent/bin/clang".to_string()
+                ),
+                (
+                    "typescript".to_string(),
+                    "mdbook-check-code-no-such-tsc".to_string()
+                ),
+            ]
+        );
     }
 }
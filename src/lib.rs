@@ -7,20 +7,69 @@
 //! ## Public API
 //!
 //! The main public interface is [`CheckCodePreprocessor`], which implements the
-//! mdBook `Preprocessor` trait.
+//! mdBook `Preprocessor` trait. [`CheckCodePreprocessor::check_markdown`] checks
+//! a single Markdown document outside of mdBook, returning a
+//! [`CompilationResult`] per block.
 //!
 //! Additional utilities:
 //! - [`get_language_metadata`] - Get metadata for a language (fence markers and file extension)
 //! - [`LanguageMetadata`] - Metadata structure for a language
+//! - [`CheckCodePreprocessor::configured_languages`] - List the languages a book
+//!   configures, as [`LanguageSummary`] values
+//! - [`LanguageRegistry::resolve_language_name`] - Resolve a fence marker to the
+//!   language it would be checked as under a [`CheckCodeConfig`]
+//! - [`extract_code_blocks`] - Parse the code blocks of a Markdown document
+//!   into [`CodeBlock`] values, configured by [`ExtractOptions`]
+//!
+//! ## Extracting Code Blocks
+//!
+//! Each [`CodeBlock`] carries both the code shown in the rendered book and the
+//! code sent to the compiler, which differ when hidden lines are enabled for
+//! the block's fence marker:
+//!
+//! ````
+//! use mdbook_check_code::{extract_code_blocks, ExtractOptions};
+//!
+//! let markdown = "```rust,propagate\n# fn main() {\nlet x = 1;\n# }\n```\n\n```text,ignore\nnot code\n```\n";
+//! let options = ExtractOptions {
+//!     hidden_line_fences: ["rust".to_string()].into(),
+//!     ..ExtractOptions::default()
+//! };
+//!
+//! let blocks = extract_code_blocks(markdown, &options);
+//! assert_eq!(blocks.len(), 2);
+//! assert_eq!(blocks[0].language, "rust");
+//! assert!(blocks[0].propagate);
+//! assert_eq!(blocks[0].variant, None);
+//! assert_eq!(blocks[0].display_code, "let x = 1;\n");
+//! assert_eq!(blocks[0].compile_code, "fn main() {\nlet x = 1;\n}\n");
+//! assert!(blocks[1].ignore);
+//! ````
 
 mod approval;
+mod block_cache;
+mod cache;
+mod chapter_cache;
 mod compilation;
 mod config;
+mod diff;
 mod extractor;
 mod language;
 mod preprocessor;
 mod reporting;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod tangle;
 mod task_collector;
 
-pub use language::{get_language_metadata, LanguageMetadata};
-pub use preprocessor::CheckCodePreprocessor;
+pub use compilation::{CompilationResult, TaskKind};
+pub use config::CheckCodeConfig;
+pub use extractor::{extract_code_blocks, CodeBlock, ExtractOptions};
+pub use language::{
+    get_language_metadata, ConfiguredLanguage, FenceResolution, LanguageMetadata, LanguageRegistry,
+    LanguageSummary,
+};
+pub use preprocessor::{
+    preprocessor_name, CheckCodePreprocessor, ASSUME_APPROVED_ENV, CHECK_MARKDOWN_PATH,
+    PREPROCESSOR_NAME, PREPROCESSOR_NAME_ENV,
+};
@@ -1,20 +1,30 @@
 mod approval;
+mod block_cache;
+mod cache;
+mod chapter_cache;
 mod compilation;
 mod config;
+mod diff;
 mod extractor;
 mod language;
 mod preprocessor;
 mod reporting;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod tangle;
 mod task_collector;
 
 use anyhow::{Context, Result};
 use chrono::Local;
 use clap::{Parser, Subcommand};
+use config::{CheckCodeConfig, Verbosity};
+use language::LanguageRegistry;
 use mdbook::preprocess::{CmdPreprocessor, Preprocessor};
 use preprocessor::CheckCodePreprocessor;
 use reporting::print_error;
+use serde::Serialize;
 use std::io::{stdin, stdout, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 
 const LONG_ABOUT: &str = r##"A configuration-driven mdBook preprocessor that validates code blocks by compiling
@@ -76,8 +86,13 @@ Language variants are referenced using the `variant=name` attribute:
 ## Environment Variables
 
 - `CLANG` - Path to Sunscreen LLVM clang (required for Parasol C variant)
+- `CHECK_CODE_NAME` - Override the preprocessor name (default "check-code"); the
+  configuration is then read from `[preprocessor.<name>]`
+- `MDBOOK_CHECK_CODE_ASSUME_APPROVED` - Set to "1" to skip the approval check
+  (e.g. in CI, where the approval store starts out empty)
 - `RUST_LOG` - Set to "info" to see detailed compilation logs
   Example: `RUST_LOG=info mdbook build`
+- `NO_COLOR` - Disable colored output (color is only used when stderr is a terminal)
 
 For more information, visit: https://github.com/Sunscreen-tech/mdbook-check-code
 "##;
@@ -93,6 +108,23 @@ For more information, visit: https://github.com/Sunscreen-tech/mdbook-check-code
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+
+    /// Override the `verbosity` setting from book.toml
+    #[arg(long, global = true, value_enum)]
+    verbosity: Option<Verbosity>,
+
+    /// Stop at the first failing block (overrides `fail_fast` in book.toml)
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Show compilation progress (overrides `progress` in book.toml)
+    #[arg(long)]
+    progress: bool,
+
+    /// List what would be compiled without running any compiler (overrides
+    /// `dry_run` in book.toml)
+    #[arg(long)]
+    dry_run: bool,
 }
 
 #[derive(Subcommand)]
@@ -103,13 +135,69 @@ enum Commands {
         renderer: String,
     },
     /// Approve the current book.toml for code execution
-    Allow,
+    Allow {
+        /// Approve by content only, so the approval also holds after the book
+        /// is moved or cloned to another path
+        #[arg(long)]
+        portable: bool,
+        /// Approve every book.toml under this directory instead
+        #[arg(long, value_name = "DIR")]
+        recursive: Option<PathBuf>,
+        /// Print the compilers, flags, and environment the book configures
+        /// and ask for confirmation before approving
+        #[arg(long)]
+        print: bool,
+        /// With --print, approve without asking
+        #[arg(long, requires = "print")]
+        yes: bool,
+    },
     /// Remove approval for the current book.toml
-    Deny,
+    Deny {
+        /// Remove approval of every book.toml under this directory instead
+        #[arg(long, value_name = "DIR")]
+        recursive: Option<PathBuf>,
+    },
     /// Show approval status for the current book
     Status,
     /// List all approved books
     List,
+    /// Remove approvals of book.toml files that were deleted or edited since
+    Prune,
+    /// Remove all cached compilation results
+    CleanCache,
+    /// Check the current book.toml's configuration without building
+    Validate,
+    /// List the enabled languages with their fence markers and compilers
+    Languages {
+        /// Print the list as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// An enabled language as listed by the `languages` command.
+#[derive(Serialize)]
+struct LanguageListing {
+    name: String,
+    fence_markers: Vec<String>,
+    file_extension: String,
+    compiler: String,
+    variants: Vec<VariantListing>,
+}
+
+/// A variant as listed by the `languages` command.
+#[derive(Serialize)]
+struct VariantListing {
+    name: String,
+    fence_markers: Vec<String>,
+    compiler: String,
+}
+
+/// Output of `languages --json`.
+#[derive(Serialize)]
+struct LanguagesReport {
+    languages: Vec<LanguageListing>,
+    collisions: Vec<String>,
 }
 
 pub fn main() {
@@ -139,7 +227,62 @@ pub fn main() {
                 exit(1);
             }
         }
-        Some(Commands::Allow) => {
+        Some(Commands::Allow {
+            portable,
+            recursive: Some(dir),
+            print,
+            yes,
+        }) => {
+            let mut declined = 0;
+            let result = for_each_book_toml(&dir, |book_toml| {
+                if allow_book(book_toml, portable, print, yes)? {
+                    println!("Approved: {}", book_toml.display());
+                } else {
+                    println!("Not approved: {}", book_toml.display());
+                    declined += 1;
+                }
+                Ok(())
+            });
+            match result {
+                Ok(count) => {
+                    println!("Approved {} book(s)", count - declined);
+                    if declined > 0 {
+                        println!("Not approved {} book(s)", declined);
+                        exit(1);
+                    }
+                    exit(0);
+                }
+                Err(e) => {
+                    print_error(format!("{:#}", e));
+                    exit(1);
+                }
+            }
+        }
+        Some(Commands::Deny {
+            recursive: Some(dir),
+        }) => {
+            let result = for_each_book_toml(&dir, |book_toml| {
+                approval::deny(book_toml)?;
+                println!("Removed approval: {}", book_toml.display());
+                Ok(())
+            });
+            match result {
+                Ok(count) => {
+                    println!("Removed approval of {} book(s)", count);
+                    exit(0);
+                }
+                Err(e) => {
+                    print_error(format!("{:#}", e));
+                    exit(1);
+                }
+            }
+        }
+        Some(Commands::Allow {
+            portable,
+            recursive: None,
+            print,
+            yes,
+        }) => {
             let book_toml = match find_book_toml() {
                 Ok(path) => path,
                 Err(e) => {
@@ -147,14 +290,25 @@ pub fn main() {
                     exit(1);
                 }
             };
-            if let Err(e) = approval::approve(&book_toml) {
-                print_error(e);
-                exit(1);
+            match allow_book(&book_toml, portable, print, yes) {
+                Ok(true) => {}
+                Ok(false) => {
+                    println!("Not approved: {}", book_toml.display());
+                    exit(1);
+                }
+                Err(e) => {
+                    print_error(format!("{:#}", e));
+                    exit(1);
+                }
+            }
+            if portable {
+                println!("Approved (portable): {}", book_toml.display());
+            } else {
+                println!("Approved: {}", book_toml.display());
             }
-            println!("Approved: {}", book_toml.display());
             exit(0);
         }
-        Some(Commands::Deny) => {
+        Some(Commands::Deny { recursive: None }) => {
             let book_toml = match find_book_toml() {
                 Ok(path) => path,
                 Err(e) => {
@@ -177,12 +331,12 @@ pub fn main() {
                     exit(1);
                 }
             };
-            match approval::is_approved(&book_toml) {
-                Ok(true) => {
-                    println!("Approved: {}", book_toml.display());
+            match approval::approval_record(&book_toml) {
+                Ok(Some(record)) => {
+                    println!("Approved: {}", record);
                     exit(0);
                 }
-                Ok(false) => {
+                Ok(None) => {
                     println!("Not approved: {}", book_toml.display());
                     exit(1);
                 }
@@ -197,8 +351,40 @@ pub fn main() {
                 if approved.is_empty() {
                     println!("No approved books");
                 } else {
-                    for path in approved {
-                        println!("{}", path);
+                    for record in approved {
+                        println!("{}", record);
+                    }
+                }
+                exit(0);
+            }
+            Err(e) => {
+                print_error(e);
+                exit(1);
+            }
+        },
+        Some(Commands::Prune) => match approval::prune() {
+            Ok(removed) => {
+                if removed.is_empty() {
+                    println!("No stale approvals");
+                } else {
+                    for record in removed {
+                        println!("Removed: {}", record);
+                    }
+                }
+                exit(0);
+            }
+            Err(e) => {
+                print_error(e);
+                exit(1);
+            }
+        },
+        Some(Commands::CleanCache) => match cache::clean() {
+            Ok(removed) => {
+                if removed.is_empty() {
+                    println!("No cache to remove");
+                } else {
+                    for dir in removed {
+                        println!("Removed: {}", dir.display());
                     }
                 }
                 exit(0);
@@ -208,9 +394,28 @@ pub fn main() {
                 exit(1);
             }
         },
+        Some(Commands::Languages { json }) => match list_languages(json) {
+            Ok(()) => exit(0),
+            Err(e) => {
+                print_error(format!("{:#}", e));
+                exit(1);
+            }
+        },
+        Some(Commands::Validate) => match validate_book_config() {
+            Ok(valid) => exit(if valid { 0 } else { 1 }),
+            Err(e) => {
+                print_error(format!("{:#}", e));
+                exit(1);
+            }
+        },
         None => {
             // Run as preprocessor (default when called by mdbook)
-            if let Err(e) = runtime.block_on(handle_preprocessing_async()) {
+            if let Err(e) = runtime.block_on(handle_preprocessing_async(
+                cli.verbosity,
+                cli.fail_fast,
+                cli.progress,
+                cli.dry_run,
+            )) {
                 print_error(format!("Preprocessing failed: {}", e));
                 exit(1);
             }
@@ -229,10 +434,161 @@ fn find_book_toml() -> Result<PathBuf> {
     Ok(book_toml)
 }
 
-async fn handle_preprocessing_async() -> Result<()> {
+/// Approves a book.toml. With `print`, its resolved languages are shown
+/// first and approval asks for confirmation unless `yes` is set.
+///
+/// Returns whether the book was approved.
+fn allow_book(book_toml: &Path, portable: bool, print: bool, yes: bool) -> Result<bool> {
+    if !print {
+        approval::approve(book_toml, portable)?;
+        return Ok(true);
+    }
+    let summary = LanguageRegistry::from_config(&load_book_config(book_toml)?)?.describe();
+    approval::approve_confirmed(
+        book_toml,
+        portable,
+        &summary,
+        yes,
+        &mut stdin().lock(),
+        &mut stdout(),
+    )
+}
+
+/// Loads a book's configuration as the preprocessor would.
+fn load_book_config(book_toml: &Path) -> Result<CheckCodeConfig> {
+    let root = book_toml
+        .parent()
+        .context("book.toml has no parent directory")?;
+    let book_config = mdbook::Config::from_disk(book_toml)
+        .with_context(|| format!("Failed to load {}", book_toml.display()))?;
+    CheckCodeConfig::from_book_config(&book_config, root, &preprocessor::preprocessor_name())
+        .context("Invalid configuration")
+}
+
+/// Runs `action` on every book.toml under `dir` (see
+/// [`approval::find_book_tomls`]), returning how many there were.
+fn for_each_book_toml(dir: &Path, mut action: impl FnMut(&Path) -> Result<()>) -> Result<usize> {
+    let book_tomls = approval::find_book_tomls(dir)?;
+    for book_toml in &book_tomls {
+        action(book_toml)?;
+    }
+    Ok(book_tomls.len())
+}
+
+/// Loads the current book's configuration as the preprocessor would, prints
+/// each enabled language's resolved compiler, flags, and fence markers, and
+/// reports any problems.
+///
+/// Returns whether the configuration is free of problems. Configurations that
+/// the preprocessor would reject outright are returned as errors.
+fn validate_book_config() -> Result<bool> {
+    let config = load_book_config(&find_book_toml()?)?;
+    print!("{}", LanguageRegistry::from_config(&config)?.describe());
+
+    let problems = config.problems();
+    for problem in &problems {
+        print_error(problem);
+    }
+    if problems.is_empty() {
+        println!("Configuration is valid");
+    }
+    Ok(problems.is_empty())
+}
+
+/// Prints each enabled language of the current book with its fence markers,
+/// file extension, compiler, and variants, followed by any fence markers
+/// claimed by more than one language.
+///
+/// Collisions are listed rather than rejected, since they are the usual reason
+/// a block is checked with an unexpected compiler.
+fn list_languages(json: bool) -> Result<()> {
+    let book_toml = find_book_toml()?;
+    let root = book_toml
+        .parent()
+        .context("book.toml has no parent directory")?;
+    let book_config = mdbook::Config::from_disk(&book_toml)
+        .with_context(|| format!("Failed to load {}", book_toml.display()))?;
+    let config = CheckCodeConfig::load(&book_config, root, &preprocessor::preprocessor_name())
+        .context("Invalid configuration")?;
+
+    let mut names: Vec<_> = config
+        .languages()
+        .iter()
+        .filter(|(_, c)| c.enabled)
+        .collect();
+    names.sort_by_key(|(name, _)| *name);
+
+    let languages: Vec<LanguageListing> = names
+        .into_iter()
+        .map(|(name, lang_config)| {
+            let mut variants: Vec<VariantListing> = lang_config
+                .variants
+                .iter()
+                .map(|(variant_name, variant)| VariantListing {
+                    name: variant_name.clone(),
+                    fence_markers: variant.fence_markers.clone(),
+                    compiler: variant.compiler.clone(),
+                })
+                .collect();
+            variants.sort_by(|a, b| a.name.cmp(&b.name));
+
+            LanguageListing {
+                name: name.clone(),
+                fence_markers: lang_config.get_fence_markers(name),
+                file_extension: language::ConfiguredLanguage::new(
+                    name.clone(),
+                    None,
+                    (*lang_config).clone(),
+                )
+                .file_extension()
+                .to_string(),
+                compiler: lang_config.compiler.clone(),
+                variants,
+            }
+        })
+        .collect();
+    let report = LanguagesReport {
+        languages,
+        collisions: config.fence_marker_collisions(),
+    };
+
+    if json {
+        serde_json::to_writer_pretty(stdout(), &report)?;
+        println!();
+        return Ok(());
+    }
+
+    for language in &report.languages {
+        println!("{} ({})", language.name, language.file_extension);
+        println!("  compiler: {}", language.compiler);
+        println!("  fence markers: {}", language.fence_markers.join(", "));
+        for variant in &language.variants {
+            println!("  variant {}", variant.name);
+            println!("    compiler: {}", variant.compiler);
+            if !variant.fence_markers.is_empty() {
+                println!("    fence markers: {}", variant.fence_markers.join(", "));
+            }
+        }
+    }
+    for collision in &report.collisions {
+        print_error(collision);
+    }
+    Ok(())
+}
+
+async fn handle_preprocessing_async(
+    verbosity: Option<Verbosity>,
+    fail_fast: bool,
+    progress: bool,
+    dry_run: bool,
+) -> Result<()> {
     let (ctx, book) = CmdPreprocessor::parse_input(stdin())?;
 
-    let preprocessor = CheckCodePreprocessor::new();
+    let preprocessor = CheckCodePreprocessor::new()
+        .with_verbosity(verbosity)
+        .with_fail_fast(fail_fast)
+        .with_progress(progress)
+        .with_dry_run(dry_run);
     let processed_book = preprocessor.run_async(&ctx, book).await?;
 
     serde_json::to_writer(stdout(), &processed_book)?;
@@ -1,13 +1,52 @@
 use crate::approval::is_approved;
-use crate::config::CheckCodeConfig;
-use crate::language::LanguageRegistry;
-use crate::reporting::print_info;
+use crate::block_cache::BlockCache;
+use crate::cache::CompileCache;
+use crate::chapter_cache::ChapterCache;
+use crate::compilation::{CompilationResult, TaskKind};
+use crate::config::{CheckCodeConfig, FailureReturn, Verbosity};
+use crate::language::{LanguageRegistry, LanguageSummary};
+use crate::reporting::{print_info, Progress};
+use crate::task_collector::BlockTally;
 use crate::{compilation, reporting, task_collector};
 use anyhow::{Context, Result};
-use mdbook::book::Book;
+use mdbook::book::{Book, BookItem, Chapter};
 use mdbook::preprocess::{Preprocessor, PreprocessorContext};
+use std::path::Path;
 use tempfile::TempDir;
 
+/// Chapter path given to the Markdown checked by
+/// [`CheckCodePreprocessor::check_markdown`], as shown in results.
+#[allow(dead_code)] // Public library API for external tooling
+pub const CHECK_MARKDOWN_PATH: &str = "input.md";
+
+/// Default preprocessor name, matching the `[preprocessor.check-code]` table in `book.toml`.
+pub const PREPROCESSOR_NAME: &str = "check-code";
+
+/// Environment variable that overrides [`PREPROCESSOR_NAME`] for renamed deployments.
+pub const PREPROCESSOR_NAME_ENV: &str = "CHECK_CODE_NAME";
+
+/// Environment variable that skips the approval check when set to `1` or
+/// `true`, for CI runs where the approval store always starts out empty.
+pub const ASSUME_APPROVED_ENV: &str = "MDBOOK_CHECK_CODE_ASSUME_APPROVED";
+
+/// Returns whether [`ASSUME_APPROVED_ENV`] is set to `1` or `true`.
+fn assume_approved() -> bool {
+    std::env::var(ASSUME_APPROVED_ENV)
+        .is_ok_and(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+}
+
+/// Returns the preprocessor name, honoring the `CHECK_CODE_NAME` override.
+///
+/// The name is used both for [`Preprocessor::name`] and to locate the
+/// `[preprocessor.<name>]` configuration table, so a renamed binary that sets
+/// `CHECK_CODE_NAME` reads its configuration from the matching table.
+pub fn preprocessor_name() -> String {
+    std::env::var(PREPROCESSOR_NAME_ENV)
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| PREPROCESSOR_NAME.to_string())
+}
+
 /// A configuration-driven mdBook preprocessor that validates code blocks.
 ///
 /// # Overview
@@ -36,6 +75,11 @@ use tempfile::TempDir;
 /// Compiler paths cannot contain shell metacharacters (`;`, `|`, `&`, `` ` ``) or
 /// use parent directory traversal (`..`).
 pub struct CheckCodePreprocessor {
+    name: String,
+    verbosity: Option<Verbosity>,
+    fail_fast: bool,
+    progress: bool,
+    dry_run: bool,
     #[cfg(feature = "integration-tests")]
     skip_approval: bool,
 }
@@ -43,11 +87,48 @@ pub struct CheckCodePreprocessor {
 impl CheckCodePreprocessor {
     pub fn new() -> Self {
         Self {
+            name: preprocessor_name(),
+            verbosity: None,
+            fail_fast: false,
+            progress: false,
+            dry_run: false,
             #[cfg(feature = "integration-tests")]
             skip_approval: false,
         }
     }
 
+    /// Override the configured `verbosity`, e.g. from a command-line flag.
+    /// `None` keeps the value from `book.toml`.
+    #[allow(dead_code)] // Used by CLI binary
+    pub fn with_verbosity(mut self, verbosity: Option<Verbosity>) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// Enable `fail_fast` regardless of `book.toml`, e.g. from a command-line
+    /// flag. `false` keeps the value from `book.toml`.
+    #[allow(dead_code)] // Used by CLI binary
+    pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Enable `progress` regardless of `book.toml`, e.g. from a command-line
+    /// flag. `false` keeps the value from `book.toml`.
+    #[allow(dead_code)] // Used by CLI binary
+    pub fn with_progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Enable `dry_run` regardless of `book.toml`, e.g. from a command-line
+    /// flag. `false` keeps the value from `book.toml`.
+    #[allow(dead_code)] // Used by CLI binary
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
     /// Create preprocessor with approval checking disabled.
     ///
     /// # Safety
@@ -66,6 +147,11 @@ impl CheckCodePreprocessor {
     #[allow(dead_code)] // Used by integration tests with integration-tests feature
     pub fn new_for_testing() -> Self {
         Self {
+            name: preprocessor_name(),
+            verbosity: None,
+            fail_fast: false,
+            progress: false,
+            dry_run: false,
             skip_approval: true,
         }
     }
@@ -79,35 +165,129 @@ impl Default for CheckCodePreprocessor {
 
 impl CheckCodePreprocessor {
     pub async fn run_async(&self, ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
-        print_info("Preprocessor started");
-
         #[cfg(feature = "integration-tests")]
         let skip_approval = self.skip_approval;
         #[cfg(not(feature = "integration-tests"))]
         let skip_approval = false;
 
+        let config = CheckCodeConfig::from_preprocessor_context(ctx, &self.name)?;
+        if !config.checks_renderer(&ctx.renderer) {
+            log::debug!("Not checking code for the {} renderer", ctx.renderer);
+            return Ok(book);
+        }
+
         if !skip_approval {
             let book_toml_path = ctx.root.join("book.toml");
-            if !is_approved(&book_toml_path)? {
+            if assume_approved() {
+                print_info(format!(
+                    "WARNING: {} is set, running compilers from {} WITHOUT approval",
+                    ASSUME_APPROVED_ENV,
+                    book_toml_path.display()
+                ));
+                print_info(
+                    "Only set it where book.toml is trusted, such as CI for your own repository",
+                );
+            } else if !is_approved(&book_toml_path)? {
                 reporting::report_approval_error(&book_toml_path)?;
                 anyhow::bail!("book.toml not approved");
             }
         }
 
-        let config = CheckCodeConfig::from_preprocessor_context(ctx)?;
-        let registry = LanguageRegistry::from_config(&config);
+        reporting::set_verbosity(self.verbosity.unwrap_or(config.verbosity));
+        print_info("Preprocessor started");
+
+        let mut registry = LanguageRegistry::from_config(&config)?;
+        let missing_compilers = registry.missing_compilers();
+        if !missing_compilers.is_empty() {
+            reporting::report_missing_compilers(&missing_compilers)?;
+        }
         let temp_dir = TempDir::new().context("Failed to create temporary directory")?;
         log::debug!("Using temporary directory: {:?}", temp_dir.path());
+        let dry_run = self.dry_run || config.dry_run;
+        if !dry_run {
+            for error in registry.precompile_preambles(temp_dir.path()).await {
+                reporting::print_warning(format!(
+                    "{:#}\nFalling back to prepending the preamble to every block",
+                    error
+                ));
+            }
+        }
         let src_dir = ctx.root.join(&ctx.config.book.src);
 
-        let tasks =
-            task_collector::collect_compilation_tasks(&mut book, &src_dir, &registry, &temp_dir)?;
+        // Keep the untouched book around if it is what failures should return
+        let original_book =
+            (config.on_failure_return == Some(FailureReturn::Original)).then(|| book.clone());
+
+        // A dry run lists every chapter's blocks and records nothing, and
+        // chapters compiled in place can include files the cache does not track
+        let chapter_cache = if config.chapter_cache && !dry_run && !config.compile_in_place {
+            Some(ChapterCache::open(&ctx.root, &config)?)
+        } else {
+            None
+        };
+        let tasks = task_collector::collect_compilation_tasks(
+            &mut book,
+            &src_dir,
+            &config,
+            &registry,
+            &temp_dir,
+            chapter_cache.as_ref(),
+        )?;
+
+        // Skipped chapters contribute no tasks, so their recorded blocks are
+        // counted from the cache
+        let chapter_tallies = BlockTally::by_chapter(&tasks);
+        let mut tally = BlockTally::of_tasks(&tasks);
+        if let Some(ref cache) = chapter_cache {
+            let skipped_chapters = cache.skipped();
+            if skipped_chapters > 0 {
+                print_info(format!(
+                    "Skipped {} unchanged chapter(s) (cached)",
+                    skipped_chapters
+                ));
+            }
+            tally.merge(&cache.skipped_tally());
+        }
+        task_collector::check_expected_blocks(&config, &tally)?;
+        if config.check_unused_variants {
+            reporting::report_unused_variants(&task_collector::unused_variants(&config, &tally));
+        }
+
+        if config.emit_compile_commands {
+            reporting::write_compile_commands(&tasks, &ctx.root.join("compile_commands.json"))?;
+        }
+
+        if dry_run {
+            reporting::report_dry_run(&tasks);
+            return Ok(book);
+        }
 
         if tasks.is_empty() {
             log::info!("No code blocks found to validate");
+            if let Some(ref cache) = chapter_cache {
+                cache.record(&chapter_tallies)?;
+            }
             return Ok(book);
         }
 
+        // Blocks compiled in place can include sibling files the caches do
+        // not track
+        let block_cache = if config.block_cache && !config.compile_in_place {
+            Some(BlockCache::open(&ctx.root)?)
+        } else {
+            None
+        };
+        let tasks = match block_cache {
+            Some(ref cache) => {
+                let (tasks, skipped) = cache.retain_changed(tasks);
+                if skipped > 0 {
+                    print_info(format!("Skipped {} unchanged block(s) (cached)", skipped));
+                }
+                tasks
+            }
+            None => tasks,
+        };
+
         log::debug!("Collected {} compilation tasks", tasks.len());
 
         let max_concurrent = get_max_concurrency(config.parallel_jobs);
@@ -120,24 +300,255 @@ impl CheckCodePreprocessor {
                 "default"
             }
         );
-        let (results, duration) = compilation::compile_tasks(tasks, max_concurrent).await;
+        let compile_cache = if config.cache && !config.compile_in_place {
+            Some(CompileCache::open()?)
+        } else {
+            None
+        };
+        let fail_fast = self.fail_fast || config.fail_fast;
+        let task_count = tasks.len();
+        let progress = Progress::new(task_count, self.progress || config.progress);
+        let (results, duration) = compilation::compile_tasks(
+            tasks,
+            max_concurrent,
+            get_max_memory_jobs(config.max_memory_jobs),
+            fail_fast,
+            Some(&progress),
+            compile_cache.as_ref(),
+        )
+        .await;
+        progress.finish();
+        if results.len() < task_count {
+            print_info(format!(
+                "Stopped at the first failure (fail_fast); {} of {} compilation(s) skipped",
+                task_count - results.len(),
+                task_count
+            ));
+        }
+
+        if let Some(ref cache) = block_cache {
+            cache.record(&results)?;
+        }
+
+        // Standalone compilations are diagnostics and never count as failures
+        let (standalone, mut results): (Vec<_>, Vec<_>) = results
+            .into_iter()
+            .partition(|r| r.kind() == TaskKind::Standalone);
+        if config.check_standalone {
+            reporting::report_context_dependent_blocks(&compilation::context_dependent_blocks(
+                &results,
+                &standalone,
+            ));
+        }
+
+        if config.keep_failed_sources {
+            compilation::keep_failed_sources(
+                &mut results,
+                &ctx.root.join(compilation::FAILED_SOURCES_DIR),
+            )?;
+        }
 
+        if let Some(ref junit_path) = config.junit_path {
+            reporting::write_junit_report(&results, &ctx.root.join(junit_path))?;
+        }
+
+        if let Some(ref report_file) = config.report_file {
+            reporting::write_json_report(&results, &ctx.root.join(report_file))?;
+        }
+
+        if let Some(ref sarif_file) = config.sarif_file {
+            reporting::write_sarif(&results, &ctx.root.join(sarif_file), &src_dir)?;
+        }
+
+        if let Some(ref summary_file) = config.summary_file {
+            reporting::write_summary(&results, duration, &ctx.root.join(summary_file))?;
+        }
+
+        // Without the feature, sqlite_path is rejected when the config loads
+        #[cfg(feature = "sqlite")]
+        if let Some(ref sqlite_path) = config.sqlite_path {
+            crate::sqlite::write_sqlite_report(&results, &ctx.root.join(sqlite_path), &ctx.root)?;
+        }
+
+        let ci_groups = config.ci_groups.then(reporting::CiGroups::detect).flatten();
+        let annotations = reporting::GitHubAnnotations::new(config.github_annotations, &src_dir);
         let (_successful, failed): (Vec<_>, Vec<_>) = results.iter().partition(|r| r.success());
+        let (failed, suppressed) = compilation::suppress_context_cascades(failed);
+        if suppressed > 0 {
+            print_info(format!(
+                "Suppressed {} failure(s) of blocks depending on broken propagated context",
+                suppressed
+            ));
+        }
 
         if !failed.is_empty() {
-            reporting::report_compilation_errors(&failed)?;
+            if let Err(error) = reporting::report_compilation_errors(
+                &failed,
+                config.show_line_numbers,
+                config.max_reported_errors(),
+                ci_groups,
+                annotations.as_ref(),
+            ) {
+                let Some(policy) = config.on_failure_return else {
+                    return Err(error);
+                };
+                print_info(format!(
+                    "Continuing despite failures (on_failure_return = {:?})",
+                    policy
+                ));
+                reporting::print_compilation_statistics(&results, duration, ci_groups);
+                return Ok(original_book.unwrap_or(book));
+            }
         }
 
-        reporting::print_compilation_statistics(&results, duration);
+        reporting::print_compilation_statistics(&results, duration, ci_groups);
+
+        if let Some(ref cache) = chapter_cache {
+            cache.record(&chapter_tallies)?;
+        }
 
         log::debug!("Preprocessor completed successfully.");
         Ok(book)
     }
 }
 
+impl CheckCodePreprocessor {
+    /// Validates the code blocks of a single Markdown document, outside of
+    /// mdBook.
+    ///
+    /// Runs the same block extraction, task collection, and compilation as a
+    /// book build, treating `content` as one chapter at [`CHECK_MARKDOWN_PATH`].
+    /// Book-level features (caches, reports, `expected_blocks`) are not used.
+    /// Every validated block is returned, failed ones included; see
+    /// [`CompilationResult::success`].
+    ///
+    /// # Security
+    ///
+    /// This bypasses approval checking, since there is no `book.toml` to
+    /// approve: the caller is responsible for trusting the compilers in
+    /// `config`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is invalid or a block cannot be
+    /// collected (for example, it exceeds the size limit).
+    #[allow(dead_code)] // Public library API for external tooling
+    pub fn check_markdown(
+        &self,
+        content: &str,
+        config: &CheckCodeConfig,
+    ) -> Result<Vec<CompilationResult>> {
+        let runtime = tokio::runtime::Runtime::new()
+            .context("Failed to create Tokio runtime for checking Markdown")?;
+        runtime.block_on(self.check_markdown_async(content, config))
+    }
+
+    /// Async version of [`CheckCodePreprocessor::check_markdown`].
+    #[allow(dead_code)] // Public library API for external tooling
+    pub async fn check_markdown_async(
+        &self,
+        content: &str,
+        config: &CheckCodeConfig,
+    ) -> Result<Vec<CompilationResult>> {
+        let mut registry = LanguageRegistry::from_config(config)?;
+        let temp_dir = TempDir::new().context("Failed to create temporary directory")?;
+        for error in registry.precompile_preambles(temp_dir.path()).await {
+            reporting::print_warning(format!(
+                "{:#}\nFalling back to prepending the preamble to every block",
+                error
+            ));
+        }
+
+        let mut book = Book::new();
+        book.push_item(BookItem::Chapter(Chapter::new(
+            CHECK_MARKDOWN_PATH,
+            content.to_string(),
+            CHECK_MARKDOWN_PATH,
+            Vec::new(),
+        )));
+        let tasks = task_collector::collect_compilation_tasks(
+            &mut book,
+            Path::new(""),
+            config,
+            &registry,
+            &temp_dir,
+            None,
+        )?;
+
+        let max_concurrent = get_max_concurrency(config.parallel_jobs);
+        let max_memory_jobs = get_max_memory_jobs(config.max_memory_jobs);
+        let (results, _) = compilation::compile_tasks(
+            tasks,
+            max_concurrent,
+            max_memory_jobs,
+            config.fail_fast,
+            None,
+            None,
+        )
+        .await;
+        Ok(results
+            .into_iter()
+            .filter(|r| r.kind() != TaskKind::Standalone)
+            .collect())
+    }
+
+    /// Lists the languages configured for the book, enabled or not, sorted by
+    /// name.
+    ///
+    /// Only the configuration is loaded: nothing is compiled, and approval is
+    /// not required.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mdbook::book::Book;
+    /// use mdbook::preprocess::CmdPreprocessor;
+    /// use mdbook_check_code::CheckCodePreprocessor;
+    ///
+    /// let config: mdbook::Config = r#"
+    ///     [preprocessor.check-code.languages.c]
+    ///     compiler = "gcc"
+    ///
+    ///     [preprocessor.check-code.languages.c.variants.parasol]
+    ///     compiler = "clang"
+    ///
+    ///     [preprocessor.check-code.languages.typescript]
+    ///     enabled = false
+    ///     compiler = "tsc"
+    /// "#
+    /// .parse()
+    /// .unwrap();
+    /// let input = serde_json::json!([
+    ///     {
+    ///         "root": ".",
+    ///         "config": config,
+    ///         "renderer": "html",
+    ///         "mdbook_version": mdbook::MDBOOK_VERSION,
+    ///     },
+    ///     Book::new(),
+    /// ]);
+    /// let (ctx, _book) = CmdPreprocessor::parse_input(input.to_string().as_bytes()).unwrap();
+    ///
+    /// let languages = CheckCodePreprocessor::new().configured_languages(&ctx).unwrap();
+    /// assert_eq!(languages.len(), 2);
+    /// assert_eq!(languages[0].name, "c");
+    /// assert_eq!(languages[0].fence_markers, ["c", "h"]);
+    /// assert_eq!(languages[0].file_extension, ".c");
+    /// assert_eq!(languages[0].variants, ["parasol"]);
+    /// assert!(languages[0].enabled);
+    /// assert_eq!(languages[1].name, "typescript");
+    /// assert!(!languages[1].enabled);
+    /// ```
+    #[allow(dead_code)] // Public library API for external tooling
+    pub fn configured_languages(&self, ctx: &PreprocessorContext) -> Result<Vec<LanguageSummary>> {
+        let config = CheckCodeConfig::from_preprocessor_context(ctx, &self.name)?;
+        Ok(LanguageSummary::from_config(&config))
+    }
+}
+
 impl Preprocessor for CheckCodePreprocessor {
     fn name(&self) -> &str {
-        "check-code"
+        &self.name
     }
 
     fn run(&self, ctx: &PreprocessorContext, book: Book) -> Result<Book> {
@@ -146,6 +557,8 @@ impl Preprocessor for CheckCodePreprocessor {
         runtime.block_on(self.run_async(ctx, book))
     }
 
+    /// Always true, so mdBook keeps running the preprocessor; renderers not
+    /// listed in `renderers` are skipped by [`CheckCodePreprocessor::run_async`].
     fn supports_renderer(&self, _renderer: &str) -> bool {
         true
     }
@@ -156,3 +569,9 @@ fn get_max_concurrency(parallel_jobs: Option<usize>) -> usize {
         .filter(|&j| j > 0)
         .unwrap_or_else(|| num_cpus::get() * 8) // 8x for I/O-bound subprocess work
 }
+
+fn get_max_memory_jobs(max_memory_jobs: Option<usize>) -> usize {
+    max_memory_jobs
+        .filter(|&j| j > 0)
+        .unwrap_or_else(num_cpus::get)
+}
@@ -1,29 +1,173 @@
-use crate::compilation::CompilationResult;
-use anyhow::Result;
+use crate::compilation::{CompilationResult, CompilationTask, TaskKind};
+use crate::config::Verbosity;
+use crate::language::CompilerNotFound;
+use anstyle::{AnsiColor, Style};
+use anyhow::{Context, Result};
 use chrono::Local;
-use std::collections::{HashMap, HashSet};
-use std::fmt::Display;
-use std::path::Path;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Write as _};
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 use std::time::Duration;
 
-/// Internal helper for printing messages with consistent formatting.
-fn print_message<S: Display>(level: &str, message: S) {
-    eprintln!(
-        "{} [{}] (mdbook_check_code): {}",
-        Local::now().format("%Y-%m-%d %H:%M:%S"),
+/// Reporting level set by [`set_verbosity`], stored as its discriminant.
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Sets how much the reporting functions print besides errors.
+///
+/// Like `log`'s maximum level this is process-wide, so the preprocessor sets
+/// it once per run rather than threading it through every report.
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+/// Returns the level set by [`set_verbosity`].
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        level if level == Verbosity::Quiet as u8 => Verbosity::Quiet,
+        level if level == Verbosity::Verbose as u8 => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    }
+}
+
+/// Style of the `ERROR` level label.
+const ERROR_STYLE: Style = AnsiColor::Red.on_default().bold();
+
+/// Style of the success summary.
+const SUCCESS_STYLE: Style = AnsiColor::Green.on_default();
+
+/// Style of code listings in failure reports.
+const CODE_STYLE: Style = Style::new().dimmed();
+
+/// Returns whether output to stderr should be colored.
+///
+/// Color requires stderr to be a terminal, so redirected output and CI logs
+/// stay clean, and otherwise follows `NO_COLOR`, `CLICOLOR`, and the global
+/// [`anstream::ColorChoice`].
+fn color_enabled() -> bool {
+    let stderr = std::io::stderr();
+    stderr.is_terminal() && anstream::AutoStream::choice(&stderr) != anstream::ColorChoice::Never
+}
+
+/// Formats a message with an mdBook-style timestamp and prefix, applying
+/// `style` to the message and coloring the `ERROR` label if `color` is set.
+fn format_message<S: Display>(level: &str, message: S, style: Style, color: bool) -> String {
+    let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S");
+    if !color {
+        return format!("{} [{}] (mdbook_check_code): {}", timestamp, level, message);
+    }
+    let level_style = if level == "ERROR" {
+        ERROR_STYLE
+    } else {
+        Style::new()
+    };
+    format!(
+        "{} [{}{}{}] (mdbook_check_code): {}{}{}",
+        timestamp,
+        level_style.render(),
         level,
-        message
-    );
+        level_style.render_reset(),
+        style.render(),
+        message,
+        style.render_reset()
+    )
+}
+
+/// Internal helper for printing messages with consistent formatting.
+fn print_message<S: Display>(level: &str, message: S, style: Style) {
+    eprintln!("{}", format_message(level, message, style, color_enabled()));
 }
 
 /// Prints an error message to stderr with mdBook-style timestamp and prefix.
 pub fn print_error<S: Display>(message: S) {
-    print_message("ERROR", message);
+    print_message("ERROR", message, Style::new());
+}
+
+/// Prints a line of a failing code block, dimmed when colored.
+fn print_code_line<S: Display>(line: S) {
+    print_message("ERROR", line, CODE_STYLE);
 }
 
-/// Prints an info message to stderr with mdBook-style timestamp and prefix.
+/// Prints an info message to stderr with mdBook-style timestamp and prefix,
+/// unless the verbosity is quiet.
 pub fn print_info<S: Display>(message: S) {
-    print_message("INFO", message);
+    if verbosity() != Verbosity::Quiet {
+        print_message("INFO", message, Style::new());
+    }
+}
+
+/// Prints a warning to stderr with mdBook-style timestamp and prefix, unless
+/// the verbosity is quiet.
+pub fn print_warning<S: Display>(message: S) {
+    if verbosity() != Verbosity::Quiet {
+        print_message("WARN", message, Style::new());
+    }
+}
+
+/// Prints an info message in the success color, unless the verbosity is quiet.
+fn print_success<S: Display>(message: S) {
+    if verbosity() != Verbosity::Quiet {
+        print_message("INFO", message, SUCCESS_STYLE);
+    }
+}
+
+/// Counts finished compilations and, when drawing, shows a "compiled X/Y
+/// blocks" line on stderr that is redrawn in place.
+pub struct Progress {
+    total: usize,
+    done: AtomicUsize,
+    draw: bool,
+}
+
+impl Progress {
+    /// Creates a progress indicator for `total` compilations. It only draws
+    /// when `enabled`, stderr is a terminal, and the verbosity is not quiet, so
+    /// CI logs never receive carriage returns.
+    pub fn new(total: usize, enabled: bool) -> Self {
+        Self {
+            total,
+            done: AtomicUsize::new(0),
+            draw: enabled && std::io::stderr().is_terminal() && verbosity() != Verbosity::Quiet,
+        }
+    }
+
+    /// Records one finished compilation.
+    pub fn advance(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.draw {
+            eprint!("\rcompiled {}/{} blocks", done, self.total);
+        }
+    }
+
+    /// Number of compilations recorded so far.
+    pub fn done(&self) -> usize {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    /// Ends the progress line so following messages start on a fresh line.
+    pub fn finish(&self) {
+        if self.draw && self.done() > 0 {
+            eprintln!();
+        }
+    }
+}
+
+/// Reports every configured compiler that does not exist, found before any
+/// block is compiled.
+///
+/// # Errors
+///
+/// Returns an error after printing them (to stop the build).
+pub fn report_missing_compilers(missing: &[CompilerNotFound]) -> Result<()> {
+    for compiler in missing {
+        print_error(compiler);
+    }
+    anyhow::bail!("{} configured compiler(s) not found", missing.len());
 }
 
 /// Reports the approval error to stderr with mdBook-style formatting.
@@ -41,74 +185,524 @@ pub fn report_approval_error(book_toml_path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Collapsible log group markers of a CI system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CiGroups {
+    /// GitHub Actions `::group::` workflow commands
+    GitHub,
+    /// GitLab CI collapsible sections
+    GitLab,
+}
+
+impl CiGroups {
+    /// Detects the CI system from its environment variables.
+    pub fn detect() -> Option<Self> {
+        let is_set = |var: &str| std::env::var(var).is_ok_and(|v| v == "true");
+        if is_set("GITHUB_ACTIONS") {
+            Some(Self::GitHub)
+        } else if is_set("GITLAB_CI") {
+            Some(Self::GitLab)
+        } else {
+            None
+        }
+    }
+
+    /// Opens a group titled `title`. `id` identifies the section for CI
+    /// systems that need it.
+    fn start(self, id: &str, title: &str) {
+        match self {
+            Self::GitHub => eprintln!("::group::{}", title),
+            Self::GitLab => eprintln!(
+                "\x1b[0Ksection_start:{}:{}[collapsed=true]\r\x1b[0K{}",
+                Local::now().timestamp(),
+                section_id(id),
+                title
+            ),
+        }
+    }
+
+    /// Closes the group opened with the same `id`.
+    fn end(self, id: &str) {
+        match self {
+            Self::GitHub => eprintln!("::endgroup::"),
+            Self::GitLab => eprintln!(
+                "\x1b[0Ksection_end:{}:{}\r\x1b[0K",
+                Local::now().timestamp(),
+                section_id(id)
+            ),
+        }
+    }
+}
+
+/// Converts `id` into a GitLab section name (letters, digits, `_`, `.`, `-`).
+fn section_id(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Runs `print` inside a CI group if `groups` is set, or directly otherwise.
+fn in_group(groups: Option<CiGroups>, id: &str, title: &str, print: impl FnOnce()) {
+    if let Some(groups) = groups {
+        groups.start(id, title);
+        print();
+        groups.end(id);
+    } else {
+        print();
+    }
+}
+
+/// GitHub Actions `::error` annotations for failed blocks.
+///
+/// Each annotation points at the chapter file, relative to the repository
+/// root, and at the line of the first compiler diagnostic that falls inside
+/// the block; if there is none, it points at the block's opening fence.
+pub struct GitHubAnnotations {
+    /// The book's source directory, relative to the repository root
+    src_dir: PathBuf,
+}
+
+impl GitHubAnnotations {
+    /// Returns annotations for chapters in `src_dir` if `enabled` is set, or
+    /// if it is None and the build runs under GitHub Actions.
+    pub fn new(enabled: Option<bool>, src_dir: &Path) -> Option<Self> {
+        let enabled =
+            enabled.unwrap_or_else(|| std::env::var("GITHUB_ACTIONS").is_ok_and(|v| v == "true"));
+        enabled.then(|| Self {
+            src_dir: relative_to_repo_root(src_dir),
+        })
+    }
+
+    /// Prints the annotation for a failed block to stderr, where GitHub
+    /// Actions picks up workflow commands alongside the build log.
+    fn print(&self, result: &CompilationResult) {
+        let file = self.src_dir.join(result.chapter_path());
+        let message = format!(
+            "{} block {} failed\n{}",
+            result.language(),
+            result.block_label(),
+            result.error_message().unwrap_or_default()
+        );
+        eprintln!(
+            "::error file={},line={}::{}",
+            escape_property(&file.display().to_string()),
+            annotation_line(result),
+            escape_data(message.trim_end())
+        );
+    }
+}
+
+/// Returns `dir` relative to the root of the git repository containing it,
+/// or unchanged if that cannot be determined.
+fn relative_to_repo_root(dir: &Path) -> PathBuf {
+    let root = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .current_dir(dir)
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()));
+
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    root.and_then(|root| root.canonicalize().ok())
+        .and_then(|root| dir.strip_prefix(root).ok().map(Path::to_path_buf))
+        .unwrap_or(dir)
+}
+
+/// Chapter line an annotation points at: the line of the first compiler
+/// diagnostic located inside the block, or the block's opening fence.
+fn annotation_line(result: &CompilationResult) -> usize {
+    let fence = result.block().line.max(1);
+    if result.kind() == TaskKind::PropagatedContext {
+        return fence;
+    }
+
+    let code_lines = result.block().compile_code.lines().count();
+    result
+        .error_message()
+        .and_then(|message| diagnostic_line(message, result.language().file_extension()))
+        .and_then(|line| line.checked_sub(result.block_start_line()))
+        .filter(|&offset| offset < code_lines)
+        .map_or(fence, |offset| fence + 1 + offset)
+}
+
+/// Extracts the line number of the first diagnostic for a source file with
+/// the given extension, in either `file.c:12:` (gcc, clang, solc) or
+/// `file.ts(12,` (tsc) form.
+fn diagnostic_line(message: &str, file_extension: &str) -> Option<usize> {
+    let pattern = format!(r"{}(?::|\()(\d+)", regex::escape(file_extension));
+    let captures = Regex::new(&pattern).ok()?.captures(message)?;
+    captures[1].parse().ok()
+}
+
+/// Escapes a workflow command message.
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escapes a workflow command property value.
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
 /// Reports compilation errors to stderr with mdBook-style formatting.
 ///
+/// When `show_line_numbers` is set, each printed code line is prefixed with
+/// its line number within the block. When `groups` is set, the failures of
+/// each chapter are wrapped in a collapsible CI group whose title names the
+/// chapter and its failure count; the final summary stays outside of groups.
+/// With `annotations`, a GitHub Actions annotation is also printed for each
+/// failure.
+///
+/// Failures are ordered by chapter path, then block index. If `max_reported`
+/// is set, only that many are printed in full, followed by a line counting
+/// the rest; the final list of failing files is always complete.
+///
 /// # Errors
 ///
-/// Returns an error after printing all failures (to stop the build).
-pub fn report_compilation_errors(failed_results: &[&CompilationResult]) -> Result<()> {
+/// Returns an error after printing the failures (to stop the build).
+pub fn report_compilation_errors(
+    failed_results: &[&CompilationResult],
+    show_line_numbers: bool,
+    max_reported: Option<usize>,
+    groups: Option<CiGroups>,
+    annotations: Option<&GitHubAnnotations>,
+) -> Result<()> {
+    let (missing_compilers, failures) = split_missing_compilers(failed_results);
+    for missing in &missing_compilers {
+        print_error(missing);
+    }
+
+    let (reported, truncated) = truncate_failures(&failures, max_reported);
+    let mut by_chapter: BTreeMap<&Path, Vec<&CompilationResult>> = BTreeMap::new();
+    for result in reported {
+        by_chapter
+            .entry(result.chapter_path())
+            .or_default()
+            .push(result);
+    }
+
+    for (chapter, results) in &by_chapter {
+        let title = format!(
+            "Compilation failed: {} ({} block(s))",
+            chapter.display(),
+            results.len()
+        );
+        in_group(groups, &chapter.display().to_string(), &title, || {
+            for result in results {
+                print_failure(result, show_line_numbers);
+                if let Some(annotations) = annotations {
+                    annotations.print(result);
+                }
+            }
+        });
+    }
+
+    if let Some(truncated) = truncated {
+        print_error(truncated);
+    }
+
+    let files: BTreeSet<&Path> = failed_results.iter().map(|r| r.chapter_path()).collect();
+    print_error("Failed to compile code in the following files:");
+    for file in files {
+        print_error(format!("  {}", file.display()));
+    }
+    print_error("Code compilation failed");
+
+    anyhow::bail!("Code compilation failed");
+}
+
+/// Separates failures caused by a missing compiler, returning one message per
+/// missing compiler (with the number of blocks it affects) and the remaining
+/// failures.
+fn split_missing_compilers<'a>(
+    failed_results: &[&'a CompilationResult],
+) -> (Vec<String>, Vec<&'a CompilationResult>) {
+    let mut missing: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut failures = Vec::new();
     for result in failed_results {
-        print_error("Compilation failed");
-        print_error(format!("File: {}", result.chapter_path().display()));
-        print_error(format!(
-            "Block: #{} ({})",
-            result.block_index(),
-            result.language()
-        ));
-        print_error("");
+        match result.error_message() {
+            Some(message) if result.missing_compiler() => *missing.entry(message).or_insert(0) += 1,
+            _ => failures.push(*result),
+        }
+    }
+    let messages = missing
+        .into_iter()
+        .map(|(message, count)| format!("{} ({} block(s) not checked)", message, count))
+        .collect();
+    (messages, failures)
+}
 
-        if let Some(error_msg) = result.error_message() {
-            for line in error_msg.lines() {
+/// Sorts failures by chapter path and block index and keeps the first
+/// `max_reported`, returning them with a summary of the omitted ones, if any.
+fn truncate_failures<'a>(
+    failed_results: &[&'a CompilationResult],
+    max_reported: Option<usize>,
+) -> (Vec<&'a CompilationResult>, Option<String>) {
+    let mut failures = failed_results.to_vec();
+    failures.sort_by(|a, b| {
+        (a.chapter_path(), a.block_index()).cmp(&(b.chapter_path(), b.block_index()))
+    });
+
+    let Some(max_reported) = max_reported.filter(|&max| max < failures.len()) else {
+        return (failures, None);
+    };
+    let omitted = failures.split_off(max_reported);
+    let omitted_files: BTreeSet<&Path> = omitted.iter().map(|r| r.chapter_path()).collect();
+    let summary = format!(
+        "… and {} more failure(s) in {} file(s) (set max_reported_errors = 0 to show all)",
+        omitted.len(),
+        omitted_files.len()
+    );
+    (failures, Some(summary))
+}
+
+/// Prints the diagnostics and source of a single failed block.
+fn print_failure(result: &CompilationResult, show_line_numbers: bool) {
+    print_error("Compilation failed");
+    print_error(format!("File: {}", result.chapter_path().display()));
+    print_error(format!(
+        "Block: {} ({})",
+        result.block_label(),
+        result.language()
+    ));
+    if let Some(heading) = &result.block().heading {
+        print_error(format!("Section: {}", heading));
+    }
+    if let Some(kept) = result.kept_source() {
+        print_error(format!("Source kept at: {}", kept.display()));
+    }
+    print_error("");
+
+    if let Some(error_msg) = result.error_message() {
+        for line in error_msg.lines() {
+            print_error(line);
+        }
+    }
+
+    for (label, output) in [
+        ("Program stdout:", result.run_stdout()),
+        ("Program stderr:", result.run_stderr()),
+    ] {
+        if let Some(output) = output.filter(|o| !o.is_empty()) {
+            print_error("");
+            print_error(label);
+            for line in output.lines() {
                 print_error(line);
             }
         }
+    }
 
-        print_error("");
-        print_error("Code block:");
-        print_error(format!("```{}", result.language()));
+    print_error("");
+    print_error("Code block:");
+    print_code_line(format!("```{}", result.language()));
 
+    if show_line_numbers {
+        for line in number_lines(result.code()) {
+            print_code_line(line);
+        }
+    } else {
         for line in result.code().lines() {
-            print_error(line);
+            print_code_line(line);
         }
+    }
+
+    print_code_line("```");
+    print_error("");
+}
 
-        print_error("```");
-        print_error("");
+/// Reports blocks that compile only with propagated code.
+///
+/// This is informational and never fails the build.
+pub fn report_context_dependent_blocks(dependent: &[&CompilationResult]) {
+    if dependent.is_empty() {
+        print_info("All blocks using propagated code also compile standalone");
+        return;
     }
 
-    let failed_files: HashSet<_> = failed_results.iter().map(|r| r.chapter_path()).collect();
-    print_error("Failed to compile code in the following files:");
-    for file in failed_files {
-        print_error(format!("  {}", file.display()));
+    print_info(format!(
+        "{} block(s) compile only with propagated code (context-dependent):",
+        dependent.len()
+    ));
+    for result in dependent {
+        print_info(format!(
+            "  {} block {} ({})",
+            result.chapter_path().display(),
+            result.block_label(),
+            result.language()
+        ));
     }
-    print_error("Code compilation failed");
+}
 
-    anyhow::bail!("Code compilation failed");
+/// Reports configured variants that no block uses.
+///
+/// This is informational and never fails the build.
+pub fn report_unused_variants(unused: &[String]) {
+    if unused.is_empty() {
+        print_info("All configured variants are used");
+        return;
+    }
+
+    print_info(format!(
+        "{} configured variant(s) are not used by any block:",
+        unused.len()
+    ));
+    for variant in unused {
+        print_info(format!("  {}", variant));
+    }
+}
+
+/// Prints the compilations a dry run would perform: for each task its
+/// chapter, block, language, and compiler command with its source files.
+pub fn report_dry_run(tasks: &[CompilationTask]) {
+    print_info(format!(
+        "Dry run: {} compilation(s) would run, no compiler was invoked:",
+        tasks.len()
+    ));
+    for task in tasks {
+        let (_, command) = task.compile_command();
+        print_info(format!(
+            "  {} block {}: {} ({})",
+            task.chapter_path().display(),
+            task.block_label(),
+            task.language(),
+            command.join(" ")
+        ));
+    }
+}
+
+/// Prints a warning listing code blocks that were skipped because their
+/// language has no configured checker.
+pub fn report_unconfigured_blocks(unconfigured: &[String]) {
+    if unconfigured.is_empty() {
+        return;
+    }
+
+    print_warning(format!(
+        "{} code block(s) have no configured checker and were not checked:",
+        unconfigured.len()
+    ));
+    for block in unconfigured {
+        print_warning(format!("  {}", block));
+    }
+}
+
+/// Prints the code blocks marked `ignore`, with their reasons, if the
+/// verbosity is verbose.
+pub fn report_ignored_blocks(ignored: &[String]) {
+    if verbosity() != Verbosity::Verbose || ignored.is_empty() {
+        return;
+    }
+
+    print_info(format!("{} code block(s) are ignored:", ignored.len()));
+    for block in ignored {
+        print_info(format!("  {}", block));
+    }
+}
+
+/// Prefixes each line of `code` with its 1-based line number, right-aligned
+/// to the width of the largest number.
+fn number_lines(code: &str) -> Vec<String> {
+    let width = code.lines().count().max(1).to_string().len();
+    code.lines()
+        .enumerate()
+        .map(|(index, line)| format!("{:>width$} | {}", index + 1, line, width = width))
+        .collect()
 }
 
 /// Prints compilation statistics to stderr.
 ///
 /// Shows:
-/// - Total blocks validated with per-language counts
+/// - Total blocks validated with per-language counts, noting how many
+///   `should_fail` blocks failed as expected
 /// - Total time and average time per block
-/// - Detailed per-language timing (RUST_LOG=debug)
+/// - Detailed per-language timing (verbose, otherwise RUST_LOG=debug)
 /// - Individual block timings (RUST_LOG=debug)
-pub fn print_compilation_statistics(results: &[CompilationResult], parallel_duration: Duration) {
+/// - Per-chapter block counts in a collapsed CI group, if `groups` is set
+///
+/// Only the debug logging remains when the verbosity is quiet.
+pub fn print_compilation_statistics(
+    results: &[CompilationResult],
+    parallel_duration: Duration,
+    groups: Option<CiGroups>,
+) {
+    let verbosity = verbosity();
+    let mut lines = statistics_lines(results, parallel_duration, verbosity).into_iter();
+    if let Some(summary) = lines.next() {
+        print_success(summary);
+    }
+    for line in lines {
+        print_info(line);
+    }
+
     let successful_results: Vec<_> = results.iter().filter(|r| r.success()).collect();
     let total_blocks = successful_results.len();
 
-    let mut lang_counts: HashMap<String, usize> = HashMap::new();
-    for result in &successful_results {
-        *lang_counts
-            .entry(result.language().to_string())
-            .or_insert(0) += 1;
+    // Per-chapter successes are only worth printing when they collapse
+    if groups.is_some() && verbosity != Verbosity::Quiet {
+        let mut chapter_counts: BTreeMap<&Path, usize> = BTreeMap::new();
+        for result in &successful_results {
+            *chapter_counts.entry(result.chapter_path()).or_insert(0) += 1;
+        }
+        in_group(
+            groups,
+            "validated-chapters",
+            &format!("Validated {} code block(s) by chapter", total_blocks),
+            || {
+                for (chapter, count) in &chapter_counts {
+                    print_info(format!("  {}: {} block(s)", chapter.display(), count));
+                }
+            },
+        );
     }
 
-    let mut sorted_stats: Vec<_> = lang_counts.iter().collect();
-    sorted_stats.sort_by_key(|(lang, _)| *lang);
+    if verbosity != Verbosity::Verbose {
+        log::debug!("Timing breakdown by language:");
+        for line in language_timing_lines(&language_stats(results)) {
+            log::debug!("{}", line);
+        }
+    }
 
-    let stats_str = sorted_stats
+    log::debug!("Individual compilation timings:");
+    for result in results {
+        log::debug!(
+            "[CODE_COMPILE_TIME] [{}] {} block #{}: {}ms",
+            result.language(),
+            result.chapter_path().display(),
+            result.block_index(),
+            result.duration().as_millis()
+        );
+    }
+}
+
+/// Info lines of the statistics summary for the given verbosity: none when
+/// quiet, the success summary and total time otherwise, followed by the
+/// per-language timing when verbose.
+fn statistics_lines(
+    results: &[CompilationResult],
+    parallel_duration: Duration,
+    verbosity: Verbosity,
+) -> Vec<String> {
+    if verbosity == Verbosity::Quiet {
+        return Vec::new();
+    }
+
+    let stats = language_stats(results);
+    let total_blocks: usize = stats.values().map(|s| s.passed).sum();
+
+    let stats_str = stats
         .iter()
-        .map(|(lang, count)| format!("{}: {}", lang, count))
+        .filter(|(_, s)| s.passed > 0)
+        .map(|(lang, s)| format!("{}: {}", lang, s.passed))
         .collect::<Vec<_>>()
         .join(", ");
 
@@ -121,34 +715,857 @@ pub fn print_compilation_statistics(results: &[CompilationResult], parallel_dura
     };
     let parallel_ms = parallel_duration.as_millis();
 
-    print_info(format!(
-        "Successfully validated {} code block(s) ({})",
-        total_blocks, stats_str
-    ));
-    print_info(format!(
-        "Preprocessor finished in {}ms (avg {}ms per block)",
-        parallel_ms, avg_ms
-    ));
+    let expected_failures = results
+        .iter()
+        .filter(|r| r.success() && r.block().should_fail)
+        .count();
+    let expected_str = if expected_failures > 0 {
+        format!(", {} failed as expected", expected_failures)
+    } else {
+        String::new()
+    };
+    let cached = results.iter().filter(|r| r.is_cached()).count();
+    let cached_str = if cached > 0 {
+        format!(", {} from cache", cached)
+    } else {
+        String::new()
+    };
+
+    let mut lines = vec![
+        format!(
+            "Successfully validated {} code block(s) ({}{}{})",
+            total_blocks, stats_str, expected_str, cached_str
+        ),
+        format!(
+            "Preprocessor finished in {}ms (avg {}ms per block)",
+            parallel_ms, avg_ms
+        ),
+    ];
+    if verbosity == Verbosity::Verbose {
+        lines.push("Timing breakdown by language:".to_string());
+        lines.extend(language_timing_lines(&stats));
+    }
+    lines
+}
+
+/// Block counts and compilation time of one language.
+#[derive(Default)]
+struct LanguageStats {
+    passed: usize,
+    failed: usize,
+    /// Total compilation time of the passed blocks
+    passed_duration: Duration,
+}
+
+/// Aggregates `results` by language, sorted by language.
+fn language_stats(results: &[CompilationResult]) -> BTreeMap<String, LanguageStats> {
+    let mut stats: BTreeMap<String, LanguageStats> = BTreeMap::new();
+    for result in results {
+        let entry = stats.entry(result.language().to_string()).or_default();
+        if result.success() {
+            entry.passed += 1;
+            entry.passed_duration += result.duration();
+        } else {
+            entry.failed += 1;
+        }
+    }
+    stats
+}
+
+/// Average compilation time of the successful blocks of each language,
+/// sorted by language.
+fn language_timing_lines(stats: &BTreeMap<String, LanguageStats>) -> Vec<String> {
+    stats
+        .iter()
+        .filter(|(_, s)| s.passed > 0)
+        .map(|(lang, s)| {
+            let lang_avg_ms = s.passed_duration.as_millis() / s.passed as u128;
+            format!("  {}: avg {}ms over {} blocks", lang, lang_avg_ms, s.passed)
+        })
+        .collect()
+}
 
-    log::debug!("Timing breakdown by language:");
-    for (lang, count) in sorted_stats {
-        let lang_results: Vec<_> = successful_results
+/// Writes a Markdown summary of the compilation results to `path`, for
+/// posting as a pull request comment.
+///
+/// The summary holds a table of passed and failed blocks per language, the
+/// total time `duration`, and a collapsed `<details>` section per failed
+/// block with its code and compiler output.
+///
+/// # Errors
+///
+/// Returns an error if the summary file cannot be written.
+pub fn write_summary(results: &[CompilationResult], duration: Duration, path: &Path) -> Result<()> {
+    let mut markdown = String::from("## mdbook-check-code\n\n");
+    markdown.push_str("| Language | Passed | Failed |\n| --- | ---: | ---: |\n");
+    for (language, stats) in language_stats(results) {
+        let _ = writeln!(
+            markdown,
+            "| {} | {} | {} |",
+            language, stats.passed, stats.failed
+        );
+    }
+    let _ = writeln!(markdown, "\nTotal time: {}ms", duration.as_millis());
+
+    for result in results.iter().filter(|r| !r.success()) {
+        let _ = write!(
+            markdown,
+            "\n<details>\n<summary>{} block {} ({})</summary>\n\n",
+            escape_xml(&result.chapter_path().display().to_string()),
+            result.block_label(),
+            result.language()
+        );
+        push_fenced(
+            &mut markdown,
+            result.language().base_language(),
+            result.code(),
+        );
+        if let Some(error_msg) = result.error_message() {
+            markdown.push('\n');
+            push_fenced(&mut markdown, "text", error_msg);
+        }
+        markdown.push_str("\n</details>\n");
+    }
+
+    fs::write(path, markdown)
+        .with_context(|| format!("Failed to write summary: {}", path.display()))
+}
+
+/// Appends `content` to `markdown` as a fenced code block whose fence is
+/// longer than any backtick run in `content`.
+fn push_fenced(markdown: &mut String, info: &str, content: &str) {
+    let longest_run = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    let _ = writeln!(
+        markdown,
+        "{}{}\n{}\n{}",
+        fence,
+        info,
+        content.trim_end_matches('\n'),
+        fence
+    );
+}
+
+/// Version of the JSON report format, incremented whenever existing fields
+/// change meaning or are removed.
+pub const JSON_REPORT_VERSION: u32 = 1;
+
+/// Top-level object of the JSON report.
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    version: u32,
+    blocks: Vec<JsonBlock<'a>>,
+}
+
+/// One validated block in the JSON report.
+#[derive(Serialize)]
+struct JsonBlock<'a> {
+    chapter: String,
+    block_index: usize,
+    language: &'a str,
+    variant: Option<&'a str>,
+    success: bool,
+    duration_ms: f64,
+    error: Option<&'a str>,
+}
+
+/// Writes a JSON report of all compilation results to `path`.
+///
+/// The report is an object with a `version` field ([`JSON_REPORT_VERSION`])
+/// and a `blocks` array holding, per result, the chapter path, the block's
+/// index in the chapter, the base language and variant, whether it succeeded,
+/// its duration in milliseconds, and the error message of failed blocks.
+///
+/// # Errors
+///
+/// Returns an error if the report file cannot be written.
+pub fn write_json_report(results: &[CompilationResult], path: &Path) -> Result<()> {
+    let report = JsonReport {
+        version: JSON_REPORT_VERSION,
+        blocks: results
             .iter()
-            .filter(|r| &r.language().to_string() == lang)
-            .collect();
-        let lang_total: Duration = lang_results.iter().map(|r| r.duration()).sum();
-        let lang_avg_ms = lang_total.as_millis() / *count as u128;
-        log::debug!("  {}: avg {}ms over {} blocks", lang, lang_avg_ms, count);
+            .map(|result| JsonBlock {
+                chapter: result.chapter_path().display().to_string(),
+                block_index: result.block_index(),
+                language: result.language().base_language(),
+                variant: result.language().variant(),
+                success: result.success(),
+                duration_ms: result.duration().as_secs_f64() * 1000.0,
+                error: result.error_message(),
+            })
+            .collect(),
+    };
+
+    let json = serde_json::to_string_pretty(&report).context("Failed to serialize JSON report")?;
+    fs::write(path, json)
+        .with_context(|| format!("Failed to write JSON report: {}", path.display()))
+}
+
+/// An entry of a `compile_commands.json` compilation database.
+#[derive(Serialize)]
+struct CompileCommand {
+    directory: String,
+    file: String,
+    arguments: Vec<String>,
+}
+
+/// Writes a clang compilation database describing how each C-family code
+/// block in `tasks` is compiled to `path`, one entry per source file.
+///
+/// Propagated context and standalone checks are left out, since they compile
+/// the same code again.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be written.
+pub fn write_compile_commands(tasks: &[CompilationTask], path: &Path) -> Result<()> {
+    let mut commands = Vec::new();
+    for task in tasks {
+        if !task.language().is_c_family()
+            || !matches!(task.kind(), TaskKind::Block | TaskKind::DependentBlock)
+        {
+            continue;
+        }
+        let (sources, arguments) = task.compile_command();
+        for source in sources {
+            commands.push(CompileCommand {
+                directory: source
+                    .parent()
+                    .unwrap_or(Path::new(""))
+                    .display()
+                    .to_string(),
+                file: source.display().to_string(),
+                arguments: arguments.clone(),
+            });
+        }
     }
 
-    log::debug!("Individual compilation timings:");
+    let json = serde_json::to_string_pretty(&commands)
+        .context("Failed to serialize compilation database")?;
+    fs::write(path, json)
+        .with_context(|| format!("Failed to write compilation database: {}", path.display()))
+}
+
+/// Writes a SARIF 2.1.0 log of the failed blocks in `results` to `path`.
+///
+/// Each compiler gets its own run, with the compiler as the tool driver, and
+/// each failed block becomes a `result` whose message is the compiler output.
+/// Locations use the chapter path relative to the repository root (with
+/// chapters found in `src_dir`) and the same line as GitHub annotations (see
+/// [`GitHubAnnotations`]). Compilers without failures get a run with no
+/// results, so a clean build clears earlier alerts.
+///
+/// # Errors
+///
+/// Returns an error if the log file cannot be written.
+pub fn write_sarif(results: &[CompilationResult], path: &Path, src_dir: &Path) -> Result<()> {
+    let src_dir = relative_to_repo_root(src_dir);
+    let mut runs: BTreeMap<&str, Vec<serde_json::Value>> = BTreeMap::new();
     for result in results {
-        log::debug!(
-            "[CODE_COMPILE_TIME] [{}] {} block #{}: {}ms",
-            result.language(),
-            result.chapter_path().display(),
-            result.block_index(),
-            result.duration().as_millis()
+        let run = runs.entry(result.language().compiler()).or_default();
+        if result.success() {
+            continue;
+        }
+
+        let file = src_dir.join(result.chapter_path());
+        run.push(serde_json::json!({
+            "ruleId": "compilation-failed",
+            "level": "error",
+            "message": {
+                "text": format!(
+                    "{} block {} failed\n{}",
+                    result.language(),
+                    result.block_label(),
+                    result.error_message().unwrap_or_default()
+                ),
+            },
+            "locations": [{
+                "physicalLocation": {
+                    "artifactLocation": { "uri": file.display().to_string() },
+                    "region": { "startLine": annotation_line(result) },
+                },
+            }],
+        }));
+    }
+
+    let sarif = serde_json::json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": runs
+            .into_iter()
+            .map(|(compiler, results)| serde_json::json!({
+                "tool": {
+                    "driver": {
+                        "name": compiler,
+                        "informationUri": "https://github.com/Sunscreen-tech/mdbook-check-code",
+                        "rules": [{
+                            "id": "compilation-failed",
+                            "shortDescription": { "text": "Code block failed to compile" },
+                        }],
+                    },
+                },
+                "results": results,
+            }))
+            .collect::<Vec<_>>(),
+    });
+
+    let json = serde_json::to_string_pretty(&sarif).context("Failed to serialize SARIF log")?;
+    fs::write(path, json).with_context(|| format!("Failed to write SARIF log: {}", path.display()))
+}
+
+/// Writes a JUnit XML report of all compilation results to `path`.
+///
+/// Results are grouped into one `<testsuite>` per language. Each code block
+/// becomes a `<testcase>` named after its chapter and block index, and failed
+/// blocks carry a `<failure>` element containing the compiler output. In run
+/// mode, each program's output is included as `<system-out>` and `<system-err>`.
+///
+/// # Errors
+///
+/// Returns an error if the report file cannot be written.
+pub fn write_junit_report(results: &[CompilationResult], path: &Path) -> Result<()> {
+    let mut suites: BTreeMap<String, Vec<&CompilationResult>> = BTreeMap::new();
+    for result in results {
+        suites
+            .entry(result.language().to_string())
+            .or_default()
+            .push(result);
+    }
+
+    let total_failures = results.iter().filter(|r| !r.success()).count();
+    let total_time: Duration = results.iter().map(|r| r.duration()).sum();
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"mdbook-check-code\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+        results.len(),
+        total_failures,
+        total_time.as_secs_f64()
+    );
+
+    for (language, cases) in &suites {
+        let failures = cases.iter().filter(|r| !r.success()).count();
+        let time: Duration = cases.iter().map(|r| r.duration()).sum();
+        let _ = writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+            escape_xml(language),
+            cases.len(),
+            failures,
+            time.as_secs_f64()
         );
+
+        for result in cases {
+            let name = format!(
+                "{} block {}",
+                result.chapter_path().display(),
+                result.block_label()
+            );
+            let _ = writeln!(
+                xml,
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\">",
+                escape_xml(&name),
+                escape_xml(language),
+                result.duration().as_secs_f64()
+            );
+
+            xml.push_str("      <properties>\n");
+            for (key, value) in junit_properties(result) {
+                let _ = writeln!(
+                    xml,
+                    "        <property name=\"{}\" value=\"{}\"/>",
+                    key,
+                    escape_xml(&value)
+                );
+            }
+            xml.push_str("      </properties>\n");
+
+            if let Some(error_msg) = result.error_message() {
+                let _ = writeln!(
+                    xml,
+                    "      <failure message=\"Compilation failed\">{}</failure>",
+                    escape_xml(error_msg)
+                );
+            }
+            if let Some(output) = result.run_stdout() {
+                let _ = writeln!(xml, "      <system-out>{}</system-out>", escape_xml(output));
+            }
+            if let Some(output) = result.run_stderr() {
+                let _ = writeln!(xml, "      <system-err>{}</system-err>", escape_xml(output));
+            }
+            xml.push_str("    </testcase>\n");
+        }
+
+        xml.push_str("  </testsuite>\n");
+    }
+
+    xml.push_str("</testsuites>\n");
+
+    fs::write(path, xml)
+        .with_context(|| format!("Failed to write JUnit report: {}", path.display()))
+}
+
+/// Collects the per-block metadata recorded as JUnit test case properties.
+fn junit_properties(result: &CompilationResult) -> Vec<(&'static str, String)> {
+    let block = result.block();
+    let mut properties = vec![
+        ("compiler", result.language().compiler().to_string()),
+        ("flags", result.language().flags().join(" ")),
+        ("propagate", block.propagate.to_string()),
+    ];
+    if let Some(variant) = &block.variant {
+        properties.push(("variant", variant.clone()));
+    }
+    if let Some(heading) = &block.heading {
+        properties.push(("heading", heading.clone()));
+    }
+    properties
+}
+
+/// Escapes the XML special characters in `s` for use in text and attribute values.
+///
+/// Control characters other than tab, line feed and carriage return are not
+/// allowed in XML 1.0 even as character references, so they are dropped, as
+/// are whole ANSI escape sequences such as compiler color codes.
+fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // A control sequence ends at its first byte in '@'..='~'
+            '\x1b' if chars.next_if_eq(&'[').is_some() => {
+                for next in chars.by_ref() {
+                    if ('@'..='~').contains(&next) {
+                        break;
+                    }
+                }
+            }
+            '\t' | '\n' | '\r' => escaped.push(ch),
+            _ if ch < ' ' => {}
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::LanguageConfig;
+    use crate::extractor::CodeBlock;
+    use crate::language::ConfiguredLanguage;
+
+    /// Compiles `code` as block `index` of `chapter` in `language` (a variant
+    /// is given as e.g. `c-parasol`), configured by `config`: the compiler
+    /// name, or the language's settings as TOML.
+    pub(crate) async fn compiled(
+        language: &str,
+        config: &str,
+        chapter: &str,
+        index: usize,
+        code: &str,
+    ) -> CompilationResult {
+        let config: LanguageConfig = if config.contains('=') {
+            toml::from_str(config).unwrap()
+        } else {
+            toml::from_str(&format!("compiler = {:?}", config)).unwrap()
+        };
+        let (language, variant) = match language.split_once('-') {
+            Some((language, variant)) => (language, Some(variant.to_string())),
+            None => (language, None),
+        };
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        CompilationTask::new(
+            ConfiguredLanguage::new(language.to_string(), variant, config),
+            temp_dir.path().join(format!("block_{}.c", index)),
+            PathBuf::from(chapter),
+            index,
+            CodeBlock::default(),
+            code.to_string(),
+        )
+        .compile()
+        .await
+    }
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(
+            escape_xml(r#"a < b && c > "d" 'e'"#),
+            "a &lt; b &amp;&amp; c &gt; &quot;d&quot; &apos;e&apos;"
+        );
+        assert_eq!(escape_xml("plain text"), "plain text");
+        assert_eq!(
+            escape_xml("\x1b[1;31merror\x1b[0m: a\tb\x07\x1bc\r\n"),
+            "error: a\tbc\r\n"
+        );
+    }
+
+    #[test]
+    fn test_workflow_command_escaping() {
+        assert_eq!(escape_data("50% done\r\nnext"), "50%25 done%0D%0Anext");
+        assert_eq!(escape_property("src/a,b:c.md"), "src/a%2Cb%3Ac.md");
+    }
+
+    #[test]
+    fn test_diagnostic_line() {
+        assert_eq!(
+            diagnostic_line("/tmp/x/c_intro_block_0.c:12:5: error: boom", ".c"),
+            Some(12)
+        );
+        assert_eq!(
+            diagnostic_line("/tmp/x/ts_intro_block_0.ts(7,3): error TS2304", ".ts"),
+            Some(7)
+        );
+        assert_eq!(diagnostic_line("File: /tmp/x/a.c\n\nerror", ".c"), None);
+    }
+
+    #[tokio::test]
+    async fn test_annotation_line_maps_into_chapter() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let result = |diagnostic_line: usize| {
+            let mut config: LanguageConfig = toml::from_str(
+                r##"
+                compiler = "sh"
+                preamble = "#include <stdio.h>"
+                "##,
+            )
+            .unwrap();
+            config.flags = vec![
+                "-c".to_string(),
+                format!("echo \"$0:{}:1: error: boom\" >&2; exit 1", diagnostic_line),
+            ];
+            let block = CodeBlock {
+                line: 10,
+                compile_code: "int a;\nint b;\nint c;\n".to_string(),
+                ..CodeBlock::default()
+            };
+            let code = block.compile_code.clone();
+            CompilationTask::new(
+                ConfiguredLanguage::new("c".to_string(), None, config),
+                temp_dir.path().join(format!("block_{}.c", diagnostic_line)),
+                PathBuf::from("intro.md"),
+                0,
+                block,
+                code,
+            )
+            .compile()
+        };
+
+        // The block starts on line 3 of the source, after the preamble and a
+        // blank line, and on line 11 of the chapter, after the fence
+        assert_eq!(annotation_line(&result(4).await), 12);
+        assert_eq!(annotation_line(&result(1).await), 10);
+        assert_eq!(annotation_line(&result(6).await), 10);
+    }
+
+    #[tokio::test]
+    async fn test_write_json_report() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let results = vec![
+            compiled("c-parasol", "true", "intro.md", 0, "int x;").await,
+            compiled("c-parasol", "false", "intro.md", 2, "int x;").await,
+        ];
+
+        let path = temp_dir.path().join("report.json");
+        write_json_report(&results, &path).unwrap();
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+
+        assert_eq!(report["version"], JSON_REPORT_VERSION);
+        let blocks = report["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0]["chapter"], "intro.md");
+        assert_eq!(blocks[0]["block_index"], 0);
+        assert_eq!(blocks[0]["language"], "c");
+        assert_eq!(blocks[0]["variant"], "parasol");
+        assert_eq!(blocks[0]["success"], true);
+        assert!(blocks[0]["duration_ms"].is_number());
+        assert!(blocks[0]["error"].is_null());
+        assert_eq!(blocks[1]["block_index"], 2);
+        assert_eq!(blocks[1]["success"], false);
+        assert!(blocks[1]["error"].is_string());
+    }
+
+    #[test]
+    fn test_write_compile_commands() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let task = |language: &str, settings: &str, name: &str| {
+            let config: LanguageConfig = toml::from_str(settings).unwrap();
+            CompilationTask::new(
+                ConfiguredLanguage::new(language.to_string(), None, config),
+                temp_dir.path().join(name),
+                PathBuf::from("intro.md"),
+                0,
+                CodeBlock::default(),
+                "int x;".to_string(),
+            )
+        };
+        let tasks = vec![
+            task(
+                "c",
+                r#"compiler = "gcc"
+                flags = ["-fsyntax-only", "-o", "{artifact}"]"#,
+                "block_0.c",
+            ),
+            task("python", r#"compiler = "python3""#, "block_1.py"),
+            task("c", r#"compiler = "gcc""#, "block_2.c").with_kind(TaskKind::Standalone),
+        ];
+
+        let path = temp_dir.path().join("compile_commands.json");
+        write_compile_commands(&tasks, &path).unwrap();
+        let commands: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+
+        let commands = commands.as_array().unwrap();
+        assert_eq!(commands.len(), 1);
+        let source = temp_dir.path().join("block_0.c");
+        assert_eq!(
+            commands[0]["directory"],
+            temp_dir.path().display().to_string()
+        );
+        assert_eq!(commands[0]["file"], source.display().to_string());
+        assert_eq!(
+            commands[0]["arguments"],
+            serde_json::json!([
+                "gcc",
+                "-fsyntax-only",
+                "-o",
+                temp_dir.path().join("block_0.out").display().to_string(),
+                source.display().to_string(),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_sarif_reports_failures() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = r#"
+            compiler = "sh"
+            flags = ["-c", "grep -q ok \"$0\""]
+            "#;
+        let mut results = Vec::new();
+        for (index, code) in ["ok", "bad", "ok", "bad", "bad"].into_iter().enumerate() {
+            results.push(compiled("c", config, "intro.md", index, code).await);
+        }
+        let failures = results.iter().filter(|r| !r.success()).count();
+        assert_eq!(failures, 3);
+
+        let path = temp_dir.path().join("results.sarif");
+        write_sarif(&results, &path, temp_dir.path()).unwrap();
+        let sarif: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+
+        assert_eq!(sarif["version"], "2.1.0");
+        assert_eq!(sarif["runs"][0]["tool"]["driver"]["name"], "sh");
+        let sarif_results = sarif["runs"][0]["results"].as_array().unwrap();
+        assert_eq!(sarif_results.len(), failures);
+        assert!(
+            sarif_results[0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"]
+                .as_str()
+                .unwrap()
+                .ends_with("intro.md")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_junit_report() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = r#"
+            compiler = "sh"
+            flags = ["-c", "echo \"error: expected '<' & got >\" >&2; grep -q ok \"$0\""]
+            "#;
+        let results = vec![
+            compiled("c", config, "a&b.md", 0, "ok").await,
+            compiled("c", config, "a&b.md", 1, "bad").await,
+        ];
+
+        let path = temp_dir.path().join("junit.xml");
+        write_junit_report(&results, &path).unwrap();
+        let xml = fs::read_to_string(&path).unwrap();
+
+        // Structure required by the JUnit schema: counts on the suites and
+        // one testcase per block, passing ones included
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites "));
+        assert!(xml.contains("<testsuites name=\"mdbook-check-code\" tests=\"2\" failures=\"1\""));
+        assert!(xml.contains("<testsuite name=\"c\" tests=\"2\" failures=\"1\""));
+        assert_eq!(xml.matches("<testcase ").count(), 2);
+        assert_eq!(xml.matches("<failure ").count(), 1);
+        assert!(xml.contains("name=\"a&amp;b.md block #0\" classname=\"c\" time=\""));
+
+        // Special characters in the compiler output are escaped
+        assert!(xml.contains("error: expected &apos;&lt;&apos; &amp; got &gt;"));
+        let failure = xml.split("<failure ").nth(1).unwrap();
+        let text = &failure[failure.find('>').unwrap() + 1..failure.find("</failure>").unwrap()];
+        assert!(!text.contains('<') && !text.contains('>'));
+    }
+
+    #[tokio::test]
+    async fn test_write_summary() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let code = "int x = ```;";
+        let results = vec![
+            compiled("c", "true", "intro.md", 0, code).await,
+            compiled("c", "false", "intro.md", 1, code).await,
+            compiled("cpp", "true", "intro.md", 2, code).await,
+        ];
+
+        let path = temp_dir.path().join("summary.md");
+        write_summary(&results, Duration::from_millis(42), &path).unwrap();
+        let markdown = fs::read_to_string(&path).unwrap();
+
+        assert!(markdown.contains("| c | 1 | 1 |\n| cpp | 1 | 0 |\n"));
+        assert!(markdown.contains("Total time: 42ms"));
+        assert_eq!(markdown.matches("<details>").count(), 1);
+        assert!(markdown.contains("<summary>intro.md block #1 (c)</summary>"));
+        // The fence outlasts the backticks inside the block
+        assert!(markdown.contains("````c\nint x = ```;\n````\n"));
+    }
+
+    #[tokio::test]
+    async fn test_junit_report_strips_colored_output() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = r#"
+            compiler = "sh"
+            flags = ["-c", "printf '\\033[1;31merror:\\033[0m boom\\a\\n' >&2; exit 1"]
+            "#;
+        let results = vec![compiled("c", config, "intro.md", 0, "int x;").await];
+        assert!(results[0].error_message().unwrap().contains('\x1b'));
+
+        let path = temp_dir.path().join("junit.xml");
+        write_junit_report(&results, &path).unwrap();
+        let xml = fs::read_to_string(&path).unwrap();
+        assert!(xml.contains("error: boom"));
+        assert!(!xml
+            .chars()
+            .any(|c| c < ' ' && !matches!(c, '\t' | '\n' | '\r')));
+    }
+
+    #[tokio::test]
+    async fn test_statistics_lines_by_verbosity() {
+        let results = vec![compiled("c", "true", "intro.md", 0, "int x;").await];
+        let duration = Duration::from_millis(5);
+
+        assert!(statistics_lines(&results, duration, Verbosity::Quiet).is_empty());
+
+        let normal = statistics_lines(&results, duration, Verbosity::Normal);
+        assert_eq!(normal.len(), 2);
+        assert_eq!(normal[0], "Successfully validated 1 code block(s) (c: 1)");
+        assert!(normal[1].starts_with("Preprocessor finished in 5ms"));
+
+        let verbose = statistics_lines(&results, duration, Verbosity::Verbose);
+        assert_eq!(verbose[..2], normal[..]);
+        assert_eq!(verbose[2], "Timing breakdown by language:");
+        assert!(verbose[3].starts_with("  c: avg "));
+        assert!(verbose[3].ends_with("ms over 1 blocks"));
+    }
+
+    #[test]
+    fn test_set_verbosity() {
+        for level in [Verbosity::Quiet, Verbosity::Verbose, Verbosity::Normal] {
+            set_verbosity(level);
+            assert_eq!(verbosity(), level);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_report_compilation_errors_truncates() {
+        let mut results = Vec::new();
+        // 20 failures over 4 chapters, collected in reverse order
+        for index in (0..20).rev() {
+            let chapter = format!("chapter_{}.md", index % 4);
+            results.push(compiled("c", "false", &chapter, index, "int x;").await);
+        }
+        let failed: Vec<_> = results.iter().collect();
+
+        let (reported, summary) = truncate_failures(&failed, Some(3));
+        let reported: Vec<_> = reported
+            .iter()
+            .map(|r| (r.chapter_path().display().to_string(), r.block_index()))
+            .collect();
+        assert_eq!(
+            reported,
+            [
+                ("chapter_0.md".to_string(), 0),
+                ("chapter_0.md".to_string(), 4),
+                ("chapter_0.md".to_string(), 8),
+            ]
+        );
+        assert_eq!(
+            summary.unwrap(),
+            "… and 17 more failure(s) in 4 file(s) (set max_reported_errors = 0 to show all)"
+        );
+
+        let (reported, summary) = truncate_failures(&failed, None);
+        assert_eq!(reported.len(), 20);
+        assert!(summary.is_none());
+
+        // Truncated output still fails the build
+        assert!(report_compilation_errors(&failed, false, Some(3), None, None).is_err());
+    }
+
+    #[test]
+    fn test_format_message_color() {
+        let plain = format_message("ERROR", "oops", CODE_STYLE, false);
+        assert!(plain.ends_with(" [ERROR] (mdbook_check_code): oops"));
+        assert!(!plain.contains('\x1b'));
+
+        let colored = format_message("ERROR", "oops", CODE_STYLE, true);
+        assert!(colored.contains('\x1b'));
+        assert!(colored.contains("ERROR"));
+        assert!(colored.contains("oops"));
+    }
+
+    #[test]
+    fn test_color_forced_off() {
+        anstream::ColorChoice::Never.write_global();
+        assert!(!color_enabled());
+        let line = format_message("INFO", "done", SUCCESS_STYLE, color_enabled());
+        assert!(!line.contains('\x1b'));
+        anstream::ColorChoice::Auto.write_global();
+    }
+
+    #[tokio::test]
+    async fn test_missing_compiler_reported_once() {
+        let mut results = Vec::new();
+        for (index, compiler) in ["no-such-cc", "false", "no-such-cc", "no-such-cc"]
+            .into_iter()
+            .enumerate()
+        {
+            results.push(compiled("c", compiler, "intro.md", index, "int x;").await);
+        }
+        let failed: Vec<_> = results.iter().collect();
+
+        let (missing, failures) = split_missing_compilers(&failed);
+        assert_eq!(missing.len(), 1);
+        assert!(
+            missing[0].starts_with("Compiler 'no-such-cc' for language 'c' was not found on PATH")
+        );
+        assert!(missing[0].ends_with("(3 block(s) not checked)"));
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].block_index(), 1);
+    }
+
+    #[test]
+    fn test_section_id() {
+        assert_eq!(section_id("src/intro page.md"), "src_intro_page.md");
+    }
+
+    #[test]
+    fn test_number_lines() {
+        let code = (1..=10)
+            .map(|i| format!("line{}", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let numbered = number_lines(&code);
+        assert_eq!(numbered.len(), 10);
+        assert_eq!(numbered[0], " 1 | line1");
+        assert_eq!(numbered[9], "10 | line10");
     }
 }
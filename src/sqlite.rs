@@ -0,0 +1,127 @@
+use crate::compilation::CompilationResult;
+use anyhow::{Context, Result};
+use chrono::Local;
+use rusqlite::{params, Connection};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Schema of the results table, created on first export.
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS results (
+    id INTEGER PRIMARY KEY,
+    build_timestamp TEXT NOT NULL,
+    git_ref TEXT,
+    chapter TEXT NOT NULL,
+    block TEXT NOT NULL,
+    language TEXT NOT NULL,
+    duration_ms REAL NOT NULL,
+    success INTEGER NOT NULL
+);";
+
+/// Appends one row per result to the SQLite database at `path`.
+///
+/// All rows of a build share the same timestamp and, when `book_root` is
+/// inside a git checkout, the current commit. The rows are inserted in a
+/// single transaction, so a failed export leaves the database unchanged.
+///
+/// # Errors
+///
+/// Returns an error if the database cannot be opened or written.
+pub fn write_sqlite_report(
+    results: &[CompilationResult],
+    path: &Path,
+    book_root: &Path,
+) -> Result<()> {
+    let timestamp = Local::now().to_rfc3339();
+    let git_ref = current_git_ref(book_root);
+
+    let mut connection = Connection::open(path)
+        .with_context(|| format!("Failed to open SQLite database: {}", path.display()))?;
+    insert_results(&mut connection, results, &timestamp, git_ref.as_deref())
+        .with_context(|| format!("Failed to write SQLite database: {}", path.display()))
+}
+
+/// Creates the results table if needed and inserts all results in a single
+/// transaction.
+fn insert_results(
+    connection: &mut Connection,
+    results: &[CompilationResult],
+    timestamp: &str,
+    git_ref: Option<&str>,
+) -> rusqlite::Result<()> {
+    connection.execute_batch(SCHEMA)?;
+
+    let transaction = connection.transaction()?;
+    {
+        let mut insert = transaction.prepare(
+            "INSERT INTO results (build_timestamp, git_ref, chapter, block, language, duration_ms, success) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        for result in results {
+            insert.execute(params![
+                timestamp,
+                git_ref,
+                result.chapter_path().display().to_string(),
+                result.block_label(),
+                result.language().to_string(),
+                result.duration().as_secs_f64() * 1000.0,
+                result.success(),
+            ])?;
+        }
+    }
+    transaction.commit()
+}
+
+/// Returns the commit checked out at `dir`, if it is inside a git repository.
+fn current_git_ref(dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(dir)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reporting::tests::compiled;
+
+    #[tokio::test]
+    async fn test_insert_results() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let results = vec![
+            compiled("c", "true", "it's.md", 0, "int x;").await,
+            compiled("c", "false", "it's.md", 1, "int x;").await,
+        ];
+
+        let path = temp_dir.path().join("results.db");
+        // Each build appends its rows
+        for _ in 0..2 {
+            write_sqlite_report(&results, &path, temp_dir.path()).unwrap();
+        }
+
+        let connection = Connection::open(&path).unwrap();
+        let mut query = connection
+            .prepare("SELECT chapter, block, success, git_ref FROM results ORDER BY id")
+            .unwrap();
+        let rows: Vec<(String, String, bool, Option<String>)> = query
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(
+            rows[..2],
+            [
+                ("it's.md".to_string(), "#0".to_string(), true, None),
+                ("it's.md".to_string(), "#1".to_string(), false, None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+
+/// Default chunk reference syntax, noweb style: `<<chunk-name>>`.
+pub const DEFAULT_TANGLE_REFERENCE: &str = r"<<(?P<name>[^<>\s]+)>>";
+
+/// Expands literate-programming chunk references in code blocks.
+///
+/// Chunks are code blocks carrying a `name=<chunk>` attribute. Blocks that
+/// define the same name are concatenated in document order. A reference
+/// matching the configured pattern is replaced by the chunk's code, expanding
+/// nested references recursively. When a reference is preceded only by
+/// whitespace on its line, that indentation is applied to every inserted line.
+///
+/// # Example
+///
+/// ````markdown
+/// ```c,name=includes
+/// #include <stdio.h>
+/// ```
+///
+/// ```c
+/// <<includes>>
+/// int main(void) { puts("hi"); }
+/// ```
+/// ````
+#[derive(Clone)]
+pub struct Tangler {
+    reference: Regex,
+    chunks: HashMap<String, String>,
+}
+
+impl Tangler {
+    /// Creates a tangler for the given reference pattern, which must contain a
+    /// capture group called `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the pattern is invalid or has no `name` group.
+    pub fn new(reference: &str) -> Result<Self> {
+        let reference = Regex::new(reference)
+            .with_context(|| format!("Invalid tangle_reference pattern: {}", reference))?;
+        if !reference.capture_names().any(|name| name == Some("name")) {
+            anyhow::bail!(
+                "tangle_reference pattern must contain a capture group named 'name': {}",
+                reference
+            );
+        }
+
+        Ok(Self {
+            reference,
+            chunks: HashMap::new(),
+        })
+    }
+
+    /// Appends `code` to the chunk called `name`.
+    pub fn define(&mut self, name: &str, code: &str) {
+        let chunk = self.chunks.entry(name.to_string()).or_default();
+        chunk.push_str(code);
+        if !code.ends_with('\n') {
+            chunk.push('\n');
+        }
+    }
+
+    /// Expands all chunk references in `code`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the chunk if a reference is undefined or if
+    /// chunks reference each other cyclically.
+    pub fn expand(&self, code: &str) -> Result<String> {
+        self.expand_with_stack(code, &mut Vec::new())
+    }
+
+    fn expand_with_stack(&self, code: &str, stack: &mut Vec<String>) -> Result<String> {
+        let mut expanded = String::with_capacity(code.len());
+        let mut last = 0;
+
+        for captures in self.reference.captures_iter(code) {
+            let whole = captures.get(0).expect("group 0 always participates");
+            let name = &captures["name"];
+
+            if stack.iter().any(|n| n == name) {
+                anyhow::bail!("Cyclic chunk reference: {} -> {}", stack.join(" -> "), name);
+            }
+            let chunk = self
+                .chunks
+                .get(name)
+                .with_context(|| format!("Undefined chunk reference: {}", name))?;
+
+            stack.push(name.to_string());
+            let body = self.expand_with_stack(chunk, stack)?;
+            stack.pop();
+
+            expanded.push_str(&code[last..whole.start()]);
+
+            // Indent continuation lines like the reference itself
+            let line_start = code[..whole.start()].rfind('\n').map_or(0, |i| i + 1);
+            let prefix = &code[line_start..whole.start()];
+            let body = body.strip_suffix('\n').unwrap_or(&body);
+            if prefix.chars().all(char::is_whitespace) {
+                expanded.push_str(&body.replace('\n', &format!("\n{}", prefix)));
+            } else {
+                expanded.push_str(body);
+            }
+
+            last = whole.end();
+        }
+
+        expanded.push_str(&code[last..]);
+        Ok(expanded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_nested_and_indented_chunks() {
+        let mut tangler = Tangler::new(DEFAULT_TANGLE_REFERENCE).unwrap();
+        tangler.define("body", "int x = 1;\n<<ret>>\n");
+        tangler.define("ret", "return x;");
+
+        let code = "int main(void) {\n    <<body>>\n}\n";
+        assert_eq!(
+            tangler.expand(code).unwrap(),
+            "int main(void) {\n    int x = 1;\n    return x;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_undefined_and_cyclic_references() {
+        let mut tangler = Tangler::new(DEFAULT_TANGLE_REFERENCE).unwrap();
+        tangler.define("a", "<<b>>");
+        tangler.define("b", "<<a>>");
+
+        let err = tangler.expand("<<missing>>").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Undefined chunk reference: missing"));
+
+        let err = tangler.expand("<<a>>").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Cyclic chunk reference: a -> b -> a"));
+    }
+
+    #[test]
+    fn test_custom_reference_syntax() {
+        assert!(Tangler::new(r"\{\{\w+\}\}").is_err());
+
+        let mut tangler = Tangler::new(r"\{\{(?P<name>\w+)\}\}").unwrap();
+        tangler.define("greeting", "hello");
+        assert_eq!(tangler.expand("say {{greeting}}").unwrap(), "say hello");
+    }
+}
@@ -1,11 +1,24 @@
-use crate::compilation::CompilationTask;
-use crate::extractor::extract_code_blocks_with_propagation;
-use crate::language::LanguageRegistry;
+use crate::chapter_cache::ChapterCache;
+use crate::compilation::{CompilationTask, TaskKind};
+use crate::config::{CheckCodeConfig, EmptyBlockPolicy, Verbosity};
+use crate::extractor::{
+    extract_code_blocks, extract_code_blocks_with_propagation, rewrite_fences, CodeBlock,
+    ExtractOptions,
+};
+use crate::language::{ConfiguredLanguage, FenceResolution, LanguageRegistry, SourceFile};
+use crate::reporting;
+use crate::tangle::{Tangler, DEFAULT_TANGLE_REFERENCE};
 use anyhow::Result;
 use mdbook::book::{Book, BookItem};
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use tempfile::TempDir;
 
+/// Prefix of the hidden source files `compile_in_place` writes next to chapters.
+const IN_PLACE_PREFIX: &str = ".check-code-";
+
 /// Maximum size of a single code block in bytes (1MB)
 pub const MAX_CODE_BLOCK_SIZE: usize = 1_000_000;
 
@@ -15,22 +28,99 @@ pub const MAX_BLOCKS_PER_CHAPTER: usize = 1000;
 /// Collects all compilation tasks from the book.
 ///
 /// Iterates through all chapters, extracts code blocks with propagation,
-/// validates size limits, and builds CompilationTask instances.
+/// validates size limits, and builds CompilationTask instances. Fence info
+/// strings of configured languages are rewritten in place to drop this
+/// preprocessor's attributes and hidden lines (see [`rewrite_fences`]). Blocks whose
+/// fence marker is prose-only (see [`CheckCodeConfig::is_prose_marker`]) are
+/// always skipped.
+///
+/// When `check_propagated_context` is enabled, each chapter also gets one
+/// [`TaskKind::PropagatedContext`] task per language and variant with
+/// `propagate` blocks, compiling that accumulated propagated code on its own.
+///
+/// When `check_standalone` is enabled, every block that receives propagated
+/// code also gets a [`TaskKind::Standalone`] task compiling it without that code.
+///
+/// In tangle mode, blocks with a `name=<chunk>` attribute define chunks and are
+/// not compiled on their own; chunk references in the remaining blocks are
+/// expanded (see [`Tangler`]) before compiling.
+///
+/// Blocks whose own content is whitespace-only are handled according to
+/// [`CheckCodeConfig::empty_block`].
+///
+/// Chapters that `chapter_cache` reports as unchanged are skipped after their
+/// fences are rewritten: no blocks are extracted from them.
+///
+/// Blocks with a `file=<name>` attribute are grouped into multi-file
+/// compilation units: consecutive `file=` blocks of the same fence marker and
+/// variant form one unit, compiled as a single task attributed to the unit's
+/// first block. A unit ends at the next checked block that has no `file=`
+/// attribute, uses another language or variant, or repeats a file name of the
+/// unit (which then starts a new unit). Their code is written verbatim, without
+/// propagated code.
 ///
 /// # Errors
 ///
 /// Returns an error if:
 /// - A chapter exceeds MAX_BLOCKS_PER_CHAPTER
-/// - A code block exceeds MAX_CODE_BLOCK_SIZE
+/// - A code block, with its propagated code and postamble, exceeds
+///   MAX_CODE_BLOCK_SIZE
+/// - A code block violates its language's forbidden or required patterns
+/// - A code block is empty and `empty_block` is set to `error`
+/// - A `file=` attribute is not a plain file name
+/// - A `flags=` attribute contains shell metacharacters
+/// - A code block references an undefined chunk or chunks reference each
+///   other cyclically (tangle mode)
+/// - The tangle reference pattern is invalid
+/// - A code block's language has no configured checker and `strict` is set
+/// - A code block names an undefined variant and `strict_variants` is set
 pub fn collect_compilation_tasks(
     book: &mut Book,
     src_dir: &Path,
+    config: &CheckCodeConfig,
     registry: &LanguageRegistry,
     temp_dir: &TempDir,
+    chapter_cache: Option<&ChapterCache>,
 ) -> Result<Vec<CompilationTask>> {
     let mut tasks = Vec::new();
     let mut task_counter = 0;
     let mut collection_errors = Vec::new();
+    let extract_options = ExtractOptions {
+        hidden_line_fences: registry.hidden_line_fences(),
+        default_indented_language: config.default_indented_language.clone(),
+    };
+
+    let tangler = config
+        .tangle
+        .then(|| {
+            Tangler::new(
+                config
+                    .tangle_reference
+                    .as_deref()
+                    .unwrap_or(DEFAULT_TANGLE_REFERENCE),
+            )
+        })
+        .transpose()?;
+
+    if reporting::verbosity() == Verbosity::Verbose {
+        reporting::report_ignored_blocks(&ignored_blocks(book, src_dir, &extract_options));
+    }
+
+    if config.strict {
+        collection_errors.extend(
+            unconfigured_blocks(book, src_dir, config, registry, &extract_options)
+                .into_iter()
+                .map(|block| format!("No configured language for {} (strict)", block)),
+        );
+    } else if config.warn_unconfigured {
+        reporting::report_unconfigured_blocks(&unconfigured_blocks(
+            book,
+            src_dir,
+            config,
+            registry,
+            &extract_options,
+        ));
+    }
 
     book.for_each_mut(|item| {
         if let BookItem::Chapter(chapter) = item {
@@ -39,7 +129,19 @@ pub fn collect_compilation_tasks(
 
                 log::debug!("Collecting tasks from chapter: {}", chapter.name);
 
-                let code_blocks = extract_code_blocks_with_propagation(&chapter.content);
+                let is_configured = |fence: &str| registry.find_by_fence(fence, None).is_ok();
+                if chapter_cache
+                    .is_some_and(|cache| cache.unchanged(chapter_path, &chapter.content))
+                {
+                    log::debug!("Skipping unchanged chapter: {}", chapter.name);
+                    chapter.content =
+                        rewrite_fences(&chapter.content, &extract_options, is_configured);
+                    return;
+                }
+
+                let code_blocks =
+                    extract_code_blocks_with_propagation(&chapter.content, &extract_options);
+                chapter.content = rewrite_fences(&chapter.content, &extract_options, is_configured);
 
                 if code_blocks.is_empty() {
                     return;
@@ -60,45 +162,250 @@ pub fn collect_compilation_tasks(
                     .and_then(|n| n.to_str())
                     .unwrap_or("unknown")
                     .trim_end_matches(".md");
+                let sources = if config.compile_in_place {
+                    Sources::InPlace(full_path.parent().unwrap_or(src_dir).to_path_buf())
+                } else {
+                    Sources::Temp(temp_dir.path().to_path_buf())
+                };
+
+                let chunks = tangler.as_ref().map(|base| {
+                    let mut chapter_tangler = base.clone();
+                    for (_, block) in &code_blocks {
+                        if let Some(name) = &block.name {
+                            chapter_tangler.define(name, &block.compile_code);
+                        }
+                    }
+                    chapter_tangler
+                });
+
+                let mut propagated_code: HashMap<(String, Option<String>), String> = HashMap::new();
+                let mut contexts = Vec::new();
+                let mut unit: Option<PendingUnit> = None;
+
+                for (propagated, block) in code_blocks {
+                    let i = block.index;
+
+                    let (propagated, code) = match &chunks {
+                        // Named blocks are fragments assembled into other blocks
+                        Some(_) if block.name.is_some() => continue,
+                        Some(chunks) => match chunks
+                            .expand(&propagated)
+                            .and_then(|p| Ok((p, chunks.expand(&block.compile_code)?)))
+                        {
+                            Ok(expanded) => expanded,
+                            Err(error) => {
+                                collection_errors.push(format!(
+                                    "Code block #{} in {}: {}",
+                                    i,
+                                    full_path.display(),
+                                    error
+                                ));
+                                continue;
+                            }
+                        },
+                        None => (propagated, block.compile_code.clone()),
+                    };
 
-                for (i, (final_code, block)) in code_blocks.into_iter().enumerate() {
-                    if final_code.len() > MAX_CODE_BLOCK_SIZE {
+                    // Mirrors the accumulation in extract_code_blocks_with_propagation
+                    let accumulated = propagated_code.entry(block.propagation_key()).or_default();
+                    let kind = if block.propagate {
+                        accumulated.push_str(&code);
+                        accumulated.push('\n');
+                        TaskKind::Block
+                    } else if accumulated.is_empty() {
+                        TaskKind::Block
+                    } else {
+                        TaskKind::DependentBlock
+                    };
+
+                    if config.is_prose_marker(&block.language) {
+                        continue;
+                    }
+
+                    let language = match registry
+                        .find_by_fence(&block.language, block.variant.as_deref())
+                    {
+                        Ok(lang) => lang,
+                        // Blocks of unconfigured languages are not checked
+                        Err(FenceResolution::NoLanguage) => continue,
+                        Err(unknown @ FenceResolution::UnknownVariant { .. }) => {
+                            let message =
+                                format!("Code block #{} in {} {}", i, full_path.display(), unknown);
+                            if config.strict_variants {
+                                collection_errors.push(message);
+                            } else {
+                                reporting::print_warning(format!(
+                                    "{}; the block is not checked",
+                                    message
+                                ));
+                            }
+                            continue;
+                        }
+                    };
+
+                    if let Some(flag) = language.disallowed_block_flag(&block.flags) {
+                        collection_errors.push(format!(
+                            "Code block #{} in {} passes flag '{}', which allowed_block_flags for language '{}' does not permit",
+                            i,
+                            full_path.display(),
+                            flag,
+                            language
+                        ));
+                        continue;
+                    }
+                    let language = language.with_extra_flags(&block.flags);
+
+                    if !unit.as_ref().is_some_and(|unit| unit.accepts(&block)) {
+                        if let Some(finished) = unit.take() {
+                            tasks.push(finished.into_task(
+                                &sources,
+                                chapter_path,
+                                chapter_name,
+                                &mut task_counter,
+                            ));
+                        }
+                    }
+
+                    let size =
+                        propagated.len() + code.len() + language.postamble().map_or(0, str::len);
+                    if size > MAX_CODE_BLOCK_SIZE {
                         collection_errors.push(format!(
                             "Code block #{} in {} exceeds size limit of {} bytes ({} bytes)",
                             i,
                             full_path.display(),
                             MAX_CODE_BLOCK_SIZE,
-                            final_code.len()
+                            size
                         ));
                         continue;
                     }
 
-                    let language =
-                        match registry.find_by_fence(&block.language, block.variant.as_deref()) {
-                            Some(lang) => lang,
-                            None => {
+                    if block.compile_code.trim().is_empty() {
+                        match config.empty_block {
+                            EmptyBlockPolicy::Skip => continue,
+                            EmptyBlockPolicy::Error => {
+                                collection_errors.push(format!(
+                                    "Code block #{} ({}) in {} is empty",
+                                    i,
+                                    language,
+                                    full_path.display()
+                                ));
                                 continue;
                             }
+                            EmptyBlockPolicy::Compile => {}
+                        }
+                    }
+
+                    for violation in language.pattern_violations(&block.compile_code) {
+                        collection_errors.push(format!(
+                            "Code block #{} ({}) in {} {}",
+                            i,
+                            language,
+                            full_path.display(),
+                            violation
+                        ));
+                    }
+
+                    if let Some(name) = &block.file {
+                        if Path::new(name).file_name() != Some(OsStr::new(name)) {
+                            collection_errors.push(format!(
+                                "Code block #{} in {} has file={}, which is not a plain file name",
+                                i,
+                                full_path.display(),
+                                name
+                            ));
+                            continue;
+                        }
+                        let file = SourceFile {
+                            name: name.clone(),
+                            code,
                         };
+                        unit.get_or_insert_with(|| PendingUnit {
+                            language,
+                            block: block.clone(),
+                            files: Vec::new(),
+                        })
+                        .files
+                        .push(file);
+                        continue;
+                    }
 
-                    let block_name =
-                        format!("{}_{}_block_{}", language, chapter_name, task_counter);
+                    let block_name = sources.name(format!(
+                        "{}_{}_block_{}",
+                        language, chapter_name, task_counter
+                    ));
                     task_counter += 1;
 
-                    let temp_file_path = temp_dir.path().join(format!(
-                        "{}{}",
-                        block_name,
-                        language.file_extension()
-                    ));
+                    let temp_file_path = language.source_path(sources.dir(), &block_name);
+
+                    let key = block.propagation_key();
+                    if block.propagate && !contexts.iter().any(|(k, _, _)| *k == key) {
+                        contexts.push((key, language.clone(), block.clone()));
+                    }
+
+                    if config.check_standalone && kind == TaskKind::DependentBlock {
+                        let standalone_name = format!("{}_standalone", block_name);
+                        let standalone_path = language.source_path(sources.dir(), &standalone_name);
+                        tasks.push(
+                            CompilationTask::new(
+                                language.clone(),
+                                standalone_path,
+                                chapter_path.clone(),
+                                i,
+                                block.clone(),
+                                block.compile_code.clone(),
+                            )
+                            .with_kind(TaskKind::Standalone)
+                            .with_cleanup(sources.cleanup(&standalone_name)),
+                        );
+                    }
+
+                    tasks.push(
+                        CompilationTask::new(
+                            language,
+                            temp_file_path,
+                            chapter_path.clone(),
+                            i,
+                            block,
+                            code,
+                        )
+                        .with_propagated(propagated)
+                        .with_kind(kind)
+                        .with_cleanup(sources.cleanup(&block_name)),
+                    );
+                }
 
-                    tasks.push(CompilationTask::new(
-                        language,
-                        temp_file_path,
-                        chapter_path.clone(),
-                        i,
-                        final_code,
+                if let Some(finished) = unit {
+                    tasks.push(finished.into_task(
+                        &sources,
+                        chapter_path,
+                        chapter_name,
+                        &mut task_counter,
                     ));
                 }
+
+                for (key, language, block) in contexts {
+                    if config.check_propagated_context {
+                        let context_name = sources.name(format!(
+                            "{}_{}_context_{}",
+                            language, chapter_name, task_counter
+                        ));
+                        let temp_file_path = language.source_path(sources.dir(), &context_name);
+                        task_counter += 1;
+
+                        tasks.push(
+                            CompilationTask::new(
+                                language,
+                                temp_file_path,
+                                chapter_path.clone(),
+                                0,
+                                block,
+                                propagated_code.remove(&key).unwrap_or_default(),
+                            )
+                            .with_kind(TaskKind::PropagatedContext)
+                            .with_cleanup(sources.cleanup(&context_name)),
+                        );
+                    }
+                }
             }
         }
     });
@@ -115,3 +422,705 @@ pub fn collect_compilation_tasks(
 
     Ok(tasks)
 }
+
+/// Where a chapter's source files are written.
+enum Sources {
+    /// In the shared temporary directory
+    Temp(PathBuf),
+    /// Next to the chapter, under hidden names removed after compiling
+    /// (`compile_in_place`)
+    InPlace(PathBuf),
+}
+
+impl Sources {
+    fn dir(&self) -> &Path {
+        match self {
+            Sources::Temp(dir) | Sources::InPlace(dir) => dir,
+        }
+    }
+
+    /// Returns the name for a source file, hidden when written in place.
+    fn name(&self, name: String) -> String {
+        match self {
+            Sources::Temp(_) => name,
+            Sources::InPlace(_) => format!("{}{}", IN_PLACE_PREFIX, name),
+        }
+    }
+
+    /// Returns what to remove after compiling the source named `name`, see
+    /// [`CompilationTask::with_cleanup`].
+    fn cleanup(&self, name: &str) -> Option<PathBuf> {
+        match self {
+            Sources::Temp(_) => None,
+            Sources::InPlace(dir) => Some(dir.join(name)),
+        }
+    }
+}
+
+/// A multi-file compilation unit whose blocks are still being collected.
+struct PendingUnit {
+    language: ConfiguredLanguage,
+    /// The unit's first block, which its task is attributed to
+    block: CodeBlock,
+    files: Vec<SourceFile>,
+}
+
+impl PendingUnit {
+    /// Whether `block` continues this unit rather than ending it.
+    fn accepts(&self, block: &CodeBlock) -> bool {
+        block.propagation_key() == self.block.propagation_key()
+            && block
+                .file
+                .as_ref()
+                .is_some_and(|name| self.files.iter().all(|file| file.name != *name))
+    }
+
+    fn into_task(
+        self,
+        sources: &Sources,
+        chapter_path: &Path,
+        chapter_name: &str,
+        task_counter: &mut usize,
+    ) -> CompilationTask {
+        let unit_name = sources.name(format!(
+            "{}_{}_unit_{}",
+            self.language, chapter_name, task_counter
+        ));
+        let unit_dir = sources.dir().join(&unit_name);
+        *task_counter += 1;
+
+        CompilationTask::new(
+            self.language,
+            unit_dir,
+            chapter_path.to_path_buf(),
+            self.block.index,
+            self.block,
+            SourceFile::listing(&self.files),
+        )
+        .with_files(self.files)
+        .with_cleanup(sources.cleanup(&unit_name))
+    }
+}
+
+/// Describes each code block in `book` marked `ignore`, as
+/// `language (code block #N in path): reason`.
+fn ignored_blocks(book: &Book, src_dir: &Path, options: &ExtractOptions) -> Vec<String> {
+    let mut ignored = Vec::new();
+    for item in book.iter() {
+        let BookItem::Chapter(chapter) = item else {
+            continue;
+        };
+        let Some(chapter_path) = &chapter.path else {
+            continue;
+        };
+
+        for block in extract_code_blocks(&chapter.content, options) {
+            if block.ignore {
+                ignored.push(format!(
+                    "{} (code block #{} in {}): {}",
+                    block.language,
+                    block.index,
+                    src_dir.join(chapter_path).display(),
+                    block.ignore_reason.as_deref().unwrap_or("no reason given")
+                ));
+            }
+        }
+    }
+    ignored
+}
+
+/// Describes each code block in `book` that is skipped because no configured
+/// language claims its fence, as `language (code block #N in path)`.
+///
+/// Ignored blocks, prose markers, `allowed_unconfigured` fences, fences without
+/// a language, and named tangle fragments are not listed.
+fn unconfigured_blocks(
+    book: &Book,
+    src_dir: &Path,
+    config: &CheckCodeConfig,
+    registry: &LanguageRegistry,
+    options: &ExtractOptions,
+) -> Vec<String> {
+    let mut unconfigured = Vec::new();
+    for item in book.iter() {
+        let BookItem::Chapter(chapter) = item else {
+            continue;
+        };
+        let Some(chapter_path) = &chapter.path else {
+            continue;
+        };
+
+        for block in extract_code_blocks(&chapter.content, options) {
+            if block.ignore
+                || block.language.is_empty()
+                || (config.tangle && block.name.is_some())
+                || config.is_prose_marker(&block.language)
+                || config.allowed_unconfigured.contains(&block.language)
+                // Undefined variants of configured languages are reported separately
+                || registry.find_by_fence(&block.language, None).is_ok()
+            {
+                continue;
+            }
+            unconfigured.push(format!(
+                "{} (code block #{} in {})",
+                block.language,
+                block.index,
+                src_dir.join(chapter_path).display()
+            ));
+        }
+    }
+    unconfigured
+}
+
+/// The code blocks checked by [`check_expected_blocks`] and
+/// [`unused_variants`]: the number of blocks per base language and the
+/// variants they use.
+///
+/// Propagated context and standalone tasks are not counted. The chapter cache
+/// records each chapter's tally, so chapters it skips still count.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockTally {
+    /// Number of code blocks per base language
+    counts: BTreeMap<String, usize>,
+    /// `(language, variant)` pairs used by at least one block
+    variants: BTreeSet<(String, String)>,
+}
+
+impl BlockTally {
+    /// Tallies the code blocks among `tasks`.
+    pub fn of_tasks<'a>(tasks: impl IntoIterator<Item = &'a CompilationTask>) -> Self {
+        let mut tally = Self::default();
+        for task in tasks {
+            if !matches!(task.kind(), TaskKind::Block | TaskKind::DependentBlock) {
+                continue;
+            }
+            let language = task.language();
+            *tally
+                .counts
+                .entry(language.base_language().to_string())
+                .or_insert(0) += 1;
+            if let Some(variant) = language.variant() {
+                tally
+                    .variants
+                    .insert((language.base_language().to_string(), variant.to_string()));
+            }
+        }
+        tally
+    }
+
+    /// Tallies `tasks` separately for each chapter, keyed by chapter path.
+    pub fn by_chapter(tasks: &[CompilationTask]) -> HashMap<PathBuf, Self> {
+        let mut chapters: HashMap<PathBuf, Vec<&CompilationTask>> = HashMap::new();
+        for task in tasks {
+            chapters
+                .entry(task.chapter_path().to_path_buf())
+                .or_default()
+                .push(task);
+        }
+        chapters
+            .into_iter()
+            .map(|(path, tasks)| (path, Self::of_tasks(tasks)))
+            .collect()
+    }
+
+    /// Adds the blocks counted in `other`.
+    pub fn merge(&mut self, other: &Self) {
+        for (language, count) in &other.counts {
+            *self.counts.entry(language.clone()).or_insert(0) += count;
+        }
+        self.variants.extend(other.variants.iter().cloned());
+    }
+}
+
+/// Returns the configured variants of enabled languages that no block in
+/// `tally` uses, as sorted `language-variant` names.
+pub fn unused_variants(config: &CheckCodeConfig, tally: &BlockTally) -> Vec<String> {
+    let mut unused: Vec<String> = config
+        .languages()
+        .iter()
+        .filter(|(_, language)| language.enabled)
+        .flat_map(|(name, language)| {
+            language
+                .variants
+                .keys()
+                .filter(|variant| {
+                    !tally
+                        .variants
+                        .contains(&(name.to_string(), variant.to_string()))
+                })
+                .map(move |variant| format!("{}-{}", name, variant))
+        })
+        .collect();
+    unused.sort();
+    unused
+}
+
+/// Checks the number of blocks per language in `tally` against each enabled
+/// language's `expected_blocks` range.
+///
+/// Blocks of all variants count towards their base language.
+///
+/// # Errors
+///
+/// Returns an error listing every language whose block count is out of range.
+pub fn check_expected_blocks(config: &CheckCodeConfig, tally: &BlockTally) -> Result<()> {
+    let mut violations: Vec<String> = config
+        .languages()
+        .iter()
+        .filter(|(_, language)| language.enabled)
+        .filter_map(|(name, language)| {
+            let range = language.expected_blocks?;
+            let count = tally.counts.get(name).copied().unwrap_or(0);
+            (!range.contains(count)).then(|| {
+                format!(
+                    "Language '{}' has {} code block(s), expected {}",
+                    name, count, range
+                )
+            })
+        })
+        .collect();
+
+    if violations.is_empty() {
+        return Ok(());
+    }
+
+    violations.sort();
+    anyhow::bail!(
+        "Code block counts out of the expected range:\n  {}",
+        violations.join("\n  ")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extractor::CodeBlock;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_unused_variants() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "gcc"
+
+            [languages.c.variants.parasol]
+            compiler = "clang"
+
+            [languages.c.variants.legacy]
+            compiler = "gcc"
+
+            [languages.c.variants.riscv]
+            compiler = "gcc"
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+        let task = |variant: &str, kind: TaskKind| {
+            CompilationTask::new(
+                registry.find_by_fence("c", Some(variant)).unwrap(),
+                PathBuf::from("/tmp/unused_variants"),
+                PathBuf::from("a.md"),
+                0,
+                CodeBlock::default(),
+                String::new(),
+            )
+            .with_kind(kind)
+        };
+
+        let tasks = [
+            task("parasol", TaskKind::Block),
+            task("legacy", TaskKind::PropagatedContext),
+        ];
+        let tally = BlockTally::of_tasks(&tasks);
+        assert_eq!(
+            unused_variants(&config, &tally),
+            vec!["c-legacy".to_string(), "c-riscv".to_string()]
+        );
+
+        // Variants used in another (e.g. cached) chapter count too
+        let mut tally = tally;
+        tally.merge(&BlockTally::of_tasks(&[task("riscv", TaskKind::Block)]));
+        assert_eq!(
+            unused_variants(&config, &tally),
+            vec!["c-legacy".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_file_blocks_grouped_into_units() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "gcc"
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+        let content = "\
+```c,file=point.h
+struct point;
+```
+
+```c,file=point.c
+#include \"point.h\"
+```
+
+```c
+int x;
+```
+
+```c,file=a.h
+int a;
+```
+
+```c,file=a.h
+int b;
+```
+";
+        let mut book = Book::new();
+        book.push_item(BookItem::Chapter(mdbook::book::Chapter::new(
+            "Units",
+            content.to_string(),
+            "units.md",
+            Vec::new(),
+        )));
+        let temp_dir = TempDir::new().unwrap();
+
+        let tasks = collect_compilation_tasks(
+            &mut book,
+            Path::new(""),
+            &config,
+            &registry,
+            &temp_dir,
+            None,
+        )
+        .unwrap();
+        let summary: Vec<_> = tasks
+            .iter()
+            .map(|task| (task.block().index, task.block_source()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    0,
+                    "==> point.h <==\nstruct point;\n\n==> point.c <==\n#include \"point.h\"\n"
+                        .to_string()
+                ),
+                (2, "int x;\n".to_string()),
+                (3, "==> a.h <==\nint a;\n".to_string()),
+                (4, "==> a.h <==\nint b;\n".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unconfigured_blocks() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            warn_unconfigured = true
+
+            [languages.c]
+            compiler = "gcc"
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+        let mut book = Book::new();
+        book.push_item(BookItem::Chapter(mdbook::book::Chapter::new(
+            "Typos",
+            "```c\nint x;\n```\n\n```rustt\nfn main() {}\n```\n\n```rustt,ignore\nfn main() {}\n```\n\n```text\noutput\n```\n\n```\nplain\n```\n"
+                .to_string(),
+            "typos.md",
+            Vec::new(),
+        )));
+        let options = ExtractOptions::default();
+
+        assert_eq!(
+            unconfigured_blocks(&book, Path::new("src"), &config, &registry, &options),
+            vec![format!(
+                "rustt (code block #1 in {})",
+                Path::new("src").join("typos.md").display()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_ignored_blocks() {
+        let mut book = Book::new();
+        book.push_item(BookItem::Chapter(mdbook::book::Chapter::new(
+            "Ignored",
+            "```c,ignore\nint x;\n```\n\n```c\nint y;\n```\n\n```c,ignore=\"needs network\"\nint z;\n```\n"
+                .to_string(),
+            "ignored.md",
+            Vec::new(),
+        )));
+        let path = Path::new("src").join("ignored.md");
+
+        assert_eq!(
+            ignored_blocks(&book, Path::new("src"), &ExtractOptions::default()),
+            vec![
+                format!("c (code block #0 in {}): no reason given", path.display()),
+                format!("c (code block #2 in {}): needs network", path.display()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strict_fails_on_unconfigured_language() {
+        let collect = |config: &CheckCodeConfig| {
+            let registry = LanguageRegistry::from_config(config).unwrap();
+            let mut book = Book::new();
+            book.push_item(BookItem::Chapter(mdbook::book::Chapter::new(
+                "Strict",
+                "```c\nint x;\n```\n\n```json\n{}\n```\n\n```python\nprint()\n```\n".to_string(),
+                "strict.md",
+                Vec::new(),
+            )));
+            let temp_dir = TempDir::new().unwrap();
+            collect_compilation_tasks(&mut book, Path::new(""), config, &registry, &temp_dir, None)
+                .map(|tasks| tasks.len())
+        };
+        let mut config: CheckCodeConfig = toml::from_str(
+            r#"
+            strict = true
+            allowed_unconfigured = ["json"]
+
+            [languages.c]
+            compiler = "gcc"
+            "#,
+        )
+        .unwrap();
+
+        assert!(collect(&config).is_err());
+
+        config.allowed_unconfigured.push("python".to_string());
+        assert_eq!(collect(&config).unwrap(), 1);
+
+        config.allowed_unconfigured.clear();
+        config.strict = false;
+        assert_eq!(collect(&config).unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_block_flags_reach_compiler() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "gcc"
+            flags = ["-fsyntax-only"]
+            allowed_block_flags = ["-D", "-std="]
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+        let code = "#ifndef ENABLED\n#error needs -DENABLED\n#endif\n";
+        let mut book = Book::new();
+        book.push_item(BookItem::Chapter(mdbook::book::Chapter::new(
+            "Flags",
+            format!("```c\n{code}```\n\n```c,flags=\"-DENABLED\"\n{code}```\n"),
+            "flags.md",
+            Vec::new(),
+        )));
+        let temp_dir = TempDir::new().unwrap();
+
+        let tasks = collect_compilation_tasks(
+            &mut book,
+            Path::new(""),
+            &config,
+            &registry,
+            &temp_dir,
+            None,
+        )
+        .unwrap();
+        let mut outcomes = Vec::new();
+        for task in tasks {
+            outcomes.push(task.compile().await.success());
+        }
+        assert_eq!(outcomes, vec![false, true]);
+
+        // Flags outside the allowlist, such as ones loading plugins or
+        // writing files, fail the build
+        for flags in ["-fplugin=./evil.so", "-DX -o /tmp/out", "-Wl,-z"] {
+            let mut book = Book::new();
+            book.push_item(BookItem::Chapter(mdbook::book::Chapter::new(
+                "Flags",
+                format!("```c,flags=\"{}\"\nint x;\n```\n", flags),
+                "flags.md",
+                Vec::new(),
+            )));
+            let error = collect_compilation_tasks(
+                &mut book,
+                Path::new(""),
+                &config,
+                &registry,
+                &temp_dir,
+                None,
+            )
+            .map(|tasks| tasks.len())
+            .unwrap_err();
+            assert!(error.to_string().contains("1 error(s)"), "{}", flags);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_propagated_context_expands_chunks() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            tangle = true
+            check_propagated_context = true
+
+            [languages.c]
+            compiler = "gcc"
+            flags = ["-fsyntax-only"]
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+        let mut book = Book::new();
+        book.push_item(BookItem::Chapter(mdbook::book::Chapter::new(
+            "Tangle",
+            concat!(
+                "```c,name=decl\nint x;\n```\n\n",
+                "```c,propagate\n<<decl>>\n```\n\n",
+                "```c\nint y(void) { return x; }\n```\n",
+            )
+            .to_string(),
+            "tangle.md",
+            Vec::new(),
+        )));
+        let temp_dir = TempDir::new().unwrap();
+
+        let tasks = collect_compilation_tasks(
+            &mut book,
+            Path::new(""),
+            &config,
+            &registry,
+            &temp_dir,
+            None,
+        )
+        .unwrap();
+        let context = tasks
+            .iter()
+            .find(|task| task.kind() == TaskKind::PropagatedContext)
+            .unwrap();
+        assert!(context.block_source().contains("int x;"));
+        assert!(!context.block_source().contains("<<decl>>"));
+
+        for task in tasks {
+            let result = task.compile().await;
+            assert!(result.success(), "{:?}", result.error_message());
+        }
+    }
+
+    #[test]
+    fn test_pattern_violations_fail_collection() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "true"
+            forbidden_patterns = ["system\\("]
+            required_patterns = ["SPDX"]
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+        let collect = |content: &str| {
+            let mut book = Book::new();
+            book.push_item(BookItem::Chapter(mdbook::book::Chapter::new(
+                "Patterns",
+                content.to_string(),
+                "patterns.md",
+                Vec::new(),
+            )));
+            let temp_dir = TempDir::new().unwrap();
+            collect_compilation_tasks(
+                &mut book,
+                Path::new(""),
+                &config,
+                &registry,
+                &temp_dir,
+                None,
+            )
+            .map(|tasks| tasks.len())
+        };
+
+        assert_eq!(collect("```c\n// SPDX\nint x;\n```\n").unwrap(), 1);
+        // One error per violated pattern
+        let error =
+            collect("```c\n// SPDX\nsystem(\"ls\");\n```\n\n```c\nint y;\n```\n").unwrap_err();
+        assert!(error.to_string().contains("2 error(s)"), "{:#}", error);
+    }
+
+    #[test]
+    fn test_undefined_variant() {
+        let collect = |config: &CheckCodeConfig| {
+            let registry = LanguageRegistry::from_config(config).unwrap();
+            let mut book = Book::new();
+            book.push_item(BookItem::Chapter(mdbook::book::Chapter::new(
+                "Variants",
+                concat!(
+                    "```c,variant=parasol\nint x;\n```\n\n",
+                    "```c,variant=parsol\nint y;\n```\n\n",
+                    "```rust,variant=nightly\nfn main() {}\n```\n",
+                )
+                .to_string(),
+                "variants.md",
+                Vec::new(),
+            )));
+            let temp_dir = TempDir::new().unwrap();
+            collect_compilation_tasks(&mut book, Path::new(""), config, &registry, &temp_dir, None)
+                .map(|tasks| tasks.len())
+        };
+        let mut config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "gcc"
+
+            [languages.c.variants.parasol]
+            compiler = "clang"
+            "#,
+        )
+        .unwrap();
+
+        // By default the block is skipped with a warning
+        assert_eq!(collect(&config).unwrap(), 1);
+
+        // Only the misspelled variant fails; the unconfigured rust block is
+        // skipped silently
+        config.strict_variants = true;
+        let error = collect(&config).unwrap_err();
+        assert!(error.to_string().contains("1 error(s)"), "{:#}", error);
+    }
+
+    #[test]
+    fn test_file_attribute_must_be_plain_name() {
+        let config: CheckCodeConfig = toml::from_str(
+            r#"
+            [languages.c]
+            compiler = "gcc"
+            "#,
+        )
+        .unwrap();
+        let registry = LanguageRegistry::from_config(&config).unwrap();
+        let mut book = Book::new();
+        book.push_item(BookItem::Chapter(mdbook::book::Chapter::new(
+            "Units",
+            "```c,file=../escape.h\nint a;\n```\n".to_string(),
+            "units.md",
+            Vec::new(),
+        )));
+        let temp_dir = TempDir::new().unwrap();
+
+        assert!(collect_compilation_tasks(
+            &mut book,
+            Path::new(""),
+            &config,
+            &registry,
+            &temp_dir,
+            None
+        )
+        .is_err());
+    }
+}
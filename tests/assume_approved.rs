@@ -0,0 +1,39 @@
+//! Approval bypass via `MDBOOK_CHECK_CODE_ASSUME_APPROVED`
+//!
+//! Kept in its own test binary because it sets process-wide environment
+//! variables, which would race with the approval test in `integration.rs`.
+
+use anyhow::Result;
+use mdbook::preprocess::CmdPreprocessor;
+use mdbook::MDBook;
+use mdbook_check_code::{CheckCodePreprocessor, ASSUME_APPROVED_ENV};
+
+#[tokio::test]
+async fn assume_approved_compiles_unapproved_book() -> Result<()> {
+    // Start from an empty approval store, like a fresh CI checkout
+    let data_dir = tempfile::TempDir::new()?;
+    std::env::set_var("XDG_DATA_HOME", data_dir.path());
+    std::env::set_var(ASSUME_APPROVED_ENV, "1");
+
+    let md = MDBook::load("tests/fixtures/postamble")?;
+    let input_json = serde_json::json!([
+        {
+            "root": md.root,
+            "config": md.config,
+            "renderer": "html",
+            "mdbook_version": env!("CARGO_PKG_VERSION"),
+        },
+        md.book
+    ]);
+    let input_str = serde_json::to_string(&input_json)?;
+    let (ctx, book) = CmdPreprocessor::parse_input(input_str.as_bytes())?;
+
+    let result = CheckCodePreprocessor::new().run_async(&ctx, book).await;
+
+    assert!(
+        result.is_ok(),
+        "Unapproved book should compile: {:?}",
+        result.err()
+    );
+    Ok(())
+}
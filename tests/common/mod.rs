@@ -62,12 +62,17 @@ impl PreprocessorTest {
     /// Uses `CheckCodePreprocessor::new_for_testing()` to bypass approval checks,
     /// allowing tests to run without manual approval.
     pub async fn run(&self) -> Result<Book> {
+        self.run_for_renderer("html").await
+    }
+
+    /// Run the preprocessor on the test book as mdBook would for `renderer`
+    pub async fn run_for_renderer(&self, renderer: &str) -> Result<Book> {
         // Create JSON input like mdbook would send
         let input_json = serde_json::json!([
             {
                 "root": self.book.root,
                 "config": self.book.config,
-                "renderer": "html",
+                "renderer": renderer,
                 "mdbook_version": env!("CARGO_PKG_VERSION"),
             },
             self.book.book
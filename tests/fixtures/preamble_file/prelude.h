@@ -0,0 +1,7 @@
+#include <stdint.h>
+
+typedef uint32_t word;
+
+static inline word rotate_left(word value, unsigned int shift) {
+    return (value << shift) | (value >> (32 - shift));
+}
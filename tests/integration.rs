@@ -25,7 +25,8 @@ use anyhow::Result;
 use common::{PreprocessorTest, TestFixture};
 use mdbook::preprocess::CmdPreprocessor;
 use mdbook::MDBook;
-use mdbook_check_code::CheckCodePreprocessor;
+use mdbook_check_code::{CheckCodeConfig, CheckCodePreprocessor, CHECK_MARKDOWN_PATH};
+use std::path::Path;
 
 // ===== Tests =====
 
@@ -81,6 +82,333 @@ async fn integration_compilation_errors_detected() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn integration_blocks_inside_tab_containers_checked() -> Result<()> {
+    let fixture = TestFixture::new("tests/fixtures/tabs")?;
+    let test = PreprocessorTest::from_fixture(&fixture)?;
+
+    let result = test.run().await;
+
+    assert!(
+        result.is_err(),
+        "Invalid code inside a tab container should fail compilation"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_indented_blocks_compile() -> Result<()> {
+    let fixture = TestFixture::new("tests/fixtures/indented")?;
+    let test = PreprocessorTest::from_fixture(&fixture)?;
+
+    let result = test.run().await;
+
+    assert!(
+        result.is_ok(),
+        "Valid indented code should compile: {:?}",
+        result.err()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_indented_blocks_checked() -> Result<()> {
+    let fixture = TestFixture::new("tests/fixtures/indented_error")?;
+    let test = PreprocessorTest::from_fixture(&fixture)?;
+
+    let result = test.run().await;
+
+    assert!(
+        result.is_err(),
+        "Invalid indented code should fail compilation"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_block_index_counts_ignored_blocks() -> Result<()> {
+    let fixture = TestFixture::new("tests/fixtures/block_index")?;
+    let test = PreprocessorTest::from_fixture(&fixture)?;
+
+    let result = test.run().await;
+    assert!(result.is_err(), "Invalid block should fail compilation");
+
+    let report = std::fs::read_to_string(fixture.book_path().join("report.xml"))?;
+    assert!(
+        report.contains("block_index.md block #2"),
+        "Failing block should be reported as the third fence: {}",
+        report
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_run_mode_fails_on_nonzero_exit() -> Result<()> {
+    let fixture = TestFixture::new("tests/fixtures/run")?;
+    let test = PreprocessorTest::from_fixture(&fixture)?;
+
+    let result = test.run().await;
+    assert!(result.is_err(), "Program exiting nonzero should fail");
+
+    let report = std::fs::read_to_string(fixture.book_path().join("report.xml"))?;
+    assert!(
+        report.contains("failures=\"1\"") && report.contains("checking answer"),
+        "Only the second program should fail, not the no_run one: {}",
+        report
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_expect_output_compares_stdout() -> Result<()> {
+    let fixture = TestFixture::new("tests/fixtures/expect_output")?;
+    let test = PreprocessorTest::from_fixture(&fixture)?;
+
+    let result = test.run().await;
+    assert!(result.is_err(), "Mismatched output should fail");
+
+    let report = std::fs::read_to_string(fixture.book_path().join("report.xml"))?;
+    assert!(
+        report.contains("failures=\"1\"") && report.contains("+ 54"),
+        "Only the wrong answer should fail, with a diff: {}",
+        report
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_preamble_file() -> Result<()> {
+    let fixture = TestFixture::new("tests/fixtures/preamble_file")?;
+    let test = PreprocessorTest::from_fixture(&fixture)?;
+
+    let result = test.run().await;
+    assert!(
+        result.is_ok(),
+        "Blocks should compile with the preamble file: {:?}",
+        result.err()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_postamble_completes_program() -> Result<()> {
+    let fixture = TestFixture::new("tests/fixtures/postamble")?;
+    let test = PreprocessorTest::from_fixture(&fixture)?;
+
+    let result = test.run().await;
+    assert!(
+        result.is_ok(),
+        "Block without main should link with the postamble: {:?}",
+        result.err()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_file_units_compile_together() -> Result<()> {
+    let fixture = TestFixture::new("tests/fixtures/multi_file")?;
+    let test = PreprocessorTest::from_fixture(&fixture)?;
+
+    let result = test.run().await;
+    assert!(
+        result.is_ok(),
+        "Header and implementation blocks should compile as one unit: {:?}",
+        result.err()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_dry_run_invokes_no_compiler() -> Result<()> {
+    let fixture = TestFixture::new("tests/fixtures/dry_run")?;
+    let test = PreprocessorTest::from_fixture(&fixture)?;
+
+    // Every block would fail if its compiler (`false`) were spawned
+    let result = test.run().await;
+    assert!(
+        result.is_ok(),
+        "Dry run should not invoke compilers: {:?}",
+        result.err()
+    );
+
+    let mut config = std::fs::read_to_string(fixture.book_path().join("book.toml"))?;
+    config = config.replace("dry_run = true", "dry_run = false");
+    std::fs::write(fixture.book_path().join("book.toml"), config)?;
+    let test = PreprocessorTest::from_fixture(&fixture)?;
+    assert!(test.run().await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_other_renderers_skip_compilation() -> Result<()> {
+    let fixture = TestFixture::new("tests/fixtures/dry_run")?;
+    let book_toml = fixture.book_path().join("book.toml");
+    let config = std::fs::read_to_string(&book_toml)?.replace("dry_run = true", "");
+    std::fs::write(&book_toml, config)?;
+    let test = PreprocessorTest::from_fixture(&fixture)?;
+
+    // The compiler (`false`) fails every block, so only a skipped run succeeds
+    let book = test.run_for_renderer("markdown").await?;
+    let mdbook::BookItem::Chapter(chapter) = &book.sections[0] else {
+        panic!("expected a chapter");
+    };
+    assert!(chapter.content.contains("```c,variant=strict"));
+    assert!(test.run_for_renderer("html").await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_compile_in_place_finds_sibling_header() -> Result<()> {
+    let fixture = TestFixture::new("tests/fixtures/compile_in_place")?;
+    let test = PreprocessorTest::from_fixture(&fixture)?;
+    let chapter_dir = fixture.book_path().join("src").join("shapes");
+    let entries = || -> Result<Vec<String>> {
+        let mut names = std::fs::read_dir(&chapter_dir)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    };
+
+    let result = test.run().await;
+    assert!(
+        result.is_ok(),
+        "Blocks compiled next to the chapter should find point.h: {:?}",
+        result.err()
+    );
+    // Every source written next to the chapter is removed again
+    assert_eq!(entries()?, vec!["point.h", "point.md"]);
+
+    // Compiled in the temporary directory, the header is not found
+    let book_toml = fixture.book_path().join("book.toml");
+    let config = std::fs::read_to_string(&book_toml)?.replace("compile_in_place = true", "");
+    std::fs::write(&book_toml, config)?;
+    let test = PreprocessorTest::from_fixture(&fixture)?;
+    assert!(test.run().await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_precompiled_preamble() -> Result<()> {
+    let config: CheckCodeConfig = toml::from_str(
+        r##"
+        [languages.c]
+        enabled = true
+        compiler = "gcc"
+        flags = ["-fsyntax-only"]
+        preamble = "#include <stdio.h>"
+        precompile_preamble = true
+        "##,
+    )?;
+    let content = "```c\nint main(void) { printf(\"hi\"); return 0; }\n```\n\n```c\nint main(void) { return undeclared; }\n```\n";
+
+    let results = CheckCodePreprocessor::new()
+        .check_markdown_async(content, &config)
+        .await?;
+
+    assert_eq!(results.len(), 2);
+    assert!(results[0].success());
+    assert!(!results[1].success());
+    Ok(())
+}
+
+/// Compares compiling blocks with a heavy preamble prepended to each block
+/// against including it precompiled. Run with
+/// `cargo test --features integration-tests --test integration -- --ignored --nocapture`.
+#[tokio::test]
+#[ignore = "benchmark"]
+async fn benchmark_precompiled_preamble() -> Result<()> {
+    let preamble: String = (0..20_000)
+        .map(|i| {
+            format!(
+                "static inline int f{0}(int x) {{ return x * {0} + 1; }}\n",
+                i
+            )
+        })
+        .collect();
+    let content: String = (0..50)
+        .map(|i| format!("```c\nint g{0}(void) {{ return f{0}({0}); }}\n```\n\n", i))
+        .collect();
+
+    for precompile in [false, true] {
+        let mut config: CheckCodeConfig = toml::from_str(
+            r#"
+            parallel_jobs = 1
+
+            [languages.c]
+            enabled = true
+            compiler = "gcc"
+            flags = ["-fsyntax-only"]
+            "#,
+        )?;
+        let c = config.languages.get_mut("c").unwrap();
+        c.preamble = Some(preamble.clone());
+        c.precompile_preamble = precompile;
+
+        let start = std::time::Instant::now();
+        let results = CheckCodePreprocessor::new()
+            .check_markdown_async(&content, &config)
+            .await?;
+        assert!(results.iter().all(|r| r.success()));
+        println!(
+            "precompile_preamble = {}: {} blocks in {:?}",
+            precompile,
+            results.len(),
+            start.elapsed()
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_makefile_blocks_named_makefile() -> Result<()> {
+    // `make -n` run in the block's directory only finds a file named Makefile
+    let config: CheckCodeConfig = toml::from_str(
+        r#"
+        [languages.makefile]
+        enabled = true
+        compiler = "sh"
+        flags = ["-c", "cd \"$(dirname \"$0\")\" && make -n"]
+        "#,
+    )?;
+    let content = "```makefile\nall:\n\techo ok\n```\n\n```makefile\nall\n\techo broken\n```\n";
+
+    let results = CheckCodePreprocessor::new()
+        .check_markdown_async(content, &config)
+        .await?;
+
+    assert_eq!(results.len(), 2);
+    assert!(results[0].success(), "{:?}", results[0].error_message());
+    assert!(!results[1].success());
+    Ok(())
+}
+
+#[tokio::test]
+async fn integration_check_markdown_without_book() -> Result<()> {
+    let config: CheckCodeConfig = toml::from_str(
+        r#"
+        [languages.c]
+        enabled = true
+        compiler = "gcc"
+        flags = ["-fsyntax-only"]
+        "#,
+    )?;
+    let content = "# Notes\n\n```c\nint ok(void) { return 0; }\n```\n\n```c\nint broken(void) { return }\n```\n\n```text\nnot code\n```\n";
+
+    let results = CheckCodePreprocessor::new()
+        .check_markdown_async(content, &config)
+        .await?;
+
+    assert_eq!(results.len(), 2);
+    assert!(results[0].success());
+    assert!(!results[1].success());
+    assert_eq!(results[1].block_index(), 1);
+    assert_eq!(results[1].chapter_path(), Path::new(CHECK_MARKDOWN_PATH));
+    assert!(results[1].error_message().is_some());
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn integration_book_structure_unchanged() -> Result<()> {
     let fixture = TestFixture::new("tests/fixtures/valid_cases")?;